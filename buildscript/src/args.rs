@@ -1,11 +1,19 @@
 //! Command line argument parsing.
 //!
 //! This module handles parsing of command line arguments for the buildscript,
-//! including build targets, environment settings, and various options.
+//! including subcommands, build targets, environment settings, and various options.
 
 use std::{collections::HashMap, path::PathBuf, process::exit, str::FromStr};
 
-use crate::targets::TARGET_NAMES;
+use serde::Deserialize;
+
+use crate::{
+    clean,
+    config::Config,
+    git::Remotes,
+    targets::{TARGET_NAMES, Target},
+    tools::ToolConfig,
+};
 
 /// Command line parameters for build mode.
 #[derive(Default)]
@@ -14,6 +22,8 @@ pub struct BuildArgs {
     pub native_image: bool,
     /// List of build targets to compile.
     pub targets: Vec<String>,
+    /// Whether to run targets after building them.
+    pub run: bool,
 
     /// Git backend to use for cloning repositories (SSH or HTTPS).
     pub git_backend: GitBackend,
@@ -29,9 +39,117 @@ pub struct BuildArgs {
 
     /// Enable Java stacktrace output.
     pub java_stackstrace: bool,
+    /// Maximum number of targets built at the same time.
+    pub jobs: usize,
+    /// Build targets even if they are up to date.
+    pub force: bool,
+    /// Use prebuilt artifacts for targets that are only dependencies.
+    pub prebuilt: bool,
+    /// Local directory to look for prebuilt artifacts in.
+    pub prebuilt_mirror: Option<PathBuf>,
 
     pub templates: HashMap<String, PathBuf>,
     pub keep_states: Vec<PathBuf>,
+    /// Git refs (branch, tag or commit) to check out, by target name or checkout directory.
+    pub refs: HashMap<String, String>,
+    /// Remote URLs by target name or checkout directory, used instead of GitHub.
+    pub remotes: HashMap<String, String>,
+    /// Directory with bare mirrors of repositories to clone from.
+    pub git_mirror: Option<PathBuf>,
+    /// Never use the network, failing on anything that is not cached.
+    pub offline: bool,
+    /// Record hashes of downloads that are not in `workspace.lock` yet.
+    pub update_lock: bool,
+    /// Replacements of URL prefixes for downloads, e.g. `https://github.com/` to a local
+    /// directory or an internal cache.
+    pub download_mirrors: HashMap<String, String>,
+    /// HTTP proxy used for downloads instead of the one from the environment.
+    pub proxy: Option<String>,
+    /// Versions and download URLs of tools by target name.
+    pub tools: HashMap<String, ToolConfig>,
+}
+
+/// Command line parameters for clean mode.
+#[derive(Default)]
+pub struct CleanArgs {
+    /// Tools to remove from `.cache/tools`.
+    ///
+    /// `Some` with an empty list removes every tool.
+    pub tools: Option<Vec<String>>,
+    /// Remove `.cache/rust`.
+    pub rust: bool,
+    /// Remove `.cache/maven`.
+    pub maven: bool,
+    /// Remove `.run` except for kept paths.
+    pub run: bool,
+    /// Remove `.bin`.
+    pub bin: bool,
+    /// Only list what would be removed.
+    pub dry_run: bool,
+    /// Targets whose outputs to remove.
+    pub targets: Vec<String>,
+    /// Paths in `.run` to keep intact.
+    pub keep_states: Vec<PathBuf>,
+}
+impl CleanArgs {
+    /// Whether nothing was selected for removal.
+    pub fn is_empty(&self) -> bool {
+        self.tools.is_none()
+            && !self.rust
+            && !self.maven
+            && !self.run
+            && !self.bin
+            && self.targets.is_empty()
+    }
+}
+
+/// Command line parameters for lock mode.
+#[derive(Default)]
+pub struct LockArgs {
+    /// Targets whose checkouts to lock (all if empty).
+    pub targets: Vec<String>,
+    /// Where repositories are cloned from.
+    pub remotes: Remotes,
+}
+
+/// Command line parameters for sync mode.
+#[derive(Default)]
+pub struct SyncArgs {
+    /// Targets whose checkouts to sync (every locked checkout if empty).
+    pub targets: Vec<String>,
+    /// Check out the commits recorded in `workspace.lock`.
+    pub locked: bool,
+    /// Where repositories are cloned from.
+    pub remotes: Remotes,
+    /// Never use the network.
+    pub offline: bool,
+}
+
+/// Git command run by `./b git`.
+pub enum GitCommand {
+    /// Show branch, upstream state and changed files.
+    Status,
+    /// Fast-forward to the upstream branch.
+    Pull,
+    /// Switch to a branch.
+    Switch {
+        branch: String,
+        /// Create the branch where it does not exist.
+        create: bool,
+    },
+}
+
+/// Command line parameters for git mode.
+pub struct GitArgs {
+    pub command: GitCommand,
+    /// Targets whose checkouts to operate on (all if empty).
+    pub targets: Vec<String>,
+}
+
+/// Command line parameters for patches mode.
+pub struct PatchesArgs {
+    /// Targets or checkout directories whose patches to refresh.
+    pub names: Vec<String>,
 }
 
 #[derive(Default, PartialEq, Eq, Clone, Copy)]
@@ -46,7 +164,8 @@ pub enum EnvTy {
     Host,
 }
 
-#[derive(Default, PartialEq, Eq, Clone, Copy)]
+#[derive(Default, PartialEq, Eq, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
 /// Git backend protocol for cloning repositories.
 pub enum GitBackend {
     /// SSH protocol (git@github.com:).
@@ -73,194 +192,1025 @@ impl GitBackend {
 
 /// Parsed command line arguments.
 pub enum Args {
-    /// Build command with build parameters and environment type.
-    Build { build: BuildArgs, env: EnvTy },
+    /// Build (and optionally run) targets.
+    Build { build: Box<BuildArgs>, env: EnvTy },
+    /// Remove build outputs.
+    Clean { clean: CleanArgs, env: EnvTy },
+    /// Show the state of every target in the workspace.
+    Status { remotes: Remotes, env: EnvTy },
     /// Run command in environment.
-    #[allow(dead_code)]
     Env { command: Vec<String>, env: EnvTy },
+    /// Check which tools are available on the host.
+    Doctor { env: EnvTy },
+    /// Print the target dependency graph.
+    Graph { targets: Vec<String>, dot: bool },
+    /// Record commits of checkouts in `workspace.lock`.
+    Lock { lock: LockArgs },
+    /// Clone missing checkouts and check out locked commits.
+    Sync { sync: SyncArgs, env: EnvTy },
+    /// Run a git command in checkouts of several targets.
+    Git { git: GitArgs },
+    /// Regenerate patch files from checkouts.
+    Patches { patches: PatchesArgs },
     /// Show help message.
-    Help,
+    ///
+    /// Holds the subcommand to show help for, if any.
+    Help(Option<&'static str>),
 }
 impl Args {
     /// Returns the environment type for this command.
-    #[allow(dead_code)]
     pub fn env_ty(&self) -> EnvTy {
         match self {
-            Self::Help => EnvTy::Host,
-            Self::Env { env, .. } => *env,
-            Self::Build { env, .. } => *env,
+            Self::Help(_)
+            | Self::Graph { .. }
+            | Self::Lock { .. }
+            | Self::Git { .. }
+            | Self::Patches { .. } => EnvTy::Host,
+            Self::Build { env, .. }
+            | Self::Clean { env, .. }
+            | Self::Sync { env, .. }
+            | Self::Status { env, .. }
+            | Self::Env { env, .. }
+            | Self::Doctor { env } => *env,
         }
     }
 }
 
-#[derive(Default, PartialEq, Eq, Clone, Copy)]
-enum TaskTy {
-    #[default]
-    Build,
-    Env,
-    Help,
+/// Option accepted by a subcommand.
+struct Opt {
+    /// Option name without the leading `--`.
+    name: &'static str,
+    /// Value placeholder, `None` for flags.
+    value: Option<&'static str>,
+    /// Value may only be passed as `--name=VALUE` and can be omitted.
+    optional: bool,
+    /// Single letter alias, passed as `-x`.
+    short: Option<char>,
+    /// Help text.
+    help: &'static str,
+}
+impl Opt {
+    const fn flag(name: &'static str, help: &'static str) -> Self {
+        Self {
+            name,
+            value: None,
+            optional: false,
+            short: None,
+            help,
+        }
+    }
+
+    const fn value(name: &'static str, value: &'static str, help: &'static str) -> Self {
+        Self {
+            name,
+            value: Some(value),
+            optional: false,
+            short: None,
+            help,
+        }
+    }
+
+    const fn optional(name: &'static str, value: &'static str, help: &'static str) -> Self {
+        Self {
+            name,
+            value: Some(value),
+            optional: true,
+            short: None,
+            help,
+        }
+    }
+
+    const fn short(mut self, short: char) -> Self {
+        self.short = Some(short);
+        self
+    }
+}
+
+/// Subcommand description.
+struct Cmd {
+    /// Subcommand name.
+    name: &'static str,
+    /// Positional arguments shown in usage.
+    args: &'static str,
+    /// One line description.
+    about: &'static str,
+    /// Accepted options.
+    opts: &'static [Opt],
+}
+
+/// Options shared by `build` and `run`.
+const BUILD_OPTS: &[Opt] = &[
+    Opt::flag("ssh", "use ssh instead of https when pulling repos"),
+    Opt::flag("https", "use https when pulling repos (default)"),
+    Opt::flag(
+        "native",
+        "build as a native image (may improve server performance)",
+    ),
+    Opt::flag("stacktrace", "pass '--stacktrace' to gradle"),
+    Opt::flag("force", "build targets even if they are up to date"),
+    Opt::flag(
+        "no-prebuilt",
+        "build dependencies from source instead of downloading them",
+    ),
+    Opt::flag(
+        "offline",
+        "never use the network, fail on anything that is not cached",
+    ),
+    Opt::flag(
+        "update-lock",
+        "record hashes of new downloads in workspace.lock",
+    ),
+    Opt::value(
+        "prebuilt-mirror",
+        "DIR",
+        "look for prebuilt dependencies in DIR first",
+    ),
+    Opt::value(
+        "git-mirror",
+        "DIR",
+        "clone repositories from bare mirrors in DIR if present",
+    ),
+    Opt::value("proxy", "URL", "download through HTTP proxy at URL"),
+    Opt::value("server-ip", "IP", "set ip used for key authorization"),
+    Opt::value(
+        "rabbitmq",
+        "URL",
+        "set rabbitmq url, disables installing and running RabbitMQ",
+    ),
+    Opt::value(
+        "surrealdb",
+        "URL",
+        "set surrealdb url, disables installing and running SurrealDB",
+    ),
+    Opt::value("ports-start", "PORT", "first port assigned to services"),
+    Opt::value(
+        "jobs",
+        "N",
+        "build at most N targets at the same time (default: number of cpus)",
+    ),
+    Opt::value("keep", "PATH", "keep path intact (relative to `.run`)"),
+    Opt::value(
+        "template",
+        "NAME=PATH",
+        "render config of target NAME from template at PATH",
+    ),
+    Opt::value(
+        "ref",
+        "NAME=REF",
+        "check out branch, tag or commit REF in checkout of target NAME",
+    ),
+];
+
+const COMMANDS: &[Cmd] = &[
+    Cmd {
+        name: "build",
+        args: "[TARGETS..]",
+        about: "build targets (default command)",
+        opts: BUILD_OPTS,
+    },
+    Cmd {
+        name: "run",
+        args: "[TARGETS..]",
+        about: "build and run targets",
+        opts: BUILD_OPTS,
+    },
+    Cmd {
+        name: "clean",
+        args: "[TARGETS..]",
+        about: "remove caches, run state and build outputs",
+        opts: &[
+            Opt::optional(
+                "tools",
+                "TOOL,..",
+                "remove locally installed tools (all except rust if none given)",
+            ),
+            Opt::flag("rust", "remove rust build cache (.cache/rust)"),
+            Opt::flag("maven", "remove local maven repository (.cache/maven)"),
+            Opt::flag("run", "remove run state (.run) except for kept paths"),
+            Opt::flag("bin", "remove build outputs (.bin)"),
+            Opt::value("keep", "PATH", "keep path intact (relative to `.run`)"),
+            Opt::flag("dry-run", "only list what would be removed"),
+        ],
+    },
+    Cmd {
+        name: "status",
+        args: "",
+        about: "show the state of every target",
+        opts: &[],
+    },
+    Cmd {
+        name: "lock",
+        args: "[TARGETS..]",
+        about: "record commits of checkouts in workspace.lock",
+        opts: &[],
+    },
+    Cmd {
+        name: "sync",
+        args: "[TARGETS..]",
+        about: "clone missing checkouts (of locked ones if no target is given)",
+        opts: &[
+            Opt::flag("locked", "check out the commits recorded in workspace.lock"),
+            Opt::flag(
+                "offline",
+                "only clone and check out from local mirrors and checkouts",
+            ),
+            Opt::flag("ssh", "use ssh instead of https when pulling repos"),
+            Opt::flag("https", "use https when pulling repos (default)"),
+            Opt::value(
+                "git-mirror",
+                "DIR",
+                "clone repositories from bare mirrors in DIR if present",
+            ),
+        ],
+    },
+    Cmd {
+        name: "git",
+        args: "status|pull|switch [--create] BRANCH [TARGETS..]",
+        about: "show status, pull or switch branches in checkouts of targets",
+        opts: &[Opt::flag(
+            "create",
+            "create the branch to switch to where it does not exist",
+        )
+        .short('c')],
+    },
+    Cmd {
+        name: "patches",
+        args: "refresh NAMES..",
+        about: "write uncommitted changes of checkouts into their patches",
+        opts: &[],
+    },
+    Cmd {
+        name: "env",
+        args: "[CMD..]",
+        about: "run command within local environment",
+        opts: &[],
+    },
+    Cmd {
+        name: "doctor",
+        args: "",
+        about: "check which tools are available on the host",
+        opts: &[],
+    },
+    Cmd {
+        name: "graph",
+        args: "[TARGETS..]",
+        about: "print the target dependency graph",
+        opts: &[Opt::flag("dot", "print the graph in graphviz format")],
+    },
+    Cmd {
+        name: "help",
+        args: "[COMMAND]",
+        about: "print help for a command",
+        opts: &[],
+    },
+];
+
+/// Special targets accepted by `build`.
+const SPECIAL_TARGETS: &[&str] = &["all", "run"];
+
+fn command(name: &str) -> Option<&'static Cmd> {
+    COMMANDS.iter().find(|x| x.name == name)
+}
+
+/// Find the closest match for a misspelled word.
+fn suggest<'a>(word: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    fn distance(a: &str, b: &str) -> usize {
+        let b: Vec<char> = b.chars().collect();
+        let mut row: Vec<usize> = (0..=b.len()).collect();
+        for (i, ca) in a.chars().enumerate() {
+            let mut prev = row[0];
+            row[0] = i + 1;
+            for (j, cb) in b.iter().enumerate() {
+                let cur = row[j + 1];
+                row[j + 1] = if ca == *cb {
+                    prev
+                } else {
+                    1 + prev.min(row[j]).min(row[j + 1])
+                };
+                prev = cur;
+            }
+        }
+        row[b.len()]
+    }
+
+    candidates
+        .into_iter()
+        .map(|x| (distance(word, x), x))
+        .filter(|(d, x)| *d <= x.len().div_ceil(3))
+        .min_by_key(|(d, _)| *d)
+        .map(|(_, x)| x)
+}
+
+/// Format a "did you mean" hint.
+fn hint(suggestion: Option<&str>) -> String {
+    match suggestion {
+        Some(x) => format!(" (did you mean '{x}'?)"),
+        None => String::new(),
+    }
 }
 
 /// Prints the help message with usage information.
-pub fn print_help() {
+///
+/// Prints help for a single subcommand if `command` is set.
+pub fn print_help(command: Option<&str>) {
+    if let Some(cmd) = command.and_then(self::command) {
+        eprintln!("Usage: ./b [WPARAMS] {} [PARAMS] {}", cmd.name, cmd.args);
+        eprintln!();
+        eprintln!("{}", cmd.about);
+        eprintln!();
+        eprintln!("Params:");
+        for x in cmd.opts {
+            let mut name = match x.value {
+                Some(value) if x.optional => format!("--{}[={value}]", x.name),
+                Some(value) => format!("--{} {value}", x.name),
+                None => format!("--{}", x.name),
+            };
+            if let Some(short) = x.short {
+                name = format!("-{short}, {name}");
+            }
+            eprintln!("\t{name:<22} - {}", x.help);
+        }
+        eprintln!("\t{:<22} - print this message", "--help");
+
+        if matches!(cmd.name, "build" | "run" | "graph") {
+            eprintln!();
+            eprintln!("Available targets:");
+            for x in TARGET_NAMES {
+                eprintln!("\t{x}");
+            }
+            eprintln!("note: targets are always compiled after their dependencies.");
+            eprintln!();
+            eprintln!("Special targets:");
+            eprintln!("\tall                - enable all targets (except for deprecated ones)");
+            if cmd.name == "build" {
+                eprintln!("\trun                - run all targets");
+            }
+        }
+        return;
+    }
+
     eprintln!("buildscript");
     eprintln!();
-    eprintln!("Usage: [WPARAMS] [PARAMS] [TARGETS..]");
+    eprintln!("Usage: ./b [WPARAMS] [COMMAND] [PARAMS] [ARGS..]");
     eprintln!();
     eprintln!("Wrapper params:");
     eprintln!("\t--recompile-build-script");
     eprintln!("\t--isolate          - force install local tools");
     eprintln!("\t--autoinstall      - install missing tools locally automatically");
     eprintln!();
-    eprintln!("Params:");
-    eprintln!("\t--env [CMD..]      - run command within local environment");
-    eprintln!("\t--help             - print this message");
-    eprintln!("\t--ssh              - use ssh instead of https when pulling repos");
-    eprintln!();
-    eprintln!("Extra params for build:");
-    eprintln!("\t--native           - build as a native image (may improve server performance)");
-    eprintln!("\t--run              - run targets");
-    eprintln!("\t--stacktrace       - pass '--stacktrace' to gradle");
-    eprintln!("\t--server-ip [IP]   - set ip used for key authorization");
-    eprintln!("\t--rabbbitmq [URL]  - set rabbitmq url");
-    eprintln!("\t                     Also disables installing and running RabbitMQ");
-    eprintln!("\t--surrealdb [URL]  - set surrealdb url");
-    eprintln!("\t                     Also disables installing and running SurrealDB");
-    eprintln!("\t--keep      [PATH] - keep path intact (relative to `.run`)");
-    eprintln!();
-    eprintln!("Available targets:");
-    for x in TARGET_NAMES {
-        eprintln!("\t{x}");
+    eprintln!("Commands:");
+    for x in COMMANDS {
+        eprintln!("\t{:<18} - {}", x.name, x.about);
     }
-    eprintln!("note: targets are always compiled in this exact order.");
     eprintln!();
-    eprintln!("Special targets:");
-    eprintln!("\tall                - enable all targets (except for deprecated ones)");
-    eprintln!("\trun                - run all targets");
+    eprintln!("If no command is given, arguments are passed to `build`.");
+    eprintln!("Use `./b COMMAND --help` to list params of a command.");
     eprintln!();
-    eprintln!(
-        "By default, calling build script with or without args creates editor configurations."
-    );
+    eprintln!("Defaults for build params are read from `workspace.toml`.");
+    eprintln!("Precedence: workspace.toml < WORKSPACE_* environment variables < params.");
 }
 
-pub fn args() -> Args {
-    let mut env = EnvTy::Host;
-    let mut task_ty = TaskTy::Build;
-    let mut template: HashMap<String, PathBuf> = HashMap::new();
-    let mut argv = std::env::args().peekable();
-    argv.next();
+/// Argument reader for a single subcommand.
+struct Parser<I: Iterator<Item = String>> {
+    cmd: &'static Cmd,
+    argv: std::iter::Peekable<I>,
+    errors: Vec<String>,
+    help: bool,
+}
 
-    // Core args
-    while let Some(x) = argv.peek() {
-        match x.as_str() {
-            "--isolate" => env = EnvTy::Isolate,
-            "--autoinstall" => {
-                if env != EnvTy::Isolate {
-                    env = EnvTy::Autoinstall
+/// Single parsed argument.
+enum Arg {
+    /// `--name [VALUE]`.
+    Opt(&'static str, Option<String>),
+    /// Positional argument.
+    Positional(String),
+}
+
+impl<I: Iterator<Item = String>> Parser<I> {
+    fn new(cmd: &'static Cmd, argv: std::iter::Peekable<I>) -> Self {
+        Self {
+            cmd,
+            argv,
+            errors: vec![],
+            help: false,
+        }
+    }
+
+    fn error(&mut self, error: impl Into<String>) {
+        self.errors.push(error.into());
+    }
+
+    fn next(&mut self) -> Option<Arg> {
+        loop {
+            let x = self.argv.next()?;
+
+            if x == "-h" || x == "--help" {
+                self.help = true;
+                continue;
+            }
+
+            let Some(opt) = x.strip_prefix("--") else {
+                if x.starts_with('-') && x.len() > 1 {
+                    let short = self.cmd.opts.iter().find(|opt| {
+                        opt.value.is_none() && opt.short.is_some_and(|c| x[1..] == *c.to_string())
+                    });
+                    match short {
+                        Some(spec) => return Some(Arg::Opt(spec.name, None)),
+                        None => self.error(format!("unknown option '{x}'")),
+                    }
+                    continue;
                 }
+                return Some(Arg::Positional(x));
+            };
+
+            let (name, inline) = match opt.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None => (opt, None),
+            };
+
+            let Some(spec) = self.cmd.opts.iter().find(|x| x.name == name) else {
+                let suggestion = suggest(name, self.cmd.opts.iter().map(|x| x.name));
+                self.error(format!(
+                    "unknown option '--{name}'{}",
+                    hint(suggestion.map(|x| format!("--{x}")).as_deref())
+                ));
+                continue;
+            };
+
+            let value = match (spec.value, inline) {
+                (None, None) => None,
+                (None, Some(_)) => {
+                    self.error(format!("option '--{name}' does not take a value"));
+                    continue;
+                }
+                (Some(_), Some(value)) => Some(value),
+                (Some(_), None) if spec.optional => None,
+                (Some(placeholder), None) => match self.argv.next_if(|x| !x.starts_with("--")) {
+                    Some(value) => Some(value),
+                    None => {
+                        self.error(format!(
+                            "option '--{name}' requires a value (--{name} {placeholder})"
+                        ));
+                        continue;
+                    }
+                },
+            };
+
+            return Some(Arg::Opt(spec.name, value));
+        }
+    }
+
+    /// Parse an option value into a typed value.
+    fn parse<T: FromStr>(&mut self, name: &str, value: &str, expected: &str) -> Option<T> {
+        match value.parse() {
+            Ok(x) => Some(x),
+            Err(_) => {
+                self.error(format!(
+                    "invalid value {value:?} for '--{name}': expected {expected}"
+                ));
+                None
+            }
+        }
+    }
+
+    /// Print collected errors and exit if there are any.
+    fn finish(self) {
+        if self.help {
+            print_help(Some(self.cmd.name));
+            exit(0);
+        }
+
+        if !self.errors.is_empty() {
+            for x in &self.errors {
+                eprintln!("error: {}: {x}", self.cmd.name);
             }
-            "--env" => {
-                if task_ty != TaskTy::Help {
-                    task_ty = TaskTy::Env
+            eprintln!();
+            eprintln!("Run './b {} --help' for usage.", self.cmd.name);
+            exit(1);
+        }
+    }
+}
+
+/// Parse a `NAME=PATH` template spec.
+fn parse_template(spec: &str) -> Result<(String, PathBuf), String> {
+    match spec.split_once('=') {
+        Some((name, path)) if !name.is_empty() && !path.is_empty() => {
+            Ok((name.to_string(), path.into()))
+        }
+        _ => Err(format!("invalid template {spec:?}: expected NAME=PATH")),
+    }
+}
+
+/// Parse a `NAME=REF` git ref spec.
+fn parse_ref(spec: &str) -> Result<(String, String), String> {
+    match spec.split_once('=') {
+        Some((name, git_ref)) if !name.is_empty() && !git_ref.is_empty() => {
+            if !is_checkout_name(name) {
+                let suggestion = suggest(name, checkout_names());
+                return Err(format!(
+                    "invalid ref {spec:?}: unknown target {name:?}{}",
+                    hint(suggestion)
+                ));
+            }
+            Ok((name.to_string(), git_ref.to_string()))
+        }
+        _ => Err(format!("invalid ref {spec:?}: expected NAME=REF")),
+    }
+}
+
+/// Names accepted by `--ref`: targets with a checkout and checkout directories.
+fn checkout_names() -> impl Iterator<Item = &'static str> {
+    Target::ALL
+        .iter()
+        .filter(|x| !x.repos().is_empty())
+        .map(|x| x.name())
+        .chain(Target::ALL.iter().flat_map(|x| x.repos()).map(|x| x.dir))
+}
+
+/// Whether `name` is accepted by `--ref`.
+pub fn is_checkout_name(name: &str) -> bool {
+    checkout_names().any(|x| x == name)
+}
+
+/// Parse arguments of `build` and `run`.
+///
+/// Command line params are applied on top of `config`.
+fn build_args<I: Iterator<Item = String>>(
+    mut parser: Parser<I>,
+    config: &Config,
+    templates: HashMap<String, PathBuf>,
+) -> BuildArgs {
+    let mut build = match config.build_args() {
+        Ok(x) => x,
+        Err(why) => {
+            eprintln!("error: {why}");
+            exit(1);
+        }
+    };
+    build.templates.extend(templates);
+    build.run = parser.cmd.name == "run";
+
+    while let Some(arg) = parser.next() {
+        match arg {
+            Arg::Opt("ssh", _) => build.git_backend = GitBackend::Ssh,
+            Arg::Opt("https", _) => build.git_backend = GitBackend::Https,
+            Arg::Opt("native", _) => build.native_image = true,
+            Arg::Opt("stacktrace", _) => build.java_stackstrace = true,
+            Arg::Opt("force", _) => build.force = true,
+            Arg::Opt("no-prebuilt", _) => build.prebuilt = false,
+            Arg::Opt("offline", _) => build.offline = true,
+            Arg::Opt("update-lock", _) => build.update_lock = true,
+            Arg::Opt("prebuilt-mirror", Some(x)) => build.prebuilt_mirror = Some(x.into()),
+            Arg::Opt("git-mirror", Some(x)) => build.git_mirror = Some(x.into()),
+            Arg::Opt("proxy", Some(x)) => build.proxy = Some(x),
+            Arg::Opt("server-ip", Some(x)) => build.server_ip = x,
+            Arg::Opt("rabbitmq", Some(x)) => build.rabbitmq_url = x,
+            Arg::Opt("surrealdb", Some(x)) => build.surrealdb_url = x,
+            Arg::Opt("keep", Some(x)) => build.keep_states.push(x.into()),
+            Arg::Opt("ports-start", Some(x)) => {
+                if let Some(x) = parser.parse("ports-start", &x, "a port number") {
+                    build.ports_start = x;
+                }
+            }
+            Arg::Opt("jobs", Some(x)) => {
+                match parser.parse::<usize>("jobs", &x, "a positive number") {
+                    Some(0) => {
+                        parser.error("invalid value \"0\" for '--jobs': expected a positive number")
+                    }
+                    Some(x) => build.jobs = x,
+                    None => (),
+                }
+            }
+            Arg::Opt("template", Some(x)) => match parse_template(&x) {
+                Ok((name, path)) => _ = build.templates.insert(name, path),
+                Err(why) => parser.error(why),
+            },
+            Arg::Opt("ref", Some(x)) => match parse_ref(&x) {
+                Ok((name, git_ref)) => _ = build.refs.insert(name, git_ref),
+                Err(why) => parser.error(why),
+            },
+            Arg::Opt(name, _) => unreachable!("unhandled option --{name}"),
+            Arg::Positional(x) => {
+                let special = match parser.cmd.name {
+                    "build" => SPECIAL_TARGETS,
+                    _ => &SPECIAL_TARGETS[..1],
+                };
+                if TARGET_NAMES.contains(&x.as_str()) || special.contains(&x.as_str()) {
+                    build.targets.push(x);
+                } else {
+                    let suggestion = suggest(&x, TARGET_NAMES.iter().chain(special).copied());
+                    parser.error(format!("unknown target {x:?}{}", hint(suggestion)));
                 }
             }
-            str if str.starts_with("--template=") => template.extend(
-                str.strip_prefix("--template=")
-                    .unwrap()
-                    .split(":")
-                    .map(|it| it.split("="))
-                    .map(|mut it| {
-                        (
-                            it.next().unwrap().into(),
-                            it.next().unwrap().parse().unwrap(),
-                        )
-                    })
-                    .collect::<Vec<_>>(),
-            ),
-            _ => break,
         }
-        argv.next();
     }
 
-    while let Some(x) = argv.peek() {
-        match x.as_str() {
-            "--isolate" | "--autoinstall" | "--env" => {
-                exit(1);
+    if let Some(x) = &build.proxy
+        && let Err(why) = ureq::Proxy::new(x)
+    {
+        parser.error(format!("invalid proxy {x:?}: {why}"));
+    }
+
+    parser.finish();
+
+    if build.targets.is_empty() {
+        build.targets = config.targets.clone();
+    }
+
+    build
+}
+
+/// Parse arguments of `clean`.
+fn clean_args<I: Iterator<Item = String>>(mut parser: Parser<I>, config: &Config) -> CleanArgs {
+    let mut clean = CleanArgs {
+        keep_states: config.keep.clone(),
+        ..Default::default()
+    };
+
+    while let Some(arg) = parser.next() {
+        match arg {
+            Arg::Opt("tools", x) => {
+                let tools = clean.tools.get_or_insert_default();
+                for x in x
+                    .iter()
+                    .flat_map(|x| x.split(','))
+                    .filter(|x| !x.is_empty())
+                {
+                    if clean::tool_names().any(|name| name == x) {
+                        tools.push(x.to_string());
+                    } else {
+                        let suggestion = suggest(x, clean::tool_names());
+                        parser.error(format!("unknown tool {x:?}{}", hint(suggestion)));
+                    }
+                }
             }
-            str if str.starts_with("--template") => {
-                exit(1);
+            Arg::Opt("rust", _) => clean.rust = true,
+            Arg::Opt("maven", _) => clean.maven = true,
+            Arg::Opt("run", _) => clean.run = true,
+            Arg::Opt("bin", _) => clean.bin = true,
+            Arg::Opt("dry-run", _) => clean.dry_run = true,
+            Arg::Opt("keep", Some(x)) => clean.keep_states.push(x.into()),
+            Arg::Opt(name, _) => unreachable!("unhandled option --{name}"),
+            Arg::Positional(x) => {
+                if x == "all" || TARGET_NAMES.contains(&x.as_str()) {
+                    clean.targets.push(x);
+                } else {
+                    let suggestion = suggest(&x, TARGET_NAMES.iter().copied());
+                    parser.error(format!("unknown target {x:?}{}", hint(suggestion)));
+                }
             }
-            "--help" => {
-                task_ty = TaskTy::Help;
+        }
+    }
+
+    if !parser.help && clean.is_empty() {
+        parser.error("nothing to clean, pass a scope or a target");
+    }
+
+    parser.finish();
+    clean
+}
+
+/// Parse arguments of `graph`.
+fn graph_args<I: Iterator<Item = String>>(mut parser: Parser<I>) -> Args {
+    let mut targets = vec![];
+    let mut dot = false;
+
+    while let Some(arg) = parser.next() {
+        match arg {
+            Arg::Opt("dot", _) => dot = true,
+            Arg::Opt(name, _) => unreachable!("unhandled option --{name}"),
+            Arg::Positional(x) => {
+                if x == "all" || TARGET_NAMES.contains(&x.as_str()) {
+                    targets.push(x);
+                } else {
+                    let suggestion = suggest(&x, TARGET_NAMES.iter().copied());
+                    parser.error(format!("unknown target {x:?}{}", hint(suggestion)));
+                }
             }
-            _ => break,
         }
-        argv.next();
     }
 
-    match task_ty {
-        TaskTy::Help => Args::Help,
-        TaskTy::Build => {
-            let mut build = BuildArgs::default();
-            build.templates = template;
-            let mut errors = vec![];
+    parser.finish();
+    Args::Graph { targets, dot }
+}
+
+/// Parse a target name given to `lock` or `sync`.
+fn checkout_target<I: Iterator<Item = String>>(
+    parser: &mut Parser<I>,
+    targets: &mut Vec<String>,
+    x: String,
+) {
+    if x == "all" || TARGET_NAMES.contains(&x.as_str()) {
+        targets.push(x);
+    } else {
+        let suggestion = suggest(&x, TARGET_NAMES.iter().copied());
+        parser.error(format!("unknown target {x:?}{}", hint(suggestion)));
+    }
+}
+
+/// Where repositories are cloned from according to the config, exiting on error.
+fn remotes(config: &Config) -> Remotes {
+    match config.build_args() {
+        Ok(x) => Remotes::new(&x),
+        Err(why) => {
+            eprintln!("error: {why}");
+            exit(1);
+        }
+    }
+}
 
-            build.ports_start = 4100;
+/// Parse arguments of `lock`.
+fn lock_args<I: Iterator<Item = String>>(mut parser: Parser<I>, config: &Config) -> LockArgs {
+    let mut lock = LockArgs {
+        remotes: remotes(config),
+        ..Default::default()
+    };
 
-            while let Some(x) = argv.next() {
-                if let Some(x) = x.strip_prefix("--") {
-                    match x {
-                        "ssh" => {
-                            build.git_backend = GitBackend::Ssh;
-                        }
-                        "stacktrace" => {
-                            build.java_stackstrace = true;
-                        }
-                        "native" => {
-                            build.native_image = true;
-                        }
-                        "server-ip" => {
-                            if let Some(x) = argv.next() {
-                                build.server_ip = x;
-                            } else {
-                                errors.push("--server-ip: no value specified".to_string());
+    while let Some(arg) = parser.next() {
+        match arg {
+            Arg::Opt(name, _) => unreachable!("unhandled option --{name}"),
+            Arg::Positional(x) => checkout_target(&mut parser, &mut lock.targets, x),
+        }
+    }
+
+    parser.finish();
+    lock
+}
+
+/// Parse arguments of `sync`.
+fn sync_args<I: Iterator<Item = String>>(mut parser: Parser<I>, config: &Config) -> SyncArgs {
+    let mut sync = SyncArgs {
+        remotes: remotes(config),
+        // Errors in the config are reported by `remotes`.
+        offline: config.build_args().is_ok_and(|x| x.offline),
+        ..Default::default()
+    };
+
+    while let Some(arg) = parser.next() {
+        match arg {
+            Arg::Opt("locked", _) => sync.locked = true,
+            Arg::Opt("offline", _) => sync.offline = true,
+            Arg::Opt("ssh", _) => sync.remotes.backend = GitBackend::Ssh,
+            Arg::Opt("https", _) => sync.remotes.backend = GitBackend::Https,
+            Arg::Opt("git-mirror", Some(x)) => sync.remotes.mirror = Some(x.into()),
+            Arg::Opt(name, _) => unreachable!("unhandled option --{name}"),
+            Arg::Positional(x) => checkout_target(&mut parser, &mut sync.targets, x),
+        }
+    }
+
+    parser.finish();
+    sync
+}
+
+/// Parse arguments of `patches`.
+fn patches_args<I: Iterator<Item = String>>(mut parser: Parser<I>) -> PatchesArgs {
+    let mut command = None;
+    let mut names = vec![];
+
+    while let Some(arg) = parser.next() {
+        match arg {
+            Arg::Opt(name, _) => unreachable!("unhandled option --{name}"),
+            Arg::Positional(x) if command.is_none() => {
+                if x != "refresh" {
+                    let suggestion = suggest(&x, ["refresh"]);
+                    parser.error(format!("unknown patches command {x:?}{}", hint(suggestion)));
+                }
+                command = Some(x);
+            }
+            Arg::Positional(x) if is_checkout_name(&x) => names.push(x),
+            Arg::Positional(x) => {
+                let suggestion = suggest(&x, checkout_names());
+                parser.error(format!("unknown checkout {x:?}{}", hint(suggestion)));
+            }
+        }
+    }
+
+    if !parser.help {
+        if command.is_none() {
+            parser.error("missing patches command (refresh)");
+        } else if names.is_empty() {
+            parser.error("'refresh' requires targets or checkout directories");
+        }
+    }
+
+    parser.finish();
+    PatchesArgs { names }
+}
+
+/// Parse arguments of `git`.
+fn git_args<I: Iterator<Item = String>>(mut parser: Parser<I>) -> GitArgs {
+    let mut command = None;
+    let mut targets = vec![];
+    let mut create = false;
+
+    while let Some(arg) = parser.next() {
+        match arg {
+            Arg::Opt("create", _) => create = true,
+            Arg::Opt(name, _) => unreachable!("unhandled option --{name}"),
+            Arg::Positional(x) if command.is_none() => {
+                command = match x.as_str() {
+                    "status" => Some(GitCommand::Status),
+                    "pull" => Some(GitCommand::Pull),
+                    "switch" => {
+                        let branch = loop {
+                            match parser.next() {
+                                Some(Arg::Opt("create", _)) => create = true,
+                                Some(Arg::Positional(x)) => break Some(x),
+                                _ => break None,
                             }
-                        }
-                        "rabbitmq" => {
-                            if let Some(x) = argv.next() {
-                                build.rabbitmq_url = x;
-                            } else {
-                                errors.push("--rabbitmq: no value specified".to_string());
+                        };
+                        match branch {
+                            Some(branch) => Some(GitCommand::Switch {
+                                branch,
+                                create: false,
+                            }),
+                            None => {
+                                parser.error(
+                                    "'switch' requires a branch (switch [--create] BRANCH TARGETS..)",
+                                );
+                                Some(GitCommand::Status)
                             }
                         }
-                        "keep" => {
-                            if let Some(x) = argv.next() {
-                                build.keep_states.push(x.into());
-                            } else {
-                                errors.push("--rabbitmq: no value specified".to_string());
-                            }
+                    }
+                    _ => {
+                        let suggestion = suggest(&x, ["status", "pull", "switch"]);
+                        parser.error(format!("unknown git command {x:?}{}", hint(suggestion)));
+                        Some(GitCommand::Status)
+                    }
+                };
+            }
+            Arg::Positional(x) => checkout_target(&mut parser, &mut targets, x),
+        }
+    }
+
+    match &mut command {
+        None if !parser.help => parser.error("missing git command (status, pull or switch)"),
+        Some(GitCommand::Switch { .. }) if targets.is_empty() => {
+            parser.error("'switch' requires targets, use 'all' to switch every checkout")
+        }
+        Some(GitCommand::Switch { create: x, .. }) => *x = create,
+        _ if create && parser.errors.is_empty() => {
+            parser.error("'--create' is only used by 'switch'")
+        }
+        _ => (),
+    }
+
+    parser.finish();
+    GitArgs {
+        command: command.unwrap_or(GitCommand::Status),
+        targets,
+    }
+}
+
+/// Parse arguments of a command that takes no arguments.
+fn no_args<I: Iterator<Item = String>>(mut parser: Parser<I>) {
+    while let Some(arg) = parser.next() {
+        match arg {
+            Arg::Opt(name, _) => unreachable!("unhandled option --{name}"),
+            Arg::Positional(x) => parser.error(format!("unexpected argument {x:?}")),
+        }
+    }
+    parser.finish();
+}
+
+/// Load the workspace configuration, exiting on error.
+fn config() -> Config {
+    match Config::load() {
+        Ok(x) => x,
+        Err(why) => {
+            eprintln!("error: {why}");
+            exit(1);
+        }
+    }
+}
+
+pub fn args() -> Args {
+    let mut env = EnvTy::Host;
+    let mut help = false;
+    let mut legacy_env = false;
+    let mut template: HashMap<String, PathBuf> = HashMap::new();
+    let mut argv = std::env::args().peekable();
+    argv.next();
+
+    // Wrapper args
+    while let Some(x) = argv.peek() {
+        match x.as_str() {
+            "--isolate" => env = EnvTy::Isolate,
+            "--autoinstall" => {
+                if env != EnvTy::Isolate {
+                    env = EnvTy::Autoinstall
+                }
+            }
+            "--recompile-build-script" => (),
+            "--env" => legacy_env = true,
+            "--help" | "-h" => help = true,
+            str if str.starts_with("--template=") => {
+                for x in str.strip_prefix("--template=").unwrap().split(':') {
+                    match parse_template(x) {
+                        Ok((name, path)) => _ = template.insert(name, path),
+                        Err(why) => {
+                            eprintln!("error: {why}");
+                            exit(1);
                         }
-                        x => errors.push(format!("unknown option {:?}", format!("--{x}"))),
                     }
-                } else if let Some(x) = x.strip_prefix("-") {
-                    _ = x;
-                } else {
-                    build.targets.push(x.to_string());
                 }
             }
+            _ => break,
+        }
+        argv.next();
+    }
 
-            if !errors.is_empty() {
-                for x in errors {
-                    eprintln!("{x}");
-                }
+    if help {
+        return Args::Help(None);
+    }
+    if legacy_env {
+        return Args::Env {
+            command: argv.collect(),
+            env,
+        };
+    }
+
+    let cmd = match argv.peek().map(String::as_str) {
+        None => return Args::Help(None),
+        Some(x) if x.starts_with('-') => command("build").unwrap(),
+        Some(x) => match command(x) {
+            Some(cmd) => {
+                argv.next();
+                cmd
+            }
+            None if TARGET_NAMES.contains(&x) || SPECIAL_TARGETS.contains(&x) => {
+                command("build").unwrap()
+            }
+            None => {
+                let suggestion = suggest(
+                    x,
+                    COMMANDS
+                        .iter()
+                        .map(|x| x.name)
+                        .chain(TARGET_NAMES.iter().copied()),
+                );
+                eprintln!("error: unknown command or target {x:?}{}", hint(suggestion));
+                eprintln!();
+                eprintln!("Run './b --help' for usage.");
                 exit(1);
             }
+        },
+    };
 
-            Args::Build { build, env }
+    match cmd.name {
+        "build" | "run" => Args::Build {
+            build: Box::new(build_args(Parser::new(cmd, argv), &config(), template)),
+            env,
+        },
+        "clean" => Args::Clean {
+            clean: clean_args(Parser::new(cmd, argv), &config()),
+            env,
+        },
+        "status" => {
+            no_args(Parser::new(cmd, argv));
+            Args::Status {
+                remotes: remotes(&config()),
+                env,
+            }
         }
-        TaskTy::Env => {
-            let command: Vec<String> = argv.collect();
-            Args::Env { command, env }
+        "doctor" => {
+            no_args(Parser::new(cmd, argv));
+            Args::Doctor { env }
         }
+        "graph" => graph_args(Parser::new(cmd, argv)),
+        "lock" => Args::Lock {
+            lock: lock_args(Parser::new(cmd, argv), &config()),
+        },
+        "git" => Args::Git {
+            git: git_args(Parser::new(cmd, argv)),
+        },
+        "patches" => Args::Patches {
+            patches: patches_args(Parser::new(cmd, argv)),
+        },
+        "sync" => Args::Sync {
+            sync: sync_args(Parser::new(cmd, argv), &config()),
+            env,
+        },
+        "env" => {
+            if argv.peek().is_some_and(|x| x == "--help" || x == "-h") {
+                return Args::Help(Some("env"));
+            }
+            Args::Env {
+                command: argv.collect(),
+                env,
+            }
+        }
+        "help" => match argv.next() {
+            None => Args::Help(None),
+            Some(x) => match command(&x) {
+                Some(cmd) => Args::Help(Some(cmd.name)),
+                None => {
+                    let suggestion = suggest(&x, COMMANDS.iter().map(|x| x.name));
+                    eprintln!("error: unknown command {x:?}{}", hint(suggestion));
+                    exit(1);
+                }
+            },
+        },
+        _ => unreachable!(),
     }
 }
@@ -0,0 +1,212 @@
+//! Workspace cleanup.
+//!
+//! This module implements `./b clean`, which removes caches, run state
+//! and build outputs without touching source checkouts.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use crate::{args::CleanArgs, fs2, targets::Target, tools};
+
+/// Tools in `.cache/tools` which are only removed when named explicitly.
+///
+/// `rust` is the toolchain installed by the wrapper and `buildscript` holds
+/// the state of the buildscript itself.
+const PROTECTED_TOOLS: &[&str] = &["rust", "buildscript"];
+
+/// Names accepted by `--tools`.
+pub fn tool_names() -> impl Iterator<Item = &'static str> {
+    tools::TOOLS
+        .iter()
+        .map(|x| x.name)
+        .chain(PROTECTED_TOOLS.iter().copied())
+}
+
+/// Calculate the size of a file or a directory.
+///
+/// Symlinks are not followed.
+pub fn size(path: &Path) -> io::Result<u64> {
+    let meta = fs::symlink_metadata(path)?;
+    if !meta.is_dir() {
+        return Ok(meta.len());
+    }
+
+    let mut total = 0;
+    for x in fs2::read_dir(path)? {
+        total += size(&x?.path())?;
+    }
+    Ok(total)
+}
+
+/// Format a size in human readable units.
+pub fn human_size(size: u64) -> String {
+    const UNITS: &[&str] = &["B", "KiB", "MiB", "GiB", "TiB"];
+
+    let mut size = size as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{size} {}", UNITS[unit])
+    } else {
+        format!("{size:.1} {}", UNITS[unit])
+    }
+}
+
+/// Collect entries of `.run` that are not kept.
+///
+/// * `dir` - Directory to scan
+/// * `rel` - Path of `dir` relative to `.run`
+/// * `keep` - Paths to keep (relative to `.run`)
+fn collect_run(dir: &Path, rel: &Path, keep: &[PathBuf], out: &mut Vec<PathBuf>) -> io::Result<()> {
+    let readdir = match fs2::read_dir(dir) {
+        Ok(x) => x,
+        Err(why) if why.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(why) => return Err(why),
+    };
+
+    for x in readdir {
+        let x = x?;
+        let rel = rel.join(x.file_name());
+
+        if keep.iter().any(|k| rel.starts_with(k)) {
+            continue;
+        }
+
+        if keep.iter().any(|k| k.starts_with(&rel)) && x.file_type()?.is_dir() {
+            collect_run(&x.path(), &rel, keep, out)?;
+        } else {
+            out.push(x.path());
+        }
+    }
+
+    Ok(())
+}
+
+/// Collect paths selected for removal.
+fn collect(args: &CleanArgs) -> io::Result<Vec<PathBuf>> {
+    let mut paths = vec![];
+
+    if let Some(tools) = &args.tools {
+        if tools.is_empty() {
+            match fs2::read_dir(".cache/tools") {
+                Ok(readdir) => {
+                    for x in readdir {
+                        let x = x?;
+                        if PROTECTED_TOOLS.iter().any(|t| x.file_name() == **t) {
+                            continue;
+                        }
+                        paths.push(x.path());
+                    }
+                }
+                Err(why) if why.kind() == io::ErrorKind::NotFound => (),
+                Err(why) => return Err(why),
+            }
+        } else {
+            for x in tools {
+                if !tool_names().any(|name| name == x) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("unknown tool {x:?}"),
+                    ));
+                }
+                paths.push(Path::new(".cache/tools").join(x));
+                paths.extend(tools::leftovers(x)?);
+            }
+        }
+    }
+
+    if args.rust {
+        paths.push(".cache/rust".into());
+    }
+    if args.maven {
+        paths.push(".cache/maven".into());
+    }
+    if args.bin {
+        paths.push(".bin".into());
+        paths.push(".build".into());
+    }
+    if args.run {
+        collect_run(
+            Path::new(".run"),
+            Path::new(""),
+            &args.keep_states,
+            &mut paths,
+        )?;
+    }
+
+    let targets: Vec<Target> = if args.targets.iter().any(|x| x == "all") {
+        Target::ALL.to_vec()
+    } else {
+        args.targets
+            .iter()
+            .map(|x| Target::from_str(x).unwrap())
+            .collect()
+    };
+    for target in targets {
+        for path in target.owned_paths() {
+            let path = Path::new(path);
+            match path.strip_prefix(".run") {
+                Ok(rel) if args.keep_states.iter().any(|k| k.starts_with(rel)) => {
+                    if !args.keep_states.iter().any(|k| rel.starts_with(k)) {
+                        collect_run(path, rel, &args.keep_states, &mut paths)?;
+                    }
+                }
+                _ => paths.push(path.to_path_buf()),
+            }
+        }
+    }
+
+    paths.retain(|x| fs::symlink_metadata(x).is_ok());
+    paths.sort();
+    paths.dedup();
+    let nested: Vec<bool> = paths
+        .iter()
+        .map(|x| paths.iter().any(|y| x != y && x.starts_with(y)))
+        .collect();
+    let mut nested = nested.into_iter();
+    paths.retain(|_| !nested.next().unwrap());
+
+    Ok(paths)
+}
+
+/// Remove caches, run state and build outputs.
+pub fn clean(args: &CleanArgs) -> io::Result<()> {
+    let paths = collect(args)?;
+
+    if paths.is_empty() {
+        eprintln!("Nothing to clean.");
+        return Ok(());
+    }
+
+    if args.dry_run {
+        eprintln!("Would remove:");
+    } else {
+        eprintln!("Removing:");
+    }
+
+    let mut total = 0;
+    for path in &paths {
+        let size = size(path)?;
+        total += size;
+        eprintln!("{:>12}  {}", human_size(size), path.display());
+
+        if !args.dry_run {
+            if fs::symlink_metadata(path)?.is_dir() {
+                fs2::remove_dir_all(path)?;
+            } else {
+                fs2::remove_file(path)?;
+            }
+        }
+    }
+
+    eprintln!("{:>12}  total", human_size(total));
+
+    Ok(())
+}
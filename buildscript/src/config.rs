@@ -0,0 +1,346 @@
+//! Workspace configuration.
+//!
+//! Defaults for build parameters are read from `workspace.toml` in the workspace root
+//! (next to `workspace.meta`). Settings are applied in this order, later sources
+//! overriding earlier ones:
+//!
+//! 1. built-in defaults,
+//! 2. `workspace.toml`,
+//! 3. `WORKSPACE_*` environment variables,
+//! 4. command line params.
+//!
+//! List settings (`keep`, `templates`, `refs`, `remotes`) are merged instead of overridden.
+//! `path` and `tools` are only read from `workspace.toml`.
+//! `targets` is only used when no target is given on the command line.
+
+use std::{collections::HashMap, fmt, fs, io, path::PathBuf};
+
+use serde::{Deserialize, de::IgnoredAny};
+use toml::Spanned;
+
+use crate::{
+    args::{self, BuildArgs, GitBackend},
+    targets::TARGET_NAMES,
+    tools::{self, ToolConfig},
+};
+
+/// Name of the configuration file.
+pub const CONFIG_FILE: &str = "workspace.toml";
+
+/// Contents of `workspace.toml`.
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Git backend to use for cloning repositories (`"ssh"` or `"https"`).
+    pub git_backend: Option<GitBackend>,
+    /// Starting port number for services.
+    pub ports_start: Option<u16>,
+    /// Server IP address for key authorization.
+    pub server_ip: Option<String>,
+    /// RabbitMQ connection URL.
+    pub rabbitmq_url: Option<String>,
+    /// SurrealDB connection URL.
+    pub surrealdb_url: Option<String>,
+    /// Whether to use `native-image`.
+    pub native_image: Option<bool>,
+    /// Enable Java stacktrace output.
+    pub java_stacktrace: Option<bool>,
+    /// Maximum number of targets built at the same time.
+    pub jobs: Option<usize>,
+    /// Use prebuilt artifacts for targets that are only dependencies.
+    pub prebuilt: Option<bool>,
+    /// Local directory (or `file://` URL) to look for prebuilt artifacts in.
+    pub prebuilt_mirror: Option<PathBuf>,
+    /// Never use the network.
+    pub offline: Option<bool>,
+    /// Replacements of URL prefixes for downloads (URLs, `file://` URLs or directories).
+    pub download_mirrors: HashMap<String, String>,
+    /// HTTP proxy used for downloads.
+    pub proxy: Option<String>,
+    /// Versions and download URLs of tools by target name.
+    pub tools: HashMap<String, ToolConfig>,
+    /// Config templates by target name.
+    pub templates: HashMap<String, PathBuf>,
+    /// Git refs to check out, by target name or checkout directory.
+    pub refs: HashMap<String, String>,
+    /// Remote URLs by target name or checkout directory, used instead of GitHub.
+    pub remotes: HashMap<String, String>,
+    /// Directory (or `file://` URL) with bare mirrors of repositories to clone from.
+    pub git_mirror: Option<PathBuf>,
+    /// Checkouts located outside of the workspace, by target name or checkout directory.
+    pub path: HashMap<String, PathBuf>,
+    /// Paths to keep intact (relative to `.run`).
+    pub keep: Vec<PathBuf>,
+    /// Targets to build when none are given on the command line.
+    pub targets: Vec<String>,
+}
+
+/// Error in the configuration file.
+#[derive(Debug)]
+pub struct ConfigError {
+    /// Path to the configuration file.
+    pub path: PathBuf,
+    /// Line of the error (1-based), if known.
+    pub line: Option<usize>,
+    /// Key the error refers to, if known.
+    pub key: Option<String>,
+    /// Error description.
+    pub message: String,
+}
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.path.display())?;
+        if let Some(line) = self.line {
+            write!(f, ":{line}")?;
+        }
+        if let Some(key) = &self.key {
+            write!(f, ": key `{key}`")?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+impl std::error::Error for ConfigError {}
+
+/// Find the key defined on the line containing `offset`.
+///
+/// Returns the line number (1-based) and the dotted key, prefixed by the
+/// closest table header.
+fn locate(source: &str, offset: usize) -> (usize, Option<String>) {
+    let offset = offset.min(source.len());
+    let line = source[..offset].matches('\n').count();
+    let lines: Vec<&str> = source.lines().collect();
+
+    let key_of = |x: &str| -> Option<String> {
+        let x = x.trim();
+        if x.starts_with('[') {
+            return Some(x.trim_matches(['[', ']', ' ']).to_string());
+        }
+        let (key, _) = x.split_once('=')?;
+        Some(key.trim().trim_matches('"').to_string())
+    };
+
+    let Some(key) = lines.get(line).and_then(|x| key_of(x)) else {
+        return (line + 1, None);
+    };
+    if lines[line].trim().starts_with('[') {
+        return (line + 1, Some(key));
+    }
+
+    let table = lines[..line]
+        .iter()
+        .rev()
+        .find(|x| x.trim().starts_with('['))
+        .and_then(|x| key_of(x));
+    match table {
+        Some(table) => (line + 1, Some(format!("{table}.{key}"))),
+        None => (line + 1, Some(key)),
+    }
+}
+
+/// Find the line where a top-level `key` is defined.
+fn key_line(source: &str, key: &str) -> Option<usize> {
+    source
+        .lines()
+        .position(|x| {
+            x.trim_start()
+                .strip_prefix(key)
+                .is_some_and(|x| x.trim_start().starts_with('='))
+        })
+        .map(|x| x + 1)
+}
+
+/// Positions of the keys of tables keyed by target, checkout or tool name.
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct NameSpans {
+    refs: HashMap<Spanned<String>, IgnoredAny>,
+    remotes: HashMap<Spanned<String>, IgnoredAny>,
+    path: HashMap<Spanned<String>, IgnoredAny>,
+    tools: HashMap<Spanned<String>, IgnoredAny>,
+}
+
+/// Find the line where `name` is defined in `table` (`refs`, `remotes`, `path` or `tools`).
+fn table_key_line(source: &str, table: &str, name: &str) -> Option<usize> {
+    let spans: NameSpans = toml::from_str(source).ok()?;
+    let keys = match table {
+        "refs" => spans.refs,
+        "remotes" => spans.remotes,
+        "path" => spans.path,
+        "tools" => spans.tools,
+        _ => return None,
+    };
+    let key = keys.into_keys().find(|x| x.get_ref() == name)?;
+    Some(locate(source, key.span().start).0)
+}
+
+impl Config {
+    /// Load the configuration file.
+    ///
+    /// Returns the default configuration if the file does not exist.
+    pub fn load() -> Result<Self, ConfigError> {
+        let path = PathBuf::from(CONFIG_FILE);
+        let source = match fs::read_to_string(&path) {
+            Ok(x) => x,
+            Err(why) if why.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(why) => {
+                return Err(ConfigError {
+                    path,
+                    line: None,
+                    key: None,
+                    message: why.to_string(),
+                });
+            }
+        };
+
+        let config: Self = toml::from_str(&source).map_err(|why| {
+            let (line, key) = match why.span() {
+                Some(span) => {
+                    let (line, key) = locate(&source, span.start);
+                    (Some(line), key)
+                }
+                None => (None, None),
+            };
+            ConfigError {
+                path: path.clone(),
+                line,
+                key,
+                message: why.message().to_string(),
+            }
+        })?;
+
+        if config.jobs == Some(0) {
+            return Err(ConfigError {
+                path,
+                line: key_line(&source, "jobs"),
+                key: Some("jobs".into()),
+                message: "expected a positive number".into(),
+            });
+        }
+
+        for target in &config.targets {
+            if !TARGET_NAMES.contains(&target.as_str()) && target != "all" {
+                return Err(ConfigError {
+                    path,
+                    line: key_line(&source, "targets"),
+                    key: Some("targets".into()),
+                    message: format!("unknown target {target:?}"),
+                });
+            }
+        }
+
+        let checkouts = [
+            ("refs", config.refs.keys().collect::<Vec<_>>()),
+            ("remotes", config.remotes.keys().collect()),
+            ("path", config.path.keys().collect()),
+        ];
+        for (table, name) in checkouts
+            .iter()
+            .flat_map(|(table, x)| x.iter().map(move |name| (table, name)))
+        {
+            if !args::is_checkout_name(name) {
+                return Err(ConfigError {
+                    line: table_key_line(&source, table, name),
+                    path,
+                    key: Some(format!("{table}.{name}")),
+                    message: format!("unknown target {name:?}"),
+                });
+            }
+        }
+
+        for name in config.tools.keys() {
+            if !tools::is_tool(name) {
+                return Err(ConfigError {
+                    line: table_key_line(&source, "tools", name),
+                    path,
+                    key: Some(format!("tools.{name}")),
+                    message: format!("unknown tool {name:?}"),
+                });
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Create build arguments with values from config and environment applied.
+    pub fn build_args(&self) -> Result<BuildArgs, String> {
+        let mut build = BuildArgs {
+            git_backend: self.git_backend.unwrap_or_default(),
+            ports_start: self.ports_start.unwrap_or(4100),
+            server_ip: self.server_ip.clone().unwrap_or_default(),
+            rabbitmq_url: self.rabbitmq_url.clone().unwrap_or_default(),
+            surrealdb_url: self.surrealdb_url.clone().unwrap_or_default(),
+            native_image: self.native_image.unwrap_or_default(),
+            java_stackstrace: self.java_stacktrace.unwrap_or_default(),
+            jobs: self
+                .jobs
+                .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |x| x.get())),
+            prebuilt: self.prebuilt.unwrap_or(true),
+            prebuilt_mirror: self.prebuilt_mirror.clone(),
+            templates: self.templates.clone(),
+            refs: self.refs.clone(),
+            remotes: self.remotes.clone(),
+            git_mirror: self.git_mirror.clone(),
+            keep_states: self.keep.clone(),
+            offline: self.offline.unwrap_or_default(),
+            download_mirrors: self.download_mirrors.clone(),
+            proxy: self.proxy.clone(),
+            tools: self.tools.clone(),
+            ..Default::default()
+        };
+
+        let var = |name: &str| std::env::var(name).ok().filter(|x| !x.is_empty());
+
+        if let Some(x) = var("WORKSPACE_GIT_BACKEND") {
+            build.git_backend = match x.as_str() {
+                "ssh" => GitBackend::Ssh,
+                "https" => GitBackend::Https,
+                _ => {
+                    return Err(format!(
+                        "WORKSPACE_GIT_BACKEND: invalid value {x:?}: expected \"ssh\" or \"https\""
+                    ));
+                }
+            };
+        }
+        if let Some(x) = var("WORKSPACE_PORTS_START") {
+            build.ports_start = x.parse().map_err(|_| {
+                format!("WORKSPACE_PORTS_START: invalid value {x:?}: expected a port number")
+            })?;
+        }
+        if let Some(x) = var("WORKSPACE_JOBS") {
+            build.jobs = x.parse().ok().filter(|x| *x != 0).ok_or_else(|| {
+                format!("WORKSPACE_JOBS: invalid value {x:?}: expected a positive number")
+            })?;
+        }
+        if let Some(x) = var("WORKSPACE_PREBUILT_MIRROR") {
+            build.prebuilt_mirror = Some(x.into());
+        }
+        if let Some(x) = var("WORKSPACE_GIT_MIRROR") {
+            build.git_mirror = Some(x.into());
+        }
+        if let Some(x) = var("WORKSPACE_OFFLINE") {
+            build.offline = match x.as_str() {
+                "1" | "true" => true,
+                "0" | "false" => false,
+                _ => {
+                    return Err(format!(
+                        "WORKSPACE_OFFLINE: invalid value {x:?}: expected \"1\", \"true\", \"0\" or \"false\""
+                    ));
+                }
+            };
+        }
+        if let Some(x) = var("WORKSPACE_PROXY") {
+            build.proxy = Some(x);
+        }
+        if let Some(x) = var("WORKSPACE_SERVER_IP") {
+            build.server_ip = x;
+        }
+        if let Some(x) = var("WORKSPACE_RABBITMQ_URL") {
+            build.rabbitmq_url = x;
+        }
+        if let Some(x) = var("WORKSPACE_SURREALDB_URL") {
+            build.surrealdb_url = x;
+        }
+
+        Ok(build)
+    }
+}
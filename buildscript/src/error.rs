@@ -0,0 +1,270 @@
+//! Build errors.
+//!
+//! Failures in targets are returned as an [Error] instead of panicking. The error records
+//! which target failed, in which [Phase] and what exactly went wrong (a command and its exit
+//! code, a file path, a download), and `main` prints it once with [report].
+
+use std::{
+    error::Error as DynError,
+    fmt, io,
+    path::{Path, PathBuf},
+    process::{Child, Command},
+};
+
+use crate::{targets::Target, util::Backtraced};
+
+/// Result of a fallible buildscript operation.
+pub type Result<T, E = Backtraced<Error>> = std::result::Result<T, E>;
+
+/// Phase of the build an error happened in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// Target initialization (installing tools, cloning repositories).
+    Init,
+    /// Building a target.
+    Build,
+    /// Preparing a target to run.
+    RunInit,
+    /// Starting a target.
+    Run,
+}
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Init => "init",
+            Self::Build => "build",
+            Self::RunInit => "run init",
+            Self::Run => "run",
+        })
+    }
+}
+
+/// What went wrong.
+#[derive(Debug)]
+pub enum ErrorKind {
+    /// Command exited with an error.
+    ///
+    /// `code` is `None` if the command was killed by a signal.
+    Command { command: String, code: Option<i32> },
+    /// Command could not be started.
+    Spawn { command: String, error: io::Error },
+    /// Filesystem operation failed.
+    ///
+    /// Errors from [crate::fs2] already include the path in the message.
+    Io {
+        path: Option<PathBuf>,
+        error: io::Error,
+    },
+    /// Download failed.
+    Download { url: String, error: io::Error },
+    /// Built-in git operation failed.
+    Git {
+        operation: String,
+        error: git2::Error,
+    },
+    /// Any other failure.
+    Other(String),
+}
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Command {
+                command,
+                code: Some(code),
+            } => write!(f, "command `{command}` exited with code {code}"),
+            Self::Command {
+                command,
+                code: None,
+            } => write!(f, "command `{command}` was killed by a signal"),
+            Self::Spawn { command, error } => write!(f, "failed to start `{command}`: {error}"),
+            Self::Io {
+                path: Some(path),
+                error,
+            } => write!(f, "{}: {error}", path.display()),
+            Self::Io { path: None, error } => write!(f, "{error}"),
+            Self::Download { url, error } => write!(f, "failed to download {url}: {error}"),
+            Self::Git { operation, error } => {
+                write!(f, "failed to {operation}: {}", error.message())
+            }
+            Self::Other(x) => f.write_str(x),
+        }
+    }
+}
+
+/// Error of a target.
+#[derive(Debug)]
+pub struct Error {
+    /// Target that failed, if known.
+    pub target: Option<Target>,
+    /// Phase the target failed in, if known.
+    pub phase: Option<Phase>,
+    /// What went wrong.
+    pub kind: ErrorKind,
+}
+impl Error {
+    /// Create an error not yet attributed to a target.
+    pub fn new(kind: ErrorKind) -> Backtraced<Self> {
+        Backtraced::new(Self {
+            target: None,
+            phase: None,
+            kind,
+        })
+    }
+
+    /// Create an error with a message.
+    pub fn other(message: impl Into<String>) -> Backtraced<Self> {
+        Self::new(ErrorKind::Other(message.into()))
+    }
+
+    /// Create an error of a filesystem operation on `path`.
+    pub fn io(path: impl AsRef<Path>, error: io::Error) -> Backtraced<Self> {
+        Self::new(ErrorKind::Io {
+            path: Some(path.as_ref().to_path_buf()),
+            error,
+        })
+    }
+
+    /// Create an error of a download from `url`.
+    pub fn download(url: impl Into<String>, error: io::Error) -> Backtraced<Self> {
+        Self::new(ErrorKind::Download {
+            url: url.into(),
+            error,
+        })
+    }
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(target) = self.target {
+            write!(f, "{}: ", target.name())?;
+        }
+        if let Some(phase) = self.phase {
+            write!(f, "{phase}: ")?;
+        }
+        fmt::Display::fmt(&self.kind, f)
+    }
+}
+impl DynError for Error {
+    fn source(&self) -> Option<&(dyn DynError + 'static)> {
+        match &self.kind {
+            ErrorKind::Spawn { error, .. }
+            | ErrorKind::Io { error, .. }
+            | ErrorKind::Download { error, .. } => Some(error),
+            ErrorKind::Git { error, .. } => Some(error),
+            ErrorKind::Command { .. } | ErrorKind::Other(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for Backtraced<Error> {
+    fn from(error: io::Error) -> Self {
+        Error::new(ErrorKind::Io { path: None, error })
+    }
+}
+impl From<zip::result::ZipError> for Backtraced<Error> {
+    fn from(error: zip::result::ZipError) -> Self {
+        io::Error::from(error).into()
+    }
+}
+impl From<Backtraced<io::Error>> for Backtraced<Error> {
+    fn from(error: Backtraced<io::Error>) -> Self {
+        error.map(|error| Error {
+            target: None,
+            phase: None,
+            kind: ErrorKind::Io { path: None, error },
+        })
+    }
+}
+
+/// Extension trait for attributing errors to targets.
+pub trait Context<T> {
+    /// Attribute the error to `target` and `phase`, unless it's already attributed.
+    fn within(self, target: Target, phase: Phase) -> Result<T>;
+}
+impl<T> Context<T> for Result<T> {
+    fn within(self, target: Target, phase: Phase) -> Result<T> {
+        self.map_err(|mut x| {
+            let error = x.error_mut();
+            error.target.get_or_insert(target);
+            error.phase.get_or_insert(phase);
+            x
+        })
+    }
+}
+
+impl<T> Context<T> for io::Result<T> {
+    fn within(self, target: Target, phase: Phase) -> Result<T> {
+        self.map_err(Backtraced::<Error>::from)
+            .within(target, phase)
+    }
+}
+
+/// Extension trait for attaching paths to io errors.
+pub trait IoContext<T> {
+    /// Convert into an [Error] of a filesystem operation on `path`.
+    fn at(self, path: impl AsRef<Path>) -> Result<T>;
+}
+impl<T> IoContext<T> for io::Result<T> {
+    fn at(self, path: impl AsRef<Path>) -> Result<T> {
+        self.map_err(|x| Error::io(path, x))
+    }
+}
+
+/// Describe a command for error messages.
+fn describe(command: &Command) -> String {
+    let mut s = command.get_program().to_string_lossy().into_owned();
+    for x in command.get_args() {
+        let x = x.to_string_lossy();
+        if x.is_empty() || x.contains(char::is_whitespace) {
+            s += &format!(" {x:?}");
+        } else {
+            s += " ";
+            s += &x;
+        }
+    }
+    s
+}
+
+/// Extension trait for running commands.
+pub trait CommandExt {
+    /// Run the command, failing if it does not exit successfully.
+    fn run(&mut self) -> Result<()>;
+    /// Start the command in the background.
+    fn start(&mut self) -> Result<Child>;
+}
+impl CommandExt for Command {
+    fn run(&mut self) -> Result<()> {
+        let status = self.status().map_err(|error| {
+            Error::new(ErrorKind::Spawn {
+                command: describe(self),
+                error,
+            })
+        })?;
+        if !status.success() {
+            return Err(Error::new(ErrorKind::Command {
+                command: describe(self),
+                code: status.code(),
+            }));
+        }
+        Ok(())
+    }
+
+    fn start(&mut self) -> Result<Child> {
+        self.spawn().map_err(|error| {
+            Error::new(ErrorKind::Spawn {
+                command: describe(self),
+                error,
+            })
+        })
+    }
+}
+
+/// Print an error report.
+///
+/// The backtrace is only printed if `RUST_BACKTRACE` is set.
+pub fn report(error: &Backtraced<Error>) {
+    eprintln!("error: {}", error.error());
+    if std::env::var_os("RUST_BACKTRACE").is_some_and(|x| x != "0") {
+        eprintln!();
+        eprintln!("{}", error.backtrace());
+    }
+}
@@ -0,0 +1,341 @@
+//! Extraction of downloaded archives.
+//!
+//! The format is detected from the contents of the archive: tar (optionally compressed with
+//! gzip, xz or bzip2) and zip are supported. Entries are never written outside of the destination:
+//! paths with `..` or absolute paths are rejected, symlinks may only point inside the
+//! destination and nothing is written through a symlink.
+
+use std::{
+    fs,
+    io::{self, Read, Seek},
+    path::{Component, Path, PathBuf},
+};
+
+use crate::fs2;
+
+/// Format of an archive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Uncompressed tar.
+    Tar,
+    /// Gzip-compressed tar.
+    TarGz,
+    /// Xz-compressed tar.
+    TarXz,
+    /// Bzip2-compressed tar.
+    TarBz2,
+    /// Zip.
+    Zip,
+}
+
+impl Format {
+    /// Detect the format of the archive at `path` from its first bytes.
+    pub fn detect(path: &Path) -> io::Result<Option<Self>> {
+        let mut head = [0; 262];
+        let mut file = fs2::open(path)?;
+        let mut len = 0;
+        while len < head.len() {
+            match file.read(&mut head[len..])? {
+                0 => break,
+                l => len += l,
+            }
+        }
+        let head = &head[..len];
+
+        Ok(if head.starts_with(&[0x1f, 0x8b]) {
+            Some(Self::TarGz)
+        } else if head.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0]) {
+            Some(Self::TarXz)
+        } else if head.starts_with(b"BZh") {
+            Some(Self::TarBz2)
+        } else if head.starts_with(b"PK\x03\x04") || head.starts_with(b"PK\x05\x06") {
+            Some(Self::Zip)
+        } else if head.get(257..262) == Some(b"ustar") {
+            Some(Self::Tar)
+        } else {
+            None
+        })
+    }
+}
+
+/// Kind of an archive entry.
+enum Kind {
+    Dir,
+    File,
+    /// Symbolic link to a path relative to the entry.
+    Symlink(String),
+    /// Hard link to another entry of the archive.
+    Hardlink(String),
+}
+
+/// Extracts an archive into `dest`.
+///
+/// The first `skip_segments` segments of every entry path are dropped, e.g. the top directory
+/// most release archives have. Entries with no segments left are skipped.
+///
+/// # Arguments
+/// * `archive` - Path to the archive
+/// * `dest` - Destination directory
+/// * `skip_segments` - Number of path segments to skip when extracting
+pub fn extract(
+    archive: impl AsRef<Path>,
+    dest: impl AsRef<Path>,
+    skip_segments: usize,
+) -> io::Result<()> {
+    let archive = archive.as_ref();
+    let out = Extractor {
+        dest: dest.as_ref(),
+        skip_segments,
+    };
+    fs2::create_dir_all(out.dest)?;
+
+    let file = || fs2::open(archive).map(io::BufReader::new);
+    match Format::detect(archive)? {
+        #[cfg(unix)]
+        Some(Format::Tar) => out.tar(file()?),
+        #[cfg(unix)]
+        Some(Format::TarGz) => out.tar(flate2::bufread::GzDecoder::new(file()?)),
+        #[cfg(unix)]
+        Some(Format::TarXz) => out.tar(xz::bufread::XzDecoder::new(file()?)),
+        #[cfg(unix)]
+        Some(Format::TarBz2) => out.tar(bzip2::bufread::BzDecoder::new(file()?)),
+        Some(Format::Zip) => out.zip(file()?),
+        #[cfg(not(unix))]
+        Some(_) => Err(out.error("tar archives are not supported on this platform")),
+        None => Err(out.error("unknown archive format")),
+    }
+}
+
+/// State of an extraction.
+struct Extractor<'a> {
+    dest: &'a Path,
+    skip_segments: usize,
+}
+
+impl Extractor<'_> {
+    /// Error about the archive, the caller adds its path.
+    fn error(&self, message: impl Into<String>) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, message.into())
+    }
+
+    /// Path of entry `name` relative to the destination, `None` if it is skipped.
+    fn entry_path(&self, name: &str) -> io::Result<Option<PathBuf>> {
+        if name.starts_with('/') || name.starts_with('\\') {
+            return Err(self.error(format!("entry {name:?} has an absolute path")));
+        }
+        let mut path = PathBuf::new();
+        for (i, segment) in name
+            .split(['/', '\\'])
+            .filter(|x| !x.is_empty() && *x != ".")
+            .enumerate()
+        {
+            let mut components = Path::new(segment).components();
+            if !matches!(components.next(), Some(Component::Normal(_)))
+                || components.next().is_some()
+            {
+                return Err(self.error(format!("entry {name:?} leaves the destination")));
+            }
+            if i >= self.skip_segments {
+                path.push(segment);
+            }
+        }
+        Ok((!path.as_os_str().is_empty()).then_some(path))
+    }
+
+    /// Check that the symlink at `path` pointing to `target` stays inside the destination.
+    fn check_symlink(&self, path: &Path, target: &str) -> io::Result<()> {
+        let escapes = || {
+            self.error(format!(
+                "symlink {:?} -> {target:?} points outside of the destination",
+                path.display()
+            ))
+        };
+        if target.starts_with('/') || target.starts_with('\\') {
+            return Err(escapes());
+        }
+        let mut depth = path.components().count() - 1;
+        for segment in target.split(['/', '\\']) {
+            match segment {
+                "" | "." => (),
+                ".." => depth = depth.checked_sub(1).ok_or_else(escapes)?,
+                x if Path::new(x).is_absolute() || x.contains(':') => return Err(escapes()),
+                _ => depth += 1,
+            }
+        }
+        Ok(())
+    }
+
+    /// Prepare writing the entry at `path`: create its parent directories and remove what was
+    /// there before, refusing to go through symlinks.
+    fn prepare(&self, path: &Path) -> io::Result<PathBuf> {
+        let mut dir = self.dest.to_path_buf();
+        let mut segments = path.iter().peekable();
+        while let Some(x) = segments.next() {
+            dir.push(x);
+            let Ok(metadata) = fs::symlink_metadata(&dir) else {
+                if segments.peek().is_some() {
+                    fs2::create_dir_all(&dir)?;
+                }
+                continue;
+            };
+            if segments.peek().is_none() {
+                if !metadata.is_dir() {
+                    fs2::remove_file(&dir)?;
+                }
+            } else if metadata.file_type().is_symlink() || !metadata.is_dir() {
+                return Err(self.error(format!(
+                    "entry {:?} is inside of a symlink or a file",
+                    path.display()
+                )));
+            }
+        }
+        Ok(dir)
+    }
+
+    /// Write one entry.
+    fn write(
+        &self,
+        path: &Path,
+        kind: Kind,
+        mode: Option<u32>,
+        data: &mut dyn Read,
+    ) -> io::Result<()> {
+        let out = self.prepare(path)?;
+        match kind {
+            Kind::Dir => {
+                if !out.is_dir() {
+                    fs2::create_dir_all(&out)?;
+                }
+                // Keep directories writable, later entries still go into them.
+                set_mode(&out, mode.map(|x| x | 0o700))
+            }
+            Kind::File => {
+                let mut file = fs2::create(&out)?;
+                io::copy(data, &mut file)
+                    .map_err(|why| self.error(format!("{}: {why}", path.display())))?;
+                drop(file);
+                set_mode(&out, mode)
+            }
+            Kind::Symlink(target) => {
+                self.check_symlink(path, &target)?;
+                symlink(&target, &out)
+            }
+            Kind::Hardlink(target) => {
+                let Some(target) = self.entry_path(&target)? else {
+                    return Err(self.error(format!(
+                        "hard link {:?} points outside of the extracted entries",
+                        path.display()
+                    )));
+                };
+                let source = self.dest.join(&target);
+                let is_file = fs::symlink_metadata(&source).is_ok_and(|x| x.is_file());
+                if !is_file {
+                    return Err(self.error(format!(
+                        "hard link {:?} -> {:?} does not point to an extracted file",
+                        path.display(),
+                        target.display()
+                    )));
+                }
+                fs2::hard_link(&source, &out).or_else(|_| fs2::copy(&source, &out).map(|_| ()))
+            }
+        }
+    }
+
+    /// Extract a tar archive.
+    #[cfg(unix)]
+    fn tar(&self, reader: impl Read) -> io::Result<()> {
+        use tar::EntryType;
+
+        let mut archive = tar::Archive::new(reader);
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let name = String::from_utf8(entry.path_bytes().into_owned())
+                .map_err(|why| self.error(format!("invalid entry path: {why}")))?;
+            let link = || {
+                let target = entry.link_name_bytes().unwrap_or_default();
+                String::from_utf8(target.into_owned())
+                    .map_err(|why| self.error(format!("{name}: invalid link target: {why}")))
+            };
+            let kind = match entry.header().entry_type() {
+                EntryType::Directory => Kind::Dir,
+                EntryType::Regular | EntryType::Continuous | EntryType::GNUSparse => Kind::File,
+                EntryType::Symlink => Kind::Symlink(link()?),
+                EntryType::Link => Kind::Hardlink(link()?),
+                // Devices, fifos and metadata entries.
+                _ => continue,
+            };
+            let Some(path) = self.entry_path(&name)? else {
+                continue;
+            };
+            let mode = entry.header().mode().ok();
+            self.write(&path, kind, mode, &mut entry)?;
+        }
+        Ok(())
+    }
+
+    /// Extract a zip archive.
+    fn zip(&self, reader: impl Read + Seek) -> io::Result<()> {
+        let mut archive = zip::ZipArchive::new(reader)?;
+        for i in 0..archive.len() {
+            let mut entry = archive.by_index(i)?;
+            let name = entry.name().to_string();
+            let kind = if entry.is_dir() {
+                Kind::Dir
+            } else if entry.is_symlink() {
+                let mut target = String::new();
+                entry.read_to_string(&mut target)?;
+                Kind::Symlink(target)
+            } else {
+                Kind::File
+            };
+            let Some(path) = self.entry_path(&name)? else {
+                continue;
+            };
+            let mode = entry.unix_mode();
+            self.write(&path, kind, mode, &mut entry)?;
+        }
+        Ok(())
+    }
+}
+
+/// Set the permission bits of `path`, without setuid, setgid and sticky bits.
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: Option<u32>) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let Some(mode) = mode else {
+        return Ok(());
+    };
+    fs::set_permissions(path, fs::Permissions::from_mode(mode & 0o777)).map_err(|x| {
+        io::Error::new(
+            x.kind(),
+            format!("set_permissions({:?}): {x}", path.display()),
+        )
+    })
+}
+
+#[cfg(not(unix))]
+fn set_mode(_: &Path, _: Option<u32>) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(unix)]
+fn symlink(target: &str, path: &Path) -> io::Result<()> {
+    crate::util::symlink_file(target, path).map_err(|x| {
+        io::Error::new(
+            x.kind(),
+            format!("symlink({target:?}, {:?}): {x}", path.display()),
+        )
+    })
+}
+
+#[cfg(not(unix))]
+fn symlink(target: &str, path: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!(
+            "symlink({target:?}, {:?}): symlinks are not supported",
+            path.display()
+        ),
+    ))
+}
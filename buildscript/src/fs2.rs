@@ -1,5 +1,5 @@
 use std::{
-    fs::{self, DirEntry, Metadata},
+    fs::{self, DirEntry, File, Metadata},
     io,
     path::{Path, PathBuf},
 };
@@ -123,6 +123,20 @@ pub fn metadata<S: AsRef<Path>>(path: S) -> io::Result<Metadata> {
     })
 }
 
+/// Get the file metadata without following symlinks.
+///
+/// ## Fs2
+/// This function will use more memory to improve error reporting.
+/// If you want a more lightweight version, use [fs::symlink_metadata].
+pub fn symlink_metadata<S: AsRef<Path>>(path: S) -> io::Result<Metadata> {
+    fs::symlink_metadata(path.as_ref()).map_err(|x| {
+        io::Error::new(
+            x.kind(),
+            format!("symlink_metadata({:?}): {}", path.as_ref().display(), x),
+        )
+    })
+}
+
 /// Delete a file.
 ///
 /// ## Fs2
@@ -150,3 +164,111 @@ pub fn remove_dir_all<S: AsRef<Path>>(path: S) -> io::Result<()> {
         )
     })
 }
+
+/// Read the entire contents of a file into a bytes vector.
+///
+/// ## Fs2
+/// This function will use more memory to improve error reporting.
+/// If you want a more lightweight version, use [fs::read].
+pub fn read<S: AsRef<Path>>(path: S) -> io::Result<Vec<u8>> {
+    fs::read(path.as_ref()).map_err(|x| {
+        io::Error::new(
+            x.kind(),
+            format!("read({:?}): {}", path.as_ref().display(), x),
+        )
+    })
+}
+
+/// Read the entire contents of a file into a string.
+///
+/// ## Fs2
+/// This function will use more memory to improve error reporting.
+/// If you want a more lightweight version, use [fs::read_to_string].
+pub fn read_to_string<S: AsRef<Path>>(path: S) -> io::Result<String> {
+    fs::read_to_string(path.as_ref()).map_err(|x| {
+        io::Error::new(
+            x.kind(),
+            format!("read_to_string({:?}): {}", path.as_ref().display(), x),
+        )
+    })
+}
+
+/// Get the absolute path with all symlinks resolved.
+///
+/// ## Fs2
+/// This function will use more memory to improve error reporting.
+/// If you want a more lightweight version, use [fs::canonicalize].
+pub fn canonicalize<S: AsRef<Path>>(path: S) -> io::Result<PathBuf> {
+    fs::canonicalize(path.as_ref()).map_err(|x| {
+        io::Error::new(
+            x.kind(),
+            format!("canonicalize({:?}): {}", path.as_ref().display(), x),
+        )
+    })
+}
+
+/// Copy a file.
+///
+/// ## Fs2
+/// This function will use more memory to improve error reporting.
+/// If you want a more lightweight version, use [fs::copy].
+pub fn copy<F: AsRef<Path>, T: AsRef<Path>>(from: F, to: T) -> io::Result<u64> {
+    fs::copy(from.as_ref(), to.as_ref()).map_err(|x| {
+        io::Error::new(
+            x.kind(),
+            format!(
+                "copy({:?}, {:?}): {}",
+                from.as_ref().display(),
+                to.as_ref().display(),
+                x
+            ),
+        )
+    })
+}
+
+/// Rename a file or a directory.
+///
+/// ## Fs2
+/// This function will use more memory to improve error reporting.
+/// If you want a more lightweight version, use [fs::rename].
+pub fn rename<F: AsRef<Path>, T: AsRef<Path>>(from: F, to: T) -> io::Result<()> {
+    fs::rename(from.as_ref(), to.as_ref()).map_err(|x| {
+        io::Error::new(
+            x.kind(),
+            format!(
+                "rename({:?}, {:?}): {}",
+                from.as_ref().display(),
+                to.as_ref().display(),
+                x
+            ),
+        )
+    })
+}
+
+/// Open a file for reading.
+///
+/// ## Fs2
+/// This function will use more memory to improve error reporting.
+/// If you want a more lightweight version, use [File::open].
+pub fn open<S: AsRef<Path>>(path: S) -> io::Result<File> {
+    File::open(path.as_ref()).map_err(|x| {
+        io::Error::new(
+            x.kind(),
+            format!("open({:?}): {}", path.as_ref().display(), x),
+        )
+    })
+}
+
+/// Create or truncate a file for writing.
+///
+/// ## Fs2
+/// This function will use more memory to improve error reporting.
+/// If you want a more lightweight version, use [File::create].
+pub fn create<S: AsRef<Path>>(path: S) -> io::Result<File> {
+    File::create(path.as_ref()).map_err(|x| {
+        io::Error::new(
+            x.kind(),
+            format!("create({:?}): {}", path.as_ref().display(), x),
+        )
+    })
+}
@@ -0,0 +1,485 @@
+//! Git operations on target checkouts.
+//!
+//! Every clone, checkout and query of a [Repo] goes through this module. It also implements
+//! `./b git`, which runs `status`, `pull` and `switch` in checkouts of several targets at once.
+//!
+//! Operations needed by builds use the [builtin] implementation instead of the `git`
+//! executable when enabled with [set_builtin]. `./b git` always runs the executable.
+
+mod builtin;
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::OnceLock,
+};
+
+use crate::{
+    args::{BuildArgs, GitArgs, GitBackend, GitCommand},
+    error::{CommandExt, Error, Result},
+    fs2, patches,
+    prebuilt::mirror_path,
+    targets::{Repo, Target},
+    util::{current_dir, is_offline, not_cached},
+};
+
+/// Where repositories are cloned from.
+#[derive(Clone, Default)]
+pub struct Remotes {
+    /// Protocol used for repositories on GitHub.
+    pub backend: GitBackend,
+    /// Remote URLs by target name or checkout directory, used instead of GitHub.
+    pub overrides: HashMap<String, String>,
+    /// Directory (or `file://` URL) with bare mirrors of repositories.
+    ///
+    /// Mirrors are named like the repository on GitHub, e.g. `Darkdustry-Coders/Forts.git`.
+    pub mirror: Option<PathBuf>,
+}
+impl Remotes {
+    /// Create remotes from build arguments.
+    pub fn new(args: &BuildArgs) -> Self {
+        Self {
+            backend: args.git_backend,
+            overrides: args.remotes.clone(),
+            mirror: args.git_mirror.clone(),
+        }
+    }
+
+    /// URL `repo` is cloned from.
+    pub fn url(&self, repo: &Repo) -> String {
+        self.resolve(repo, self.backend)
+    }
+
+    /// Remote of `repo` recorded in the lock and shown in status.
+    ///
+    /// Same as [url](Self::url), except GitHub is always reached over https, so it does not
+    /// depend on the git backend.
+    pub fn remote(&self, repo: &Repo) -> String {
+        self.resolve(repo, GitBackend::Https)
+    }
+
+    fn resolve(&self, repo: &Repo, backend: GitBackend) -> String {
+        if let Some(x) = for_repo(&self.overrides, repo) {
+            return x.clone();
+        }
+        if let Some(mirror) = &self.mirror {
+            let path = mirror_path(mirror).join(format!("{}.git", repo.remote));
+            if path.is_dir() {
+                return path.display().to_string();
+            }
+        }
+        backend.repo_url(repo.remote)
+    }
+}
+
+/// Look up the setting of `repo` in a map keyed by target name or checkout directory.
+///
+/// A setting given for a target applies to its first repository only, others have to be
+/// named by their checkout directory.
+pub fn for_repo<'a, T>(map: &'a HashMap<String, T>, repo: &Repo) -> Option<&'a T> {
+    if let Some(x) = map.get(repo.dir) {
+        return Some(x);
+    }
+    let target = Target::ALL
+        .iter()
+        .find(|x| x.repos().first().is_some_and(|x| x.dir == repo.dir))?;
+    map.get(target.name())
+}
+
+/// Whether the built-in git implementation is used, see [set_builtin].
+static BUILTIN: OnceLock<bool> = OnceLock::new();
+
+/// Use the built-in git implementation instead of the `git` executable.
+///
+/// Enabled in isolated environments and when git is not installed.
+pub fn set_builtin(builtin: bool) {
+    _ = BUILTIN.set(builtin);
+}
+
+/// Whether the built-in git implementation is used.
+pub fn is_builtin() -> bool {
+    BUILTIN.get().copied().unwrap_or(false)
+}
+
+/// Create a `git` command operating on the checkout at `dir`.
+fn git(dir: impl AsRef<Path>) -> Command {
+    let mut command = Command::new("git");
+    command.arg("-C").arg(dir.as_ref());
+    command
+}
+
+/// Run `git` in `dir` and return its trimmed output.
+///
+/// Returns `None` if the command fails.
+fn output(dir: impl AsRef<Path>, args: &[&str]) -> Option<String> {
+    let output = git(dir).args(args).stderr(Stdio::null()).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8(output.stdout).ok()?.trim().to_string())
+}
+
+/// Clone `repo` into its checkout directory.
+///
+/// In offline mode, only local mirrors and remotes can be cloned.
+pub fn clone(repo: &Repo, remotes: &Remotes) -> Result<()> {
+    let url = remotes.url(repo);
+    if is_offline() && !is_local(&url) {
+        return not_cached(format!("checkout {} (from {url})", repo.dir));
+    }
+    if is_builtin() {
+        return builtin::clone(&url, &repo.path(), repo.dir);
+    }
+    Command::new("git")
+        .arg("clone")
+        .arg(url)
+        .arg(repo.path())
+        .run()
+}
+
+/// Whether `url` is a repository on the local filesystem.
+fn is_local(url: &str) -> bool {
+    url.starts_with("file://") || Path::new(url).is_absolute()
+}
+
+/// Fail if `what` has to be fetched from `origin` of the repository at `dir` in offline mode.
+///
+/// Local remotes can be fetched from offline.
+fn check_online(dir: &Path, what: &str) -> Result<()> {
+    if !is_offline() {
+        return Ok(());
+    }
+    let url = if is_builtin() {
+        builtin::origin_url(dir)
+    } else {
+        output(dir, &["remote", "get-url", "origin"])
+    };
+    match url {
+        Some(url) if is_local(&url) => Ok(()),
+        _ => not_cached(format!("{what} of {}", dir.display())),
+    }
+}
+
+/// Whether `dir` is a git checkout.
+pub fn is_checkout(dir: impl AsRef<Path>) -> bool {
+    dir.as_ref().join(".git").exists()
+}
+
+/// Obtain the commit checked out in the repository at `dir`.
+pub fn revision(dir: impl AsRef<Path>) -> Option<String> {
+    if is_builtin() {
+        return builtin::revision(dir.as_ref());
+    }
+    output(dir, &["rev-parse", "HEAD"])
+}
+
+/// Whether the checkout at `dir` has uncommitted changes to tracked files.
+pub fn is_dirty(dir: impl AsRef<Path>) -> bool {
+    if is_builtin() {
+        return builtin::is_dirty(dir.as_ref());
+    }
+    output(dir, &["status", "--porcelain", "--untracked-files=no"]).is_some_and(|x| !x.is_empty())
+}
+
+/// Whether `commit` is available in the repository at `dir`.
+pub fn has_commit(dir: impl AsRef<Path>, commit: &str) -> bool {
+    resolve(dir, commit).is_some()
+}
+
+/// Fetch all branches of `origin` into the repository at `dir` to get `commit`.
+pub fn fetch(dir: impl AsRef<Path>, commit: &str) -> Result<()> {
+    check_online(dir.as_ref(), &format!("commit {}", short(commit)))?;
+    if is_builtin() {
+        return builtin::fetch(dir.as_ref(), &[]);
+    }
+    git(dir).args(["fetch", "origin"]).run()
+}
+
+/// Check out `commit` in the repository at `dir`, detaching `HEAD`.
+pub fn checkout_detached(dir: impl AsRef<Path>, commit: &str) -> Result<()> {
+    if is_builtin() {
+        return builtin::checkout_detached(dir.as_ref(), commit);
+    }
+    git(dir)
+        .args(["checkout", "--quiet", "--detach", commit])
+        .run()
+}
+
+/// Commit `git_ref` points to in the repository at `dir`.
+fn resolve(dir: impl AsRef<Path>, git_ref: &str) -> Option<String> {
+    if is_builtin() {
+        return builtin::resolve(dir.as_ref(), git_ref).map(|x| x.to_string());
+    }
+    output(
+        dir,
+        &[
+            "rev-parse",
+            "--verify",
+            "--quiet",
+            &format!("{git_ref}^{{commit}}"),
+        ],
+    )
+}
+
+/// Check out a branch, tag or commit in the checkout of `repo`.
+///
+/// Nothing is done if the checkout is already at `git_ref`. Refs not known locally (like
+/// `pull/42/head`) are fetched from `origin` first.
+pub fn checkout_ref(repo: &Repo, git_ref: &str) -> Result<()> {
+    if !is_checkout(repo.path())
+        || branch(repo.path()).as_deref() == Some(git_ref)
+        || resolve(repo.path(), git_ref).is_some_and(|x| Some(x) == revision(repo.path()))
+    {
+        return Ok(());
+    }
+    patches::revert(repo)?;
+    if is_dirty(repo.path()) {
+        return Err(Error::other(format!(
+            "{}: has uncommitted changes, cannot check out {git_ref}",
+            repo.dir
+        )));
+    }
+
+    eprintln!("{}: checking out {git_ref}", repo.dir);
+    if resolve(repo.path(), git_ref).is_some() || has_branch(repo.path(), git_ref) {
+        if is_builtin() {
+            return builtin::checkout(&repo.path(), git_ref);
+        }
+        return git(repo.path())
+            .args(["checkout", "--quiet", git_ref])
+            .run();
+    }
+    check_online(&repo.path(), git_ref)?;
+    if is_builtin() {
+        builtin::fetch(&repo.path(), &[git_ref])?;
+    } else {
+        git(repo.path()).args(["fetch", "origin", git_ref]).run()?;
+    }
+    checkout_detached(repo.path(), "FETCH_HEAD")
+}
+
+/// Apply the patch file at `patch` to the working tree and index of the checkout at `dir`.
+pub fn apply(dir: impl AsRef<Path>, patch: &Path) -> Result<()> {
+    if is_builtin() {
+        let data = fs2::read(patch)?;
+        return builtin::apply(dir.as_ref(), &data);
+    }
+    git(dir)
+        .args(["apply", "--index", "--whitespace=nowarn"])
+        .arg(current_dir().join(patch))
+        .run()
+}
+
+/// Diff of the checkout at `dir` against `HEAD`, including staged files.
+pub fn diff(dir: impl AsRef<Path>) -> Result<Vec<u8>> {
+    let dir = dir.as_ref();
+    if is_builtin() {
+        return builtin::diff(dir);
+    }
+    let output = git(dir)
+        .args(["diff", "--binary", "HEAD"])
+        .stderr(Stdio::inherit())
+        .output()?;
+    if !output.status.success() {
+        return Err(Error::other(format!(
+            "git diff failed in {}",
+            dir.display()
+        )));
+    }
+    Ok(output.stdout)
+}
+
+/// Discard changes to tracked files in the checkout at `dir`.
+pub fn reset_hard(dir: impl AsRef<Path>) -> Result<()> {
+    if is_builtin() {
+        return builtin::reset_hard(dir.as_ref());
+    }
+    git(dir).args(["reset", "--quiet", "--hard"]).run()
+}
+
+/// Shorten a commit hash for messages.
+pub fn short(commit: &str) -> &str {
+    &commit[..commit.len().min(12)]
+}
+
+/// Repositories of the given targets (all targets if `names` is empty or has `all`).
+pub fn repos(names: &[String]) -> Vec<&'static Repo> {
+    let all = names.is_empty() || names.iter().any(|x| x == "all");
+    Target::ALL
+        .iter()
+        .filter(|x| all || names.iter().any(|name| name == x.name()))
+        .flat_map(|x| x.repos())
+        .collect()
+}
+
+/// Branch checked out in the repository at `dir`.
+///
+/// Returns `None` if `HEAD` is detached.
+pub fn branch(dir: impl AsRef<Path>) -> Option<String> {
+    if is_builtin() {
+        return builtin::branch(dir.as_ref());
+    }
+    output(dir, &["symbolic-ref", "--short", "--quiet", "HEAD"])
+}
+
+/// Number of commits the current branch is ahead and behind its upstream.
+///
+/// Returns `None` if the branch has no upstream.
+pub fn ahead_behind(dir: impl AsRef<Path>) -> Option<(u32, u32)> {
+    if is_builtin() {
+        return builtin::ahead_behind(dir.as_ref());
+    }
+    let counts = output(
+        dir,
+        &["rev-list", "--left-right", "--count", "HEAD...@{upstream}"],
+    )?;
+    let (ahead, behind) = counts.split_once('\t')?;
+    Some((ahead.parse().ok()?, behind.parse().ok()?))
+}
+
+/// Changed and untracked files in the checkout at `dir`, in `git status --short` format.
+pub fn changes(dir: impl AsRef<Path>) -> Vec<String> {
+    if is_builtin() {
+        return builtin::changes(dir.as_ref());
+    }
+    let output = git(dir)
+        .args(["status", "--porcelain"])
+        .stderr(Stdio::null())
+        .output();
+    match output {
+        Ok(x) if x.status.success() => String::from_utf8_lossy(&x.stdout)
+            .lines()
+            .map(str::to_string)
+            .collect(),
+        _ => vec![],
+    }
+}
+
+/// Whether `branch` exists locally or in `origin` of the repository at `dir`.
+fn has_branch(dir: impl AsRef<Path>, branch: &str) -> bool {
+    let dir = dir.as_ref();
+    [
+        format!("refs/heads/{branch}"),
+        format!("refs/remotes/origin/{branch}"),
+    ]
+    .iter()
+    .any(|x| resolve(dir, x).is_some())
+}
+
+/// Implementation of `./b git`.
+///
+/// Operates on present checkouts of the given targets (of all targets if none are given).
+/// A failure in one checkout does not stop the others.
+pub fn git_command(args: &GitArgs) -> Result<()> {
+    let repos: Vec<&Repo> = repos(&args.targets)
+        .into_iter()
+        .filter(|x| is_checkout(x.path()))
+        .collect();
+    if repos.is_empty() {
+        eprintln!("no checkouts present");
+        return Ok(());
+    }
+
+    let mut failed = vec![];
+    for repo in repos {
+        match &args.command {
+            GitCommand::Status => status(repo),
+            GitCommand::Pull => {
+                if branch(repo.path()).is_none() {
+                    eprintln!("==> {}: detached HEAD, skipped", repo.dir);
+                    continue;
+                }
+                eprintln!("==> {}", repo.dir);
+                if let Err(why) = without_patches(repo, || pull(repo.path())) {
+                    eprintln!("error: {}", why.error());
+                    failed.push(repo.dir);
+                }
+            }
+            GitCommand::Switch { branch, create } => {
+                eprintln!("==> {}", repo.dir);
+                let exists = has_branch(repo.path(), branch);
+                if !exists && !create {
+                    eprintln!("error: no branch {branch}, pass `--create` to create it");
+                    failed.push(repo.dir);
+                    continue;
+                }
+                if let Err(why) = without_patches(repo, || switch(repo.path(), branch, !exists)) {
+                    eprintln!("error: {}", why.error());
+                    failed.push(repo.dir);
+                }
+            }
+        }
+    }
+
+    if !failed.is_empty() {
+        return Err(Error::other(format!("git failed in {}", failed.join(", "))));
+    }
+    Ok(())
+}
+
+/// Fast-forward the current branch of the checkout at `dir` to its upstream.
+fn pull(dir: impl AsRef<Path>) -> Result<()> {
+    let dir = dir.as_ref();
+    if is_builtin() {
+        return builtin::pull(dir);
+    }
+    git(dir).args(["pull", "--ff-only"]).run()
+}
+
+/// Switch the checkout at `dir` to `branch`, creating it at `HEAD` if `create` is set.
+fn switch(dir: impl AsRef<Path>, branch: &str, create: bool) -> Result<()> {
+    let dir = dir.as_ref();
+    if is_builtin() {
+        if create {
+            builtin::create_branch(dir, branch)?;
+        }
+        return builtin::checkout(dir, branch);
+    }
+    let mut command = git(dir);
+    command.arg("switch");
+    if create {
+        command.arg("--create");
+    }
+    command.arg(branch).run()
+}
+
+/// Run `f` on the checkout of `repo` with its applied patches reverted.
+///
+/// The patches are applied again afterwards, also if `f` fails.
+fn without_patches(repo: &Repo, f: impl FnOnce() -> Result<()>) -> Result<()> {
+    let applied = patches::is_applied(repo);
+    patches::revert(repo)?;
+    let result = f();
+    if applied {
+        patches::apply(repo)?;
+    }
+    result
+}
+
+/// Print the status of a checkout for `./b git status`.
+fn status(repo: &Repo) {
+    let head = match branch(repo.path()) {
+        Some(x) => x,
+        None => format!(
+            "(detached at {})",
+            revision(repo.path()).as_deref().map_or("?", short)
+        ),
+    };
+    let tracking = match ahead_behind(repo.path()) {
+        Some((0, 0)) => "up to date".to_string(),
+        Some((ahead, behind)) => format!("ahead {ahead}, behind {behind}"),
+        None => "no upstream".to_string(),
+    };
+    let changes = changes(repo.path());
+    let dirty = match changes.len() {
+        0 => String::new(),
+        1 => ", 1 changed file".to_string(),
+        n => format!(", {n} changed files"),
+    };
+
+    println!("{:<24} {head:<24} {tracking}{dirty}", repo.dir);
+    for x in changes {
+        println!("    {x}");
+    }
+}
@@ -0,0 +1,363 @@
+//! In-process git implementation.
+//!
+//! Used instead of the `git` executable in isolated environments and on hosts without git.
+//! Covers what builds and `./b git` need: cloning, fetching, checking out, fast-forwarding
+//! and querying checkouts.
+
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use git2::{
+    ApplyLocation, AutotagOption, BranchType, Cred, CredentialType, Diff, DiffFormat, DiffOptions,
+    FetchOptions, Oid, Progress, RemoteCallbacks, Repository, ResetType, Status, StatusOptions,
+    build::{CheckoutBuilder, RepoBuilder},
+};
+
+use crate::{
+    error::{Error, ErrorKind, Result},
+    util::Backtraced,
+};
+
+/// Wrap a git error with the operation that failed.
+fn error(operation: impl Into<String>) -> impl FnOnce(git2::Error) -> Backtraced<Error> {
+    let operation = operation.into();
+    move |error| Error::new(ErrorKind::Git { operation, error })
+}
+
+/// Keys in `~/.ssh` tried after the ssh agent, like ssh does.
+const SSH_KEYS: &[&str] = &["id_ed25519", "id_ecdsa", "id_rsa"];
+
+/// Fetch options printing transfer progress of `label` to stderr.
+///
+/// ssh remotes are authenticated with the ssh agent, then with the default keys in `~/.ssh`.
+fn fetch_options(label: &str) -> FetchOptions<'_> {
+    let mut last = None;
+    let mut callbacks = RemoteCallbacks::new();
+    // libgit2 asks again after every rejected credential, each one is offered once.
+    let mut attempt = 0;
+    callbacks.credentials(move |url, username, allowed| {
+        let username = username.unwrap_or("git");
+        if allowed.contains(CredentialType::USERNAME) {
+            return Cred::username(username);
+        }
+        if !allowed.contains(CredentialType::SSH_KEY) {
+            return Err(git2::Error::from_str(&format!(
+                "{url} needs credentials, which only the installed git can ask for"
+            )));
+        }
+        let ssh_dir = std::env::var_os("HOME").map(|x| PathBuf::from(x).join(".ssh"));
+        let keys: Vec<PathBuf> = SSH_KEYS
+            .iter()
+            .filter_map(|x| Some(ssh_dir.as_ref()?.join(x)))
+            .filter(|x| x.is_file())
+            .collect();
+        attempt += 1;
+        if attempt == 1 {
+            return Cred::ssh_key_from_agent(username);
+        }
+        match keys.get(attempt - 2) {
+            Some(key) => Cred::ssh_key(username, None, key, None),
+            None => Err(git2::Error::from_str(&format!(
+                "no ssh key was accepted for {url}, add one to the ssh agent or use an https remote"
+            ))),
+        }
+    });
+    callbacks.transfer_progress(move |progress: Progress<'_>| {
+        let total = progress.total_objects();
+        if total == 0 {
+            return true;
+        }
+        let (done, step) = if progress.received_objects() < total {
+            (progress.received_objects(), "receiving")
+        } else {
+            (progress.indexed_objects(), "indexing")
+        };
+        let percent = done * 100 / total;
+        if last != Some((step, percent)) {
+            last = Some((step, percent));
+            eprint!("\r{label}: {step} objects: {percent:>3}% ({done}/{total})");
+            if step == "indexing" && done == total {
+                eprintln!();
+            }
+            _ = std::io::stderr().flush();
+        }
+        true
+    });
+
+    let mut options = FetchOptions::new();
+    options
+        .remote_callbacks(callbacks)
+        .download_tags(AutotagOption::All);
+    options
+}
+
+/// Open the repository at `dir`.
+fn open(dir: &Path) -> Result<Repository> {
+    Repository::open(dir).map_err(error(format!("open {}", dir.display())))
+}
+
+/// Clone `url` into `dir`.
+pub fn clone(url: &str, dir: &Path, label: &str) -> Result<()> {
+    eprintln!("{label}: cloning {url}");
+    RepoBuilder::new()
+        .fetch_options(fetch_options(label))
+        .clone(url, dir)
+        .map_err(error(format!("clone {url}")))?;
+    Ok(())
+}
+
+/// Fetch `refspecs` from `origin`, or all branches if `refspecs` is empty.
+pub fn fetch(dir: &Path, refspecs: &[&str]) -> Result<()> {
+    let repo = open(dir)?;
+    let mut remote = repo
+        .find_remote("origin")
+        .map_err(error(format!("fetch into {}", dir.display())))?;
+    let label = dir.display().to_string();
+    remote
+        .fetch(refspecs, Some(&mut fetch_options(&label)), None)
+        .map_err(error(format!("fetch into {}", dir.display())))
+}
+
+/// Commit `spec` resolves to in the repository at `dir`.
+pub fn resolve(dir: &Path, spec: &str) -> Option<Oid> {
+    let repo = Repository::open(dir).ok()?;
+    let object = repo.revparse_single(spec).ok()?;
+    Some(object.peel_to_commit().ok()?.id())
+}
+
+/// URL of `origin` of the repository at `dir`.
+pub fn origin_url(dir: &Path) -> Option<String> {
+    let repo = Repository::open(dir).ok()?;
+    let remote = repo.find_remote("origin").ok()?;
+    remote.url().map(str::to_string)
+}
+
+/// Commit checked out in the repository at `dir`.
+pub fn revision(dir: &Path) -> Option<String> {
+    resolve(dir, "HEAD").map(|x| x.to_string())
+}
+
+/// Branch checked out in the repository at `dir`, `None` if `HEAD` is detached.
+pub fn branch(dir: &Path) -> Option<String> {
+    let repo = Repository::open(dir).ok()?;
+    if repo.head_detached().ok()? {
+        return None;
+    }
+    let head = repo.find_reference("HEAD").ok()?;
+    let target = head.symbolic_target()?;
+    target.strip_prefix("refs/heads/").map(str::to_string)
+}
+
+/// Whether the checkout at `dir` has uncommitted changes to tracked files.
+pub fn is_dirty(dir: &Path) -> bool {
+    let Ok(repo) = Repository::open(dir) else {
+        return false;
+    };
+    let mut options = StatusOptions::new();
+    options.include_untracked(false).include_ignored(false);
+    repo.statuses(Some(&mut options))
+        .is_ok_and(|x| !x.is_empty())
+}
+
+/// Number of commits the current branch of the repository at `dir` is ahead and behind its
+/// upstream, `None` if it has none.
+pub fn ahead_behind(dir: &Path) -> Option<(u32, u32)> {
+    let repo = Repository::open(dir).ok()?;
+    let (local, upstream) = upstream(&repo)?;
+    let (ahead, behind) = repo.graph_ahead_behind(local, upstream).ok()?;
+    Some((ahead.try_into().ok()?, behind.try_into().ok()?))
+}
+
+/// Commits of the current branch of `repo` and of its upstream.
+fn upstream(repo: &Repository) -> Option<(Oid, Oid)> {
+    let name = branch(repo.workdir()?)?;
+    let local = repo.find_branch(&name, BranchType::Local).ok()?;
+    let upstream = local.upstream().ok()?;
+    Some((local.get().target()?, upstream.get().target()?))
+}
+
+/// Changed and untracked files in the repository at `dir`, in `git status --short` format.
+pub fn changes(dir: &Path) -> Vec<String> {
+    let Ok(repo) = Repository::open(dir) else {
+        return vec![];
+    };
+    let mut options = StatusOptions::new();
+    options.include_untracked(true).include_ignored(false);
+    let Ok(statuses) = repo.statuses(Some(&mut options)) else {
+        return vec![];
+    };
+    statuses
+        .iter()
+        .map(|x| {
+            let status = x.status();
+            let code = if status.is_wt_new() {
+                "??".to_string()
+            } else if status.is_conflicted() {
+                "UU".to_string()
+            } else {
+                let index = [
+                    (Status::INDEX_NEW, 'A'),
+                    (Status::INDEX_MODIFIED, 'M'),
+                    (Status::INDEX_DELETED, 'D'),
+                    (Status::INDEX_RENAMED, 'R'),
+                    (Status::INDEX_TYPECHANGE, 'T'),
+                ];
+                let worktree = [
+                    (Status::WT_MODIFIED, 'M'),
+                    (Status::WT_DELETED, 'D'),
+                    (Status::WT_RENAMED, 'R'),
+                    (Status::WT_TYPECHANGE, 'T'),
+                ];
+                let letter = |flags: &[(Status, char)]| {
+                    flags
+                        .iter()
+                        .find(|x| status.contains(x.0))
+                        .map_or(' ', |x| x.1)
+                };
+                format!("{}{}", letter(&index), letter(&worktree))
+            };
+            format!("{code} {}", x.path().unwrap_or("?"))
+        })
+        .collect()
+}
+
+/// Fetch `origin` and fast-forward the current branch of the repository at `dir` to its
+/// upstream, like `git pull --ff-only`.
+pub fn pull(dir: &Path) -> Result<()> {
+    fetch(dir, &[])?;
+    let repo = open(dir)?;
+    let operation = || format!("pull in {}", dir.display());
+    let Some((local, upstream)) = upstream(&repo) else {
+        return Err(Error::other(format!(
+            "{}: the current branch has no upstream",
+            dir.display()
+        )));
+    };
+    if local == upstream
+        || repo
+            .graph_descendant_of(local, upstream)
+            .map_err(error(operation()))?
+    {
+        return Ok(());
+    }
+    if !repo
+        .graph_descendant_of(upstream, local)
+        .map_err(error(operation()))?
+    {
+        return Err(Error::other(format!(
+            "{}: the current branch and its upstream have diverged, not possible to fast-forward",
+            dir.display()
+        )));
+    }
+    let commit = repo.find_commit(upstream).map_err(error(operation()))?;
+    repo.checkout_tree(commit.as_object(), Some(CheckoutBuilder::new().safe()))
+        .map_err(error(operation()))?;
+    repo.head()
+        .and_then(|mut x| x.set_target(upstream, "pull: fast-forward"))
+        .map_err(error(operation()))?;
+    Ok(())
+}
+
+/// Create a local branch `name` at `HEAD` of the repository at `dir`.
+pub fn create_branch(dir: &Path, name: &str) -> Result<()> {
+    let repo = open(dir)?;
+    let operation = || format!("create branch {name} in {}", dir.display());
+    let head = repo
+        .head()
+        .and_then(|x| x.peel_to_commit())
+        .map_err(error(operation()))?;
+    repo.branch(name, &head, false)
+        .map_err(error(operation()))?;
+    Ok(())
+}
+
+/// Check out `spec` in the repository at `dir`, detaching `HEAD`.
+pub fn checkout_detached(dir: &Path, spec: &str) -> Result<()> {
+    let repo = open(dir)?;
+    let operation = || format!("check out {spec} in {}", dir.display());
+    let commit = repo
+        .revparse_single(spec)
+        .and_then(|x| x.peel_to_commit())
+        .map_err(error(operation()))?;
+    repo.checkout_tree(commit.as_object(), Some(CheckoutBuilder::new().safe()))
+        .map_err(error(operation()))?;
+    repo.set_head_detached(commit.id())
+        .map_err(error(operation()))
+}
+
+/// Check out a branch, tag or commit in the repository at `dir`, like `git checkout`.
+///
+/// A branch only present in `origin` gets a local branch tracking it.
+pub fn checkout(dir: &Path, name: &str) -> Result<()> {
+    let repo = open(dir)?;
+    let operation = || format!("check out {name} in {}", dir.display());
+
+    if repo.find_branch(name, BranchType::Local).is_err() {
+        let Ok(remote) = repo.find_branch(&format!("origin/{name}"), BranchType::Remote) else {
+            return checkout_detached(dir, name);
+        };
+        let commit = remote.get().peel_to_commit().map_err(error(operation()))?;
+        let mut local = repo
+            .branch(name, &commit, false)
+            .map_err(error(operation()))?;
+        local
+            .set_upstream(Some(&format!("origin/{name}")))
+            .map_err(error(operation()))?;
+    }
+
+    let reference = format!("refs/heads/{name}");
+    let commit = repo
+        .find_reference(&reference)
+        .and_then(|x| x.peel_to_commit())
+        .map_err(error(operation()))?;
+    repo.checkout_tree(commit.as_object(), Some(CheckoutBuilder::new().safe()))
+        .map_err(error(operation()))?;
+    repo.set_head(&reference).map_err(error(operation()))
+}
+
+/// Apply `patch` to the working tree and index of the repository at `dir`.
+pub fn apply(dir: &Path, patch: &[u8]) -> Result<()> {
+    let repo = open(dir)?;
+    let operation = || format!("apply patch in {}", dir.display());
+    let diff = Diff::from_buffer(patch).map_err(error(operation()))?;
+    repo.apply(&diff, ApplyLocation::Both, None)
+        .map_err(error(operation()))
+}
+
+/// Diff of the working tree and index of the repository at `dir` against `HEAD`.
+pub fn diff(dir: &Path) -> Result<Vec<u8>> {
+    let repo = open(dir)?;
+    let operation = || format!("diff {}", dir.display());
+    let head = repo
+        .head()
+        .and_then(|x| x.peel_to_tree())
+        .map_err(error(operation()))?;
+    let diff = repo
+        .diff_tree_to_workdir_with_index(Some(&head), Some(DiffOptions::new().show_binary(true)))
+        .map_err(error(operation()))?;
+
+    let mut patch = vec![];
+    diff.print(DiffFormat::Patch, |_, _, line| {
+        if matches!(line.origin(), '+' | '-' | ' ') {
+            patch.push(line.origin() as u8);
+        }
+        patch.extend_from_slice(line.content());
+        true
+    })
+    .map_err(error(operation()))?;
+    Ok(patch)
+}
+
+/// Discard changes to tracked files in the repository at `dir`.
+pub fn reset_hard(dir: &Path) -> Result<()> {
+    let repo = open(dir)?;
+    let operation = || format!("reset {}", dir.display());
+    let head = repo
+        .head()
+        .and_then(|x| x.peel_to_commit())
+        .map_err(error(operation()))?;
+    repo.reset(head.as_object(), ResetType::Hard, None)
+        .map_err(error(operation()))
+}
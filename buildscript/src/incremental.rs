@@ -0,0 +1,135 @@
+//! Incremental builds and the artifact store.
+//!
+//! Targets declaring [inputs](crate::targets::TargetImplStatic::inputs) get a record in
+//! [STATE_DIR] after a successful build. It holds a hash of the inputs (and of the records of
+//! dependencies), paths and a hash of the produced artifacts and the commit every repository
+//! of the target was at.
+//!
+//! Building is skipped when both hashes match on the next build. Targets enabled only as a
+//! dependency reuse the last good artifact even if their inputs have changed since.
+
+use std::{collections::BTreeMap, fs, io, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha512};
+
+use crate::{
+    fs2, git,
+    targets::{Target, checkout_path},
+    util::{hash_files, hex},
+};
+
+/// Directory with build records of targets.
+pub const STATE_DIR: &str = ".cache/tools/buildscript/artifacts";
+
+/// Record of a successful build.
+#[derive(Serialize, Deserialize)]
+pub struct BuildState {
+    /// Hash of the inputs.
+    pub inputs: String,
+    /// Hash of the artifacts.
+    pub artifacts: String,
+    /// Paths to the artifacts (relative to the workspace root).
+    pub paths: Vec<PathBuf>,
+    /// Commit each repository of the target was at, by checkout directory.
+    pub revisions: BTreeMap<String, String>,
+}
+impl BuildState {
+    /// Path to the record of `target`.
+    fn path(target: Target) -> PathBuf {
+        PathBuf::from(STATE_DIR).join(format!("{}.toml", target.name()))
+    }
+
+    /// Load the record of `target`.
+    pub fn load(target: Target) -> Option<Self> {
+        let data = fs::read_to_string(Self::path(target)).ok()?;
+        toml::from_str(&data).ok()
+    }
+
+    /// Hash the inputs of `target`.
+    ///
+    /// Returns `None` if the target has no inputs.
+    pub fn inputs(target: Target, native_image: bool) -> io::Result<Option<String>> {
+        if target.inputs().is_empty() {
+            return Ok(None);
+        }
+
+        let mut hash = Sha512::new();
+        hash.update(hash_files(
+            target.inputs().iter().map(|x| checkout_path(x)),
+        )?);
+        hash.update([native_image as u8]);
+        // A target is rebuilt when any of its dependencies changes.
+        for dep in target.transitive_dependencies() {
+            if let Some(state) = Self::load(dep) {
+                hash.update(dep.name());
+                hash.update(state.inputs);
+            }
+        }
+        Ok(Some(hex(&hash.finalize())))
+    }
+
+    /// Whether the artifacts of `target` are unchanged since the last successful build.
+    pub fn is_intact(&self, target: Target) -> bool {
+        Self::artifacts(target).is_some_and(|x| x == self.artifacts)
+    }
+
+    /// Whether `target` was built from `inputs` and its artifacts are unchanged since.
+    pub fn is_up_to_date(target: Target, inputs: &str) -> bool {
+        Self::load(target).is_some_and(|state| state.inputs == inputs && state.is_intact(target))
+    }
+
+    /// Hash the artifacts of `target`.
+    ///
+    /// Returns `None` if any of the artifacts is missing or can't be read.
+    fn artifacts(target: Target) -> Option<String> {
+        if target
+            .artifacts()
+            .iter()
+            .any(|x| fs::symlink_metadata(x).is_err())
+        {
+            return None;
+        }
+        Some(hex(&hash_files(target.artifacts()).ok()?))
+    }
+
+    /// Record a successful build of `target` with the given inputs.
+    pub fn record(target: Target, inputs: String) -> io::Result<()> {
+        let Some(artifacts) = Self::artifacts(target) else {
+            return Self::forget(target);
+        };
+
+        let state = Self {
+            inputs,
+            artifacts,
+            paths: target.artifacts().iter().map(PathBuf::from).collect(),
+            revisions: target
+                .repos()
+                .iter()
+                .filter_map(|x| Some((x.dir.to_string(), git::revision(x.path())?)))
+                .collect(),
+        };
+
+        fs2::create_dir_all(STATE_DIR)?;
+        fs2::write(
+            Self::path(target),
+            toml::to_string(&state).map_err(io::Error::other)?,
+        )
+    }
+
+    /// Record `target` as initialized from a prebuilt artifact.
+    pub fn record_prebuilt(target: Target) -> io::Result<()> {
+        match Self::artifacts(target) {
+            Some(artifacts) => Self::record(target, format!("prebuilt:{artifacts}")),
+            None => Self::forget(target),
+        }
+    }
+
+    /// Remove the record of `target`.
+    pub fn forget(target: Target) -> io::Result<()> {
+        match fs2::remove_file(Self::path(target)) {
+            Err(why) if why.kind() != io::ErrorKind::NotFound => Err(why),
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,306 @@
+//! Workspace lockfile.
+//!
+//! `workspace.lock` in the workspace root records the remote and the commit of every target
+//! checkout, so everyone working on the workspace can have the same trees, and the SHA-256 of
+//! every downloaded tool:
+//!
+//! ```toml
+//! [repos.mindustry]
+//! remote = "https://github.com/Darkdustry-Coders/MindustryServer"
+//! commit = "0123456789abcdef0123456789abcdef01234567"
+//!
+//! [downloads]
+//! "https://busybox.net/downloads/binaries/1.35.0-x86_64-linux-musl/busybox" = "6e12..."
+//! ```
+//!
+//! `./b lock` records the commits currently checked out, `./b sync --locked` checks them out
+//! again and builds warn about checkouts that have drifted from the lock. Hashes of downloads
+//! are only recorded by builds with `--update-lock` and checked on every download, a URL
+//! without a hash fails the download otherwise.
+
+use std::{
+    collections::BTreeMap,
+    io,
+    path::Path,
+    sync::{Mutex, OnceLock, PoisonError},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    args::{LockArgs, SyncArgs},
+    error::{Error, IoContext, Result},
+    fs2, git, patches,
+    targets::Repo,
+    util::sha256_file,
+};
+
+/// Name of the lockfile.
+pub const LOCK_FILE: &str = "workspace.lock";
+
+/// Comment at the top of the lockfile.
+const HEADER: &str = "# Commits of target checkouts and hashes of downloads. Updated by `./b lock`\n\
+     # and `./b build --update-lock`, do not edit by hand.\n\n";
+
+/// Held while the lockfile is updated, as tools may be downloaded by several targets at once.
+static UPDATE: Mutex<()> = Mutex::new(());
+
+/// Whether hashes of downloads missing from the lock are recorded, see [set_update_downloads].
+static UPDATE_DOWNLOADS: OnceLock<bool> = OnceLock::new();
+
+/// Record hashes of downloads that are not in the lock instead of failing on them.
+pub fn set_update_downloads(update: bool) {
+    _ = UPDATE_DOWNLOADS.set(update);
+}
+
+/// Whether hashes of downloads missing from the lock are recorded.
+pub fn updates_downloads() -> bool {
+    UPDATE_DOWNLOADS.get().copied().unwrap_or(false)
+}
+
+/// Contents of `workspace.lock`.
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Lock {
+    /// Locked repositories by checkout directory.
+    pub repos: BTreeMap<String, LockedRepo>,
+    /// SHA-256 of downloaded files by URL.
+    pub downloads: BTreeMap<String, String>,
+}
+
+/// Locked state of a checkout.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LockedRepo {
+    /// URL of the repository.
+    pub remote: String,
+    /// Commit the checkout is locked to.
+    pub commit: String,
+}
+
+impl Lock {
+    /// Load the lockfile.
+    ///
+    /// Returns an empty lock if the file does not exist.
+    pub fn load() -> Result<Self> {
+        let source = match fs2::read_to_string(LOCK_FILE) {
+            Ok(x) => x,
+            Err(why) if why.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(why) => return Err(why.into()),
+        };
+        toml::from_str(&source)
+            .map_err(|why| Error::other(format!("{LOCK_FILE}: {}", why.message())))
+    }
+
+    /// Write the lockfile.
+    pub fn save(&self) -> Result<()> {
+        let data = toml::to_string(self).map_err(|why| Error::other(why.to_string()))?;
+        fs2::write(LOCK_FILE, format!("{HEADER}{data}")).at(LOCK_FILE)
+    }
+}
+
+/// Commit `repo` is checked out at, if it differs from the lock.
+///
+/// Returns `None` if the checkout is missing, not locked or matches the lock.
+pub fn drift(lock: &Lock, repo: &Repo) -> Option<String> {
+    let locked = lock.repos.get(repo.dir)?;
+    let head = git::revision(repo.path())?;
+    (head != locked.commit).then_some(head)
+}
+
+/// Warn about checkouts in `repos` that have drifted from the lock.
+pub fn warn_drift<'a>(repos: impl IntoIterator<Item = &'a Repo>) {
+    if !Path::new(LOCK_FILE).exists() {
+        return;
+    }
+    let lock = match Lock::load() {
+        Ok(x) => x,
+        Err(why) => {
+            eprintln!("warning: {}", why.error());
+            return;
+        }
+    };
+
+    let mut drifted = false;
+    for repo in repos {
+        if let Some(head) = drift(&lock, repo) {
+            drifted = true;
+            eprintln!(
+                "warning: {}: checkout is at {}, but {LOCK_FILE} has {}",
+                repo.dir,
+                git::short(&head),
+                git::short(&lock.repos[repo.dir].commit),
+            );
+        }
+    }
+    if drifted {
+        eprintln!(
+            "note: run `./b sync --locked` to check out the locked commits or `./b lock` to update the lock"
+        );
+    }
+}
+
+/// Check the SHA-256 of the file at `path` downloaded from `url` against the lock.
+///
+/// A built-in `sha256` (see [`Tool::sha256`](crate::tools::Tool::sha256)) is checked instead
+/// and the lock is left alone. The hash of a URL that is not in the lock yet is only recorded with `--update-lock`, the
+/// download fails otherwise.
+pub fn verify_download(url: &str, path: &Path, sha256: Option<&str>) -> Result<()> {
+    let hash = sha256_file(path)?;
+    if let Some(x) = sha256 {
+        return if x == hash {
+            Ok(())
+        } else {
+            Err(Error::other(format!(
+                "checksum mismatch for {url}: expected sha256 {x}, downloaded file has {hash}, the file was removed"
+            )))
+        };
+    }
+
+    let _guard = UPDATE.lock().unwrap_or_else(PoisonError::into_inner);
+    let mut lock = Lock::load()?;
+    match lock.downloads.get(url) {
+        Some(x) if *x == hash => Ok(()),
+        Some(x) => Err(Error::other(format!(
+            "checksum mismatch for {url}: {LOCK_FILE} has sha256 {x}, downloaded file has {hash}, the file was removed"
+        ))),
+        None if !updates_downloads() => Err(Error::other(format!(
+            "{url} has no sha256 in {LOCK_FILE}, the file was removed; check where it comes from and run the build with `--update-lock` to record {hash}"
+        ))),
+        None => {
+            eprintln!("recorded sha256 of {url} in {LOCK_FILE}");
+            lock.downloads.insert(url.to_string(), hash);
+            lock.save()
+        }
+    }
+}
+
+/// Implementation of `./b lock`.
+///
+/// Records the commits of present checkouts of the given targets. Entries of missing
+/// checkouts are kept and entries of repositories no target has anymore are removed.
+pub fn lock(args: &LockArgs) -> Result<()> {
+    let mut lock = Lock::load()?;
+
+    let known: Vec<&str> = git::repos(&[]).iter().map(|x| x.dir).collect();
+    let mut changed = false;
+    lock.repos.retain(|dir, _| {
+        let keep = known.contains(&dir.as_str());
+        if !keep {
+            eprintln!("{dir}: removed");
+            changed = true;
+        }
+        keep
+    });
+
+    for repo in git::repos(&args.targets) {
+        if !git::is_checkout(repo.path()) {
+            continue;
+        }
+        let Some(commit) = git::revision(repo.path()) else {
+            eprintln!(
+                "warning: {}: could not read the checked out commit",
+                repo.dir
+            );
+            continue;
+        };
+        if git::is_dirty(repo.path()) && !patches::is_applied(repo) {
+            eprintln!(
+                "warning: {}: uncommitted changes are not recorded in the lock",
+                repo.dir
+            );
+        }
+
+        let locked = LockedRepo {
+            remote: args.remotes.remote(repo),
+            commit,
+        };
+        match lock.repos.get(repo.dir) {
+            Some(x) if *x == locked => continue,
+            Some(x) if x.commit == locked.commit => {
+                eprintln!("{}: remote {} -> {}", repo.dir, x.remote, locked.remote)
+            }
+            Some(x) => eprintln!(
+                "{}: {} -> {}",
+                repo.dir,
+                git::short(&x.commit),
+                git::short(&locked.commit)
+            ),
+            None => eprintln!("{}: locked at {}", repo.dir, git::short(&locked.commit)),
+        }
+        changed = true;
+        lock.repos.insert(repo.dir.to_string(), locked);
+    }
+
+    if !changed {
+        eprintln!("{LOCK_FILE} is up to date");
+    }
+    lock.save()
+}
+
+/// Implementation of `./b sync`.
+///
+/// Clones missing checkouts of the given targets (every locked checkout if no target is
+/// given). With `--locked`, checkouts are also moved to the locked commits.
+pub fn sync(args: &SyncArgs) -> Result<()> {
+    let lock = Lock::load()?;
+
+    let repos: Vec<&Repo> = if args.targets.is_empty() {
+        git::repos(&[])
+            .into_iter()
+            .filter(|x| lock.repos.contains_key(x.dir))
+            .collect()
+    } else {
+        git::repos(&args.targets)
+    };
+    if repos.is_empty() {
+        eprintln!("nothing to sync, {LOCK_FILE} is empty");
+        return Ok(());
+    }
+
+    for repo in repos {
+        let locked = lock.repos.get(repo.dir);
+        if args.locked && locked.is_none() {
+            return Err(Error::other(format!(
+                "{}: not in {LOCK_FILE}, run `./b lock` first",
+                repo.dir
+            )));
+        }
+
+        let remote = args.remotes.remote(repo);
+        if let Some(locked) = locked.filter(|x| args.locked && x.remote != remote) {
+            return Err(Error::other(format!(
+                "{}: {LOCK_FILE} has remote {}, but {remote} is configured; set it in `[remotes]` or run `./b lock` to lock the configured one",
+                repo.dir, locked.remote
+            )));
+        }
+
+        if !repo.path().exists() {
+            git::clone(repo, &args.remotes)?;
+        }
+        let Some(locked) = locked.filter(|_| args.locked) else {
+            patches::apply(repo)?;
+            continue;
+        };
+
+        if git::revision(repo.path()).as_ref() == Some(&locked.commit) {
+            eprintln!("{}: at {}", repo.dir, git::short(&locked.commit));
+            patches::apply(repo)?;
+            continue;
+        }
+        patches::revert(repo)?;
+        if git::is_dirty(repo.path()) {
+            return Err(Error::other(format!(
+                "{}: has uncommitted changes, commit or stash them before syncing",
+                repo.dir
+            )));
+        }
+        if !git::has_commit(repo.path(), &locked.commit) {
+            git::fetch(repo.path(), &locked.commit)?;
+        }
+        git::checkout_detached(repo.path(), &locked.commit)?;
+        eprintln!("{}: checked out {}", repo.dir, git::short(&locked.commit));
+        patches::apply(repo)?;
+    }
+
+    Ok(())
+}
@@ -1,22 +1,392 @@
 mod args;
+mod clean;
+mod config;
+mod error;
+mod extract;
 mod fs2;
+mod git;
+mod incremental;
+mod lock;
+mod patches;
+mod platform;
+mod prebuilt;
+mod report;
 mod syncfs;
 mod targets;
+mod tools;
 mod util;
 
 use std::{
     borrow::Cow,
-    fs,
-    path::PathBuf,
+    path::{Path, PathBuf},
     process::{Command, Stdio, exit},
     str::FromStr,
 };
 
-use args::{Args, EnvTy};
-use targets::{BuildParams, InitParams, RunParams, TARGET_NAMES, Target, TargetList, Targets};
+use args::{Args, BuildArgs, EnvTy};
+use error::Error;
+use git::Remotes;
+use incremental::BuildState;
+use report::Report;
+use targets::{
+    BuildParams, InitParams, RunParams, TARGET_NAMES, Target, TargetList, Targets, checkout_path,
+};
 use util::CURRENT_DIR;
 
-use crate::util::{current_dir, strip_extras, write_if_diff};
+use crate::util::{current_dir, find_executable, is_executable, strip_extras, write_if_diff};
+
+/// Print the state of every target in the workspace.
+fn status(remotes: &Remotes) {
+    let lock = lock::Lock::load().unwrap_or_else(|why| {
+        eprintln!("warning: {}", why.error());
+        Default::default()
+    });
+
+    for target in Target::ALL {
+        let name = target.name();
+        if target.repos().is_empty() {
+            let installed = tools::TOOLS
+                .iter()
+                .find(|x| x.name == name)
+                .and_then(|x| x.record());
+            let state = if let Some(record) = installed {
+                format!("{} installed in .cache/tools", record.version)
+            } else if Path::new(".cache/tools").join(name).exists() {
+                "installed in .cache/tools".to_string()
+            } else {
+                "not installed locally".to_string()
+            };
+            eprintln!("{name:<24} tool    {state}");
+            continue;
+        }
+
+        for repo in target.repos() {
+            let state = if !repo.path().is_dir() {
+                "missing".to_string()
+            } else if let Some(head) = lock::drift(&lock, repo) {
+                format!(
+                    "present, at {} instead of locked {}",
+                    git::short(&head),
+                    git::short(&lock.repos[repo.dir].commit),
+                )
+            } else if lock.repos.contains_key(repo.dir) {
+                "present, locked".to_string()
+            } else {
+                "present".to_string()
+            };
+            let state = if repo.is_external() {
+                format!("{state} ({})", repo.path().display())
+            } else {
+                state
+            };
+            eprintln!("{name:<24} source  {:<24} {state}", repo.dir);
+            eprintln!(
+                "{name:<24} remote  {:<24} {}",
+                repo.dir,
+                remotes.remote(repo)
+            );
+        }
+
+        if let Some(state) = BuildState::load(*target) {
+            let intact = if state.is_intact(*target) {
+                "intact"
+            } else {
+                "modified or missing"
+            };
+            for path in &state.paths {
+                eprintln!("{name:<24} output  {:<24} {intact}", path.display());
+            }
+            for (dir, rev) in &state.revisions {
+                eprintln!("{name:<24} built   {dir:<24} {}", &rev[..rev.len().min(12)]);
+            }
+        }
+    }
+}
+
+/// Check which tools are available on the host.
+fn doctor(env: EnvTy) {
+    let mut missing = false;
+
+    for (tool, purpose) in [
+        ("git", "cloning repositories"),
+        ("cargo", "building rust targets"),
+        ("javac", "building plugins"),
+        ("mprocs", "running targets"),
+        ("surreal", "running SurrealDB"),
+        ("rabbitmq-server", "running RabbitMQ"),
+        ("xargs", "coreutils"),
+    ] {
+        let found = if tool == "javac" {
+            std::env::var_os("JAVA_HOME")
+                .map(|x| PathBuf::from(x).join("bin/javac"))
+                .filter(|x| is_executable(x))
+                .or_else(|| find_executable(tool))
+        } else {
+            find_executable(tool)
+        };
+
+        match found {
+            Some(path) => eprintln!("[ ok ] {tool:<16} {}", path.display()),
+            None if tool == "git" => eprintln!("[ ok ] {tool:<16} not found, using built-in git"),
+            None => {
+                missing = true;
+                eprintln!("[ -- ] {tool:<16} not found (needed for {purpose})");
+            }
+        }
+    }
+
+    if missing {
+        eprintln!();
+        if env == EnvTy::Host {
+            eprintln!("Missing tools can be installed into .cache/tools with --autoinstall.");
+        } else {
+            eprintln!("Missing tools will be installed into .cache/tools.");
+        }
+    }
+
+    for tool in tools::TOOLS {
+        if tool.url.contains("{platform}") && tool.asset().is_none() {
+            eprintln!(
+                "note: {} has no release for {}, set `url` in `[tools.{}]` of {} to install it",
+                tool.name,
+                platform::host_name(),
+                tool.name,
+                config::CONFIG_FILE,
+            );
+        }
+    }
+}
+
+/// Print the dependency graph of `targets` (or of every target).
+fn graph(targets: &[String], dot: bool) {
+    let mut list: Vec<Target> = if targets.is_empty() || targets.iter().any(|x| x == "all") {
+        Target::ALL.to_vec()
+    } else {
+        targets
+            .iter()
+            .map(|x| Target::from_str(x).unwrap())
+            .collect()
+    };
+
+    let mut i = 0;
+    while i < list.len() {
+        for dep in list[i].dependencies() {
+            if !list.contains(&dep) {
+                list.push(dep);
+            }
+        }
+        i += 1;
+    }
+
+    if dot {
+        println!("digraph targets {{");
+        for target in &list {
+            println!("    {:?};", target.name());
+            for dep in target.dependencies() {
+                println!("    {:?} -> {:?};", target.name(), dep.name());
+            }
+        }
+        println!("}}");
+    } else {
+        for target in &list {
+            let deps: Vec<_> = target.dependencies().iter().map(|x| x.name()).collect();
+            if deps.is_empty() {
+                println!("{}", target.name());
+            } else {
+                println!("{} -> {}", target.name(), deps.join(", "));
+            }
+        }
+    }
+}
+
+/// `includeBuild` line of `settings.gradle` for the checkout `dir`.
+///
+/// Checkouts outside of the workspace keep `dir` as the build name, so task paths like
+/// `:coreplugin:build` work wherever the checkout is.
+fn include_build(dir: &str) -> String {
+    let path = checkout_path(dir);
+    if path == Path::new(dir) {
+        format!("includeBuild '{dir}'")
+    } else {
+        format!("includeBuild('{}') {{ name = '{dir}' }}", path.display())
+    }
+}
+
+/// Build the requested targets and run them if requested.
+///
+/// The `report` is finished by the caller with the result, once the targets stopped running.
+fn build_and_run(build: &BuildArgs, env: EnvTy, report: &mut Report) -> error::Result<()> {
+    fs2::create_dir_all(".bin")?;
+
+    let mut targets = Targets::default();
+    let mut recipe = TargetList::default();
+
+    let mut run = build.run;
+
+    'b: for target in &build.targets {
+        'a: {
+            match target.as_str() {
+                "all" => TARGET_NAMES
+                    .iter()
+                    .map(|x| Target::from_str(x).unwrap())
+                    .filter(|x| !x.flags().deprecated)
+                    .for_each(|target| recipe.set_build(target)),
+                "run" => run = true,
+                _ => break 'a,
+            }
+            continue 'b;
+        }
+
+        let target = Target::from_str(target.as_str())
+            .map_err(|_| Error::other(format!("no target {target:?} defined")))?;
+        recipe.set_build(target);
+    }
+
+    if run {
+        recipe.set_build(Target::MProcs);
+    }
+
+    let mut params = InitParams::new(build);
+
+    targets.init_all(env, &mut recipe, &mut params, report)?;
+    // Checkouts at an explicitly requested ref are expected to differ from the lock.
+    lock::warn_drift(
+        Target::ALL
+            .iter()
+            .filter(|x| targets.target(**x).is_some())
+            .flat_map(|x| x.repos())
+            .filter(|x| params.git_ref(x).is_none()),
+    );
+    write_if_diff(
+        "buildscript/assets/shared.settings.gradle",
+        fs2::read_to_string("buildscript/assets/shared.settings.gradle.in")?
+            .replace(
+                "PKGS",
+                &strip_extras(
+                    r#"
+                    library("mindustry-core", "anuken.mindustry", "core").version("release")
+                    library("mindustry-server", "anuken.mindustry", "server").version("release")
+                    library("arc-core", "anuken.arc", "arc-core").version("1.0")
+                    "#,
+                    12,
+                ),
+            )
+            .replace(
+                "BUNDLES",
+                &strip_extras(
+                    r#"
+                    bundle("mindustry", ["mindustry-core", "mindustry-server", "arc-core"])
+                    "#,
+                    12,
+                ),
+            ),
+    )?;
+    write_if_diff(
+        ".cache/tools/buildscript/shared.repos.gradle",
+        // TODO: Windows.
+        fs2::read_to_string("buildscript/assets/shared.repos.gradle.in")?
+            .replace("WORKSPACE_PATH", current_dir().to_str().unwrap()),
+    )?;
+    write_if_diff(
+        "Cargo.toml",
+        include_str!("../assets/Cargo.toml.in").replace("MEMBERS", &{
+            let mut s = "\"buildscript\"".to_string();
+            for x in &params.rust_workspace_members {
+                s += ", \"";
+                s += &checkout_path(x).display().to_string();
+                s += "\"";
+            }
+            s
+        }),
+    )?;
+    write_if_diff("settings.gradle", {
+        let mut s = String::new();
+        s += include_str!("../assets/settings.gradle.in");
+        s += "def inWorkspace = System.env['MINDURKA_WORKSPACE'] != null";
+        for x in &params.java_workspace_members {
+            s += &format!("\n{}", include_build(x));
+        }
+        for x in &params.java_masked_members {
+            s += &format!("\nif (!inWorkspace) {}", include_build(x));
+        }
+        s
+    })?;
+
+    let mut params = BuildParams::new(params, build);
+
+    if env != EnvTy::Isolate {
+        params.path.extend(
+            std::env::var("PATH")
+                .unwrap_or_default()
+                .split(if cfg!(unix) { ':' } else { ';' })
+                .map(PathBuf::from),
+        );
+    }
+
+    targets.build_all(&recipe, &mut params, build.jobs, report)?;
+
+    if run {
+        let mut params = RunParams::new(params, build);
+
+        // if env == EnvTy::Isolate {
+        //     if cfg!(unix) {
+        //         params.path.push(PathBuf::from("/usr/bin"));
+        //     } else if cfg!(target_os = "windows") {
+        //         let sysroot = std::env::var("SYSTEMROOT").unwrap();
+        //         params
+        //             .path
+        //             .push(PathBuf::from(format!("{sysroot}\\System32")));
+        //     }
+        // } else {
+        //     params.path.extend(
+        //         std::env::var("PATH")
+        //             .unwrap()
+        //             .split(if cfg!(unix) { ':' } else { ';' })
+        //             .map(PathBuf::from),
+        //     );
+        // }
+
+        params.run.restore(".run-save", "");
+        targets.run_init_all(&mut params, report)?;
+
+        if let Some(rabbitmq) = targets.rabbitmq.as_ref()
+            && let Some(surreal) = targets.surrealdb.as_ref()
+        {
+            params.run.write(
+                "sharedConfig.toml",
+                format!(
+                    "serverIp = {:?}\nrabbitMqUrl = {:?}\nsurrealDbUrl = {:?}",
+                    if build.server_ip.is_empty() {
+                        "127.0.0.1"
+                    } else {
+                        build.server_ip.as_str()
+                    },
+                    if build.rabbitmq_url.is_empty() {
+                        Cow::Owned(rabbitmq.url())
+                    } else {
+                        Cow::Borrowed(build.rabbitmq_url.as_str())
+                    },
+                    if build.surrealdb_url.is_empty() {
+                        Cow::Owned(surreal.url())
+                    } else {
+                        Cow::Borrowed(build.surrealdb_url.as_str())
+                    },
+                ),
+            );
+        }
+
+        params.run.finalize()?;
+        params.run.clear();
+
+        targets.run_all(&mut params)?;
+
+        if !targets.mprocs.as_mut().unwrap().wait() {
+            return Err(Error::other("mprocs exited with a non-zero code"));
+        }
+    }
+
+    Ok(())
+}
 
 fn main() {
     unsafe {
@@ -25,12 +395,26 @@ fn main() {
 
     let args = args::args();
 
-    if match &args {
-        Args::Help => true,
-        Args::Build { build, .. } => build.targets.is_empty(),
+    // Errors in the config are already reported by commands using it.
+    if let Ok(config) = config::Config::load() {
+        targets::set_checkout_paths(&config.path);
+    }
+    if let Args::Build { build, .. } = &args {
+        util::set_download_sources(build.download_mirrors.clone(), build.proxy.as_deref());
+        lock::set_update_downloads(build.update_lock);
+    }
+    util::set_offline(match &args {
+        Args::Build { build, .. } => build.offline,
+        Args::Sync { sync, .. } => sync.offline,
         _ => false,
-    } {
-        args::print_help();
+    });
+    git::set_builtin(args.env_ty() == EnvTy::Isolate || find_executable("git").is_none());
+
+    if let Args::Build { build, .. } = &args
+        && build.targets.is_empty()
+        && !build.run
+    {
+        args::print_help(Some("build"));
         exit(1);
     }
 
@@ -40,7 +424,43 @@ fn main() {
     };
 
     match args {
-        Args::Help => unreachable!(),
+        Args::Help(command) => {
+            args::print_help(command);
+            exit(0);
+        }
+        Args::Clean { clean, .. } => {
+            if let Err(why) = clean::clean(&clean) {
+                error::report(&why.into());
+                exit(1);
+            }
+        }
+        Args::Status { remotes, .. } => status(&remotes),
+        Args::Doctor { env } => doctor(env),
+        Args::Graph { targets, dot } => graph(&targets, dot),
+        Args::Lock { lock } => {
+            if let Err(why) = lock::lock(&lock) {
+                error::report(&why);
+                exit(1);
+            }
+        }
+        Args::Git { git } => {
+            if let Err(why) = git::git_command(&git) {
+                error::report(&why);
+                exit(1);
+            }
+        }
+        Args::Patches { patches } => {
+            if let Err(why) = patches::refresh(&patches) {
+                error::report(&why);
+                exit(1);
+            }
+        }
+        Args::Sync { sync, .. } => {
+            if let Err(why) = lock::sync(&sync) {
+                error::report(&why);
+                exit(1);
+            }
+        }
         Args::Env { mut command, .. } => {
             if command.is_empty() {
                 #[cfg(unix)]
@@ -68,179 +488,12 @@ fn main() {
             }
         }
         Args::Build { build, env } => {
-            fs::remove_dir_all(".build").ok();
-            fs::remove_dir_all(".bin").ok();
-            fs::create_dir_all(".bin").unwrap();
-
-            let mut targets = Targets::default();
-            let mut recipe = TargetList::default();
-
-            let mut run = false;
-
-            'b: for target in &build.targets {
-                'a: {
-                    match target.as_str() {
-                        "all" => TARGET_NAMES
-                            .iter()
-                            .map(|x| Target::from_str(x).unwrap())
-                            .filter(|x| !x.flags().deprecated)
-                            .for_each(|target| recipe.set_build(target)),
-                        "run" => run = true,
-                        _ => break 'a,
-                    }
-                    continue 'b;
-                }
-
-                let target = match Target::from_str(target.as_str()) {
-                    Ok(x) => x,
-                    Err(_) => {
-                        eprintln!("no target {target:?} defined");
-                        exit(1);
-                    }
-                };
-                recipe.set_build(target);
-            }
-
-            if run {
-                recipe.set_build(Target::MProcs);
-            }
-
-            let mut params = InitParams::new(&build);
-
-            targets.init_all(env, &mut recipe, &mut params);
-            write_if_diff(
-                "buildscript/assets/shared.settings.gradle",
-                fs::read_to_string("buildscript/assets/shared.settings.gradle.in")
-                    .unwrap()
-                    .replace(
-                        "PKGS",
-                        &strip_extras(
-                            r#"
-                            library("mindustry-core", "anuken.mindustry", "core").version("release")
-                            library("mindustry-server", "anuken.mindustry", "server").version("release")
-                            library("arc-core", "anuken.arc", "arc-core").version("1.0")
-                            "#,
-                            12,
-                        ),
-                    )
-                    .replace(
-                        "BUNDLES",
-                        &strip_extras(
-                            r#"
-                            bundle("mindustry", ["mindustry-core", "mindustry-server", "arc-core"])
-                            "#,
-                            12,
-                        ),
-                    ),
-            )
-            .unwrap();
-            write_if_diff(
-                ".cache/tools/buildscript/shared.repos.gradle",
-                // TODO: Windows.
-                fs::read_to_string("buildscript/assets/shared.repos.gradle.in")
-                    .unwrap()
-                    .replace("WORKSPACE_PATH", current_dir().to_str().unwrap()),
-            )
-            .unwrap();
-            write_if_diff(
-                "Cargo.toml",
-                include_str!("../assets/Cargo.toml.in").replace("MEMBERS", &{
-                    let mut s = "\"buildscript\"".to_string();
-                    for x in &params.rust_workspace_members {
-                        s += ", \"";
-                        s += x.as_str();
-                        s += "\"";
-                    }
-                    s
-                }),
-            )
-            .unwrap();
-            write_if_diff("settings.gradle", {
-                let mut s = String::new();
-                s += include_str!("../assets/settings.gradle.in");
-                s += "def inWorkspace = System.env['MINDURKA_WORKSPACE'] != null";
-                for x in &params.java_workspace_members {
-                    s += &format!("\nincludeBuild '{x}'");
-                }
-                for x in &params.java_masked_members {
-                    s += &format!("\nif (!inWorkspace) includeBuild '{x}'");
-                }
-                s
-            })
-            .unwrap();
-
-            let mut params = BuildParams::new(params, &build);
-
-            if env != EnvTy::Isolate {
-                params.path.extend(
-                    std::env::var("PATH")
-                        .unwrap()
-                        .split(if cfg!(unix) { ':' } else { ';' })
-                        .map(PathBuf::from),
-                );
-            }
-
-            targets.build_all(&mut params);
-
-            if run {
-                let mut params = RunParams::new(params, &build);
-
-                // if env == EnvTy::Isolate {
-                //     if cfg!(unix) {
-                //         params.path.push(PathBuf::from("/usr/bin"));
-                //     } else if cfg!(target_os = "windows") {
-                //         let sysroot = std::env::var("SYSTEMROOT").unwrap();
-                //         params
-                //             .path
-                //             .push(PathBuf::from(format!("{sysroot}\\System32")));
-                //     }
-                // } else {
-                //     params.path.extend(
-                //         std::env::var("PATH")
-                //             .unwrap()
-                //             .split(if cfg!(unix) { ':' } else { ';' })
-                //             .map(PathBuf::from),
-                //     );
-                // }
-
-                params.run.restore(".run-save", "");
-                targets.run_init_all(&mut params);
-
-                if let Some(rabbitmq) = targets.rabbitmq.as_ref()
-                    && let Some(surreal) = targets.surrealdb.as_ref()
-                {
-                    params.run.write(
-                        "sharedConfig.toml",
-                        format!(
-                            "serverIp = {:?}\nrabbitMqUrl = {:?}\nsurrealDbUrl = {:?}",
-                            if build.server_ip.is_empty() {
-                                "127.0.0.1"
-                            } else {
-                                build.server_ip.as_str()
-                            },
-                            if build.rabbitmq_url.is_empty() {
-                                Cow::Owned(rabbitmq.url())
-                            } else {
-                                Cow::Borrowed(build.rabbitmq_url.as_str())
-                            },
-                            if build.surrealdb_url.is_empty() {
-                                Cow::Owned(surreal.url())
-                            } else {
-                                Cow::Borrowed(build.surrealdb_url.as_str())
-                            },
-                        ),
-                    );
-                }
-
-                params.run.finalize().unwrap();
-                params.run.clear();
-
-                targets.run_all(&mut params);
-
-                if !targets.mprocs.as_mut().unwrap().wait() {
-                    eprintln!("mprocs exited with a non-zero code");
-                    exit(1);
-                }
+            let mut report = Report::new(&build.targets);
+            let result = build_and_run(&build, env, &mut report);
+            report.finish(result.as_ref().err());
+            if let Err(why) = result {
+                error::report(&why);
+                exit(1);
             }
         }
     }
@@ -0,0 +1,322 @@
+//! Local patches on top of target checkouts.
+//!
+//! Patch files in `patches/NAME/*.patch`, where `NAME` is a target name or a checkout
+//! directory, are applied in file name order after the checkout is cloned or checked out and
+//! before it is built. They are applied to the working tree and the index, so they show up as
+//! uncommitted changes, and `./b patches refresh NAME` writes those changes back into the
+//! patch files.
+//!
+//! What was applied is recorded in `.git/workspace-patches` of the checkout, so patches are
+//! not applied twice and changed patches are reapplied.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha512};
+
+use crate::{
+    args::PatchesArgs,
+    error::{Error, IoContext, Result},
+    fs2, git,
+    targets::{Repo, Target},
+    util::hex,
+};
+
+/// Directory with patches of every checkout.
+pub const PATCHES_DIR: &str = "patches";
+
+/// File in the git directory of a checkout recording the applied patches.
+const STATE_FILE: &str = "workspace-patches";
+
+/// Name of the patch file created by `./b patches refresh` if a checkout has none yet.
+const DEFAULT_PATCH: &str = "local.patch";
+
+/// Patches applied to a checkout.
+#[derive(Serialize, Deserialize)]
+struct Applied {
+    /// Hash of the patch files.
+    patches: String,
+    /// Hash of the diff of the checkout right after applying them.
+    diff: String,
+}
+
+/// Directory with patches of `repo`, if it has one.
+fn patch_dir(repo: &Repo) -> Option<PathBuf> {
+    let dirs: HashMap<String, PathBuf> = fs2::read_dir(PATCHES_DIR)
+        .ok()?
+        .filter_map(|x| x.ok())
+        .filter(|x| x.path().is_dir())
+        .map(|x| (x.file_name().to_string_lossy().into_owned(), x.path()))
+        .collect();
+    git::for_repo(&dirs, repo).cloned()
+}
+
+/// Patch files in `dir`, in the order they are applied.
+fn patch_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    for entry in fs2::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|x| x == "patch") {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Hash the names and contents of patch files.
+fn hash_patches(files: &[PathBuf]) -> Result<String> {
+    let mut hash = Sha512::new();
+    for file in files {
+        hash.update(file.file_name().unwrap_or_default().as_encoded_bytes());
+        hash.update([0]);
+        hash.update(fs2::read(file)?);
+        hash.update([0]);
+    }
+    Ok(hex(&hash.finalize()))
+}
+
+/// Hash the diff of the checkout of `repo`.
+fn hash_diff(repo: &Repo) -> Result<String> {
+    Ok(hex(&Sha512::digest(git::diff(repo.path())?)))
+}
+
+fn state_path(repo: &Repo) -> PathBuf {
+    repo.path().join(".git").join(STATE_FILE)
+}
+
+fn load_state(repo: &Repo) -> Option<Applied> {
+    toml::from_str(&fs2::read_to_string(state_path(repo)).ok()?).ok()
+}
+
+fn save_state(repo: &Repo, state: &Applied) -> Result<()> {
+    let data = toml::to_string(state).map_err(|why| Error::other(why.to_string()))?;
+    fs2::write(state_path(repo), data).at(state_path(repo))
+}
+
+/// Whether any checkout of `target` has patches.
+///
+/// Such targets are always built from source.
+pub fn has_patches(target: Target) -> bool {
+    target.repos().iter().any(|x| patch_dir(x).is_some())
+}
+
+/// Whether the uncommitted changes in the checkout of `repo` are exactly its applied patches.
+pub fn is_applied(repo: &Repo) -> bool {
+    load_state(repo).is_some_and(|state| hash_diff(repo).is_ok_and(|x| x == state.diff))
+}
+
+/// Remove applied patches from the checkout of `repo`, before checking out something else.
+///
+/// Nothing is done if the checkout has other uncommitted changes.
+pub fn revert(repo: &Repo) -> Result<()> {
+    if !is_applied(repo) {
+        return Ok(());
+    }
+    git::reset_hard(repo.path())?;
+    fs2::remove_file(state_path(repo)).at(state_path(repo))
+}
+
+/// Apply patches of `repo` to its checkout.
+///
+/// Nothing is done if the patches are already applied. Patches that changed since they were
+/// applied are applied again.
+pub fn apply(repo: &Repo) -> Result<()> {
+    let Some(dir) = patch_dir(repo) else {
+        return Ok(());
+    };
+    if !git::is_checkout(repo.path()) {
+        return Ok(());
+    }
+    let files = patch_files(&dir)?;
+    let patches = hash_patches(&files)?;
+
+    if git::is_dirty(repo.path()) {
+        let diff = hash_diff(repo)?;
+        match load_state(repo) {
+            Some(x) if x.diff == diff && x.patches == patches => return Ok(()),
+            Some(x) if x.diff == diff => {
+                eprintln!("{}: {} changed, applying again", repo.dir, dir.display());
+                git::reset_hard(repo.path())?;
+            }
+            Some(x) if x.patches == patches => {
+                eprintln!(
+                    "note: {}: building with uncommitted changes on top of {}, run `./b patches refresh {}` to keep them",
+                    repo.dir,
+                    dir.display(),
+                    repo.dir,
+                );
+                return Ok(());
+            }
+            _ => {
+                return Err(Error::other(format!(
+                    "{}: has uncommitted changes, cannot apply {}; run `./b patches refresh {}` to save them into the patches or discard them",
+                    repo.dir,
+                    dir.display(),
+                    repo.dir,
+                )));
+            }
+        }
+    }
+
+    for file in &files {
+        eprintln!("{}: applying {}", repo.dir, file.display());
+        if let Err(why) = git::apply(repo.path(), file) {
+            // Don't leave some of the patches applied.
+            _ = git::reset_hard(repo.path());
+            return Err(Error::other(format!(
+                "{}: {} no longer applies at {} ({}); apply the patches by hand with `git apply --3way`, resolve the conflicts and run `./b patches refresh {}`",
+                repo.dir,
+                file.display(),
+                git::revision(repo.path())
+                    .as_deref()
+                    .map_or("?", git::short),
+                why.error(),
+                repo.dir,
+            )));
+        }
+    }
+
+    if files.is_empty() {
+        return Ok(());
+    }
+    let diff = hash_diff(repo)?;
+    save_state(repo, &Applied { patches, diff })
+}
+
+/// Checkout named by a target name or a checkout directory.
+fn find_repo(name: &str) -> Option<&'static Repo> {
+    Target::ALL
+        .iter()
+        .flat_map(|x| x.repos())
+        .find(|x| x.dir == name)
+        .or_else(|| {
+            Target::ALL
+                .iter()
+                .find(|x| x.name() == name)?
+                .repos()
+                .first()
+        })
+}
+
+/// Changed file a section of a diff is for, from its `diff --git a/PATH b/PATH` header.
+fn section_path(section: &str) -> &str {
+    let header = section.lines().next().unwrap_or_default();
+    header.rsplit_once(" b/").map_or(header, |x| x.1)
+}
+
+/// Split a diff into sections of single files.
+fn sections(diff: &str) -> Vec<&str> {
+    let mut starts: Vec<usize> = diff
+        .match_indices("diff --git ")
+        .map(|x| x.0)
+        .filter(|&x| x == 0 || diff.as_bytes()[x - 1] == b'\n')
+        .collect();
+    if starts.first() != Some(&0) {
+        // Leading text, like the message of a patch made by `git format-patch`.
+        starts.insert(0, 0);
+    }
+    starts.push(diff.len());
+    starts.windows(2).map(|x| &diff[x[0]..x[1]]).collect()
+}
+
+/// Implementation of `./b patches refresh`.
+///
+/// Writes uncommitted changes of each named checkout into its patch files. The change of a
+/// file goes into the last patch that already changed it, changes of other files go into the
+/// last patch (or into `local.patch` if the checkout has none). Patches left empty are
+/// removed.
+pub fn refresh(args: &PatchesArgs) -> Result<()> {
+    for name in &args.names {
+        let Some(repo) = find_repo(name) else {
+            return Err(Error::other(format!("{name}: not a checkout")));
+        };
+        if !git::is_checkout(repo.path()) {
+            return Err(Error::other(format!("{}: not cloned", repo.dir)));
+        }
+
+        let dir = patch_dir(repo).unwrap_or_else(|| Path::new(PATCHES_DIR).join(name));
+        let files = if dir.is_dir() {
+            patch_files(&dir)?
+        } else {
+            vec![]
+        };
+        if !files.is_empty() {
+            match load_state(repo) {
+                Some(x) if x.patches == hash_patches(&files)? => (),
+                Some(_) => {
+                    return Err(Error::other(format!(
+                        "{}: {} changed since it was applied, build the target to apply it first",
+                        repo.dir,
+                        dir.display()
+                    )));
+                }
+                None => {
+                    return Err(Error::other(format!(
+                        "{}: {} is not applied to the checkout, build the target to apply it first",
+                        repo.dir,
+                        dir.display()
+                    )));
+                }
+            }
+        }
+
+        let diff = git::diff(repo.path())?;
+        if diff.is_empty() {
+            return Err(Error::other(format!(
+                "{}: no uncommitted changes to write into {}",
+                repo.dir,
+                dir.display()
+            )));
+        }
+        let diff = String::from_utf8(diff)
+            .map_err(|_| Error::other(format!("{}: diff is not valid UTF-8", repo.dir)))?;
+
+        // Files changed by each existing patch.
+        let mut owners: HashMap<String, usize> = HashMap::new();
+        for (i, file) in files.iter().enumerate() {
+            let data = fs2::read_to_string(file)?;
+            for x in sections(&data) {
+                owners.insert(section_path(x).to_string(), i);
+            }
+        }
+
+        let mut contents = vec![String::new(); files.len().max(1)];
+        let last = contents.len() - 1;
+        for x in sections(&diff) {
+            let i = owners.get(section_path(x)).copied().unwrap_or(last);
+            contents[i] += x;
+        }
+
+        let files = if files.is_empty() {
+            fs2::create_dir_all(&dir)?;
+            vec![dir.join(DEFAULT_PATCH)]
+        } else {
+            files
+        };
+        for (file, data) in files.iter().zip(&contents) {
+            if data.is_empty() {
+                fs2::remove_file(file)?;
+                eprintln!("{}: removed {}", repo.dir, file.display());
+            } else if fs2::read(file).is_ok_and(|x| x == data.as_bytes()) {
+                eprintln!("{}: {} is up to date", repo.dir, file.display());
+            } else {
+                fs2::write(file, data)?;
+                eprintln!("{}: wrote {}", repo.dir, file.display());
+            }
+        }
+
+        let files = patch_files(&dir)?;
+        save_state(
+            repo,
+            &Applied {
+                patches: hash_patches(&files)?,
+                diff: hash_diff(repo)?,
+            },
+        )?;
+    }
+    Ok(())
+}
@@ -0,0 +1,35 @@
+//! Detection of the host platform.
+//!
+//! Tools are downloaded as prebuilt release assets, which upstream publishes for a few
+//! platforms only. Each tool lists the asset name for every platform it supports, see
+//! [`Tool::assets`](crate::tools::Tool::assets).
+
+use std::env::consts;
+
+/// Platform tools can be downloaded for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Platform {
+    /// 64-bit x86 Linux.
+    LinuxX86_64,
+    /// 64-bit ARM Linux.
+    LinuxAarch64,
+    /// 64-bit x86 Windows.
+    WindowsX86_64,
+}
+
+impl Platform {
+    /// Platform of the host, `None` if no tool is downloaded for it.
+    pub fn host() -> Option<Self> {
+        match (consts::OS, consts::ARCH) {
+            ("linux", "x86_64") => Some(Self::LinuxX86_64),
+            ("linux", "aarch64") => Some(Self::LinuxAarch64),
+            ("windows", "x86_64") => Some(Self::WindowsX86_64),
+            _ => None,
+        }
+    }
+}
+
+/// Name of the host platform, like `linux-aarch64`.
+pub fn host_name() -> String {
+    format!("{}-{}", consts::OS, consts::ARCH)
+}
@@ -0,0 +1,370 @@
+//! Prebuilt artifacts.
+//!
+//! Targets enabled only as a dependency whose sources are not checked out are initialized
+//! from a released artifact instead of cloning and building them. Artifacts are looked up in
+//! this order:
+//!
+//! 1. local mirror directory (`prebuilt_mirror` in `workspace.toml` or `--prebuilt-mirror`),
+//! 2. GitHub release of the target's repository locked in `workspace.lock`,
+//! 3. mindurka maven repository ([MAVEN_REPO]).
+//!
+//! If none of them has the artifact, the target is built from source. Downloads are checked
+//! against the lock like tools, and copies from the mirror against the hash locked for the
+//! release they stand in for. The release is locked by its download URL, builds with
+//! `--update-lock` lock the latest release of repositories that have none locked yet.
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+use crate::{
+    error::{Error, Result},
+    fs2,
+    lock::{self, LOCK_FILE, Lock},
+    targets::InitParams,
+    util::{download, is_offline, sha256_file, try_download},
+};
+
+/// Maven repository with artifacts published by mindurka.
+pub const MAVEN_REPO: &str = "https://mindurka.online/files/repo";
+
+/// Directory with downloaded artifacts.
+pub const CACHE_DIR: &str = ".cache/prebuilt";
+
+/// Description of a prebuilt artifact of a target.
+pub struct Prebuilt {
+    /// Name of the target, used in messages.
+    pub name: &'static str,
+    /// Repository on GitHub (e.g. "Darkdustry-Coders/CorePlugin") to look for releases in.
+    pub repo: &'static str,
+    /// Path of the artifact relative to the workspace root.
+    pub artifact: &'static str,
+    /// File name of the artifact in releases and mirrors.
+    pub asset: &'static str,
+    /// Maven coordinates (`group:artifact:version`) in [MAVEN_REPO], if published there.
+    pub maven: Option<&'static str>,
+}
+impl Prebuilt {
+    /// URL of the artifact in [MAVEN_REPO].
+    fn maven_url(&self) -> Option<String> {
+        let mut parts = self.maven?.split(':');
+        let (group, artifact, version) = (parts.next()?, parts.next()?, parts.next()?);
+        Some(format!(
+            "{MAVEN_REPO}/{}/{artifact}/{version}/{artifact}-{version}.jar",
+            group.replace('.', "/"),
+        ))
+    }
+
+    /// URL of the artifact in GitHub release `tag`.
+    fn release_url(&self, tag: &str) -> String {
+        format!(
+            "https://github.com/{}/releases/download/{tag}/{}",
+            self.repo, self.asset,
+        )
+    }
+
+    /// URL of the artifact in the GitHub release locked in `lock`, if any.
+    fn locked_release_url(&self, lock: &Lock) -> Option<String> {
+        let prefix = format!("https://github.com/{}/releases/download/", self.repo);
+        let suffix = format!("/{}", self.asset);
+        lock.downloads
+            .keys()
+            .find(|x| {
+                x.strip_prefix(&prefix)
+                    .and_then(|x| x.strip_suffix(&suffix))
+                    .is_some_and(|tag| !tag.is_empty() && !tag.contains('/'))
+            })
+            .cloned()
+    }
+
+    /// Tag of the latest GitHub release, asked for through the GitHub API.
+    ///
+    /// The response is downloaded into `cache` and removed afterwards.
+    fn latest_tag(&self, cache: &Path) -> io::Result<String> {
+        #[derive(Deserialize)]
+        struct Release {
+            tag_name: String,
+        }
+
+        let url = format!("https://api.github.com/repos/{}/releases/latest", self.repo);
+        let path = cache.join(format!(".{}-latest.json", self.name));
+        try_download(&url, &path)?;
+        let data = fs2::read_to_string(&path)?;
+        fs2::remove_file(&path)?;
+        serde_json::from_str::<Release>(&data)
+            .map(|x| x.tag_name)
+            .map_err(|why| io::Error::new(io::ErrorKind::InvalidData, format!("{url}: {why}")))
+    }
+
+    /// URL of the artifact in the GitHub release locked in `lock`.
+    ///
+    /// With `--update-lock` the latest release is used if none is locked, its hash is then
+    /// recorded by the download. Returns `None` if there is no release to download.
+    fn release(&self, cache: &Path, lock: &Lock) -> Result<Option<String>> {
+        if let Some(url) = self.locked_release_url(lock) {
+            return Ok(Some(url));
+        }
+        if !lock::updates_downloads() {
+            eprintln!(
+                "{}: no release of {} in {LOCK_FILE}, run the build with `--update-lock` to lock the latest one",
+                self.name, self.repo
+            );
+            return Ok(None);
+        }
+        match self.latest_tag(cache) {
+            Ok(tag) => Ok(Some(self.release_url(&tag))),
+            Err(why) => {
+                eprintln!("{}: could not find the latest release: {why}", self.name);
+                Ok(None)
+            }
+        }
+    }
+
+    /// Check a copy of the artifact at `path` from a mirror against the hash of the release
+    /// locked in `lock`.
+    ///
+    /// Mirrors stand in for the release, so copies without a locked release are not used.
+    fn verify_mirror_copy(&self, path: &Path, lock: &Lock) -> Result<()> {
+        let Some(url) = self.locked_release_url(lock) else {
+            return Err(Error::other(format!(
+                "no release of {} in {LOCK_FILE} to check it against, run the build online with `--update-lock` to lock one",
+                self.repo
+            )));
+        };
+        let hash = sha256_file(path)?;
+        match &lock.downloads[&url] {
+            x if *x == hash => Ok(()),
+            x => Err(Error::other(format!(
+                "checksum mismatch: {LOCK_FILE} has sha256 {x} for {url}, the copy has {hash}"
+            ))),
+        }
+    }
+}
+
+/// Path to a local mirror given either as a path or a `file://` URL.
+pub fn mirror_path(mirror: &Path) -> PathBuf {
+    match mirror.to_str().and_then(|x| x.strip_prefix("file://")) {
+        Some(x) => PathBuf::from(x),
+        None => mirror.to_path_buf(),
+    }
+}
+
+/// Fetch a prebuilt artifact into the workspace.
+///
+/// Returns `false` if the artifact could not be found in any source.
+pub fn fetch(prebuilt: &Prebuilt, params: &InitParams) -> Result<bool> {
+    fetch_into(
+        prebuilt,
+        &params.root,
+        params.prebuilt_mirror.as_deref(),
+        &Lock::load()?,
+    )
+}
+
+/// Fetch a prebuilt artifact into the workspace at `root`, looking in `mirror` first.
+///
+/// The release to download and the hash copies from `mirror` must have are taken from `lock`.
+fn fetch_into(
+    prebuilt: &Prebuilt,
+    root: &Path,
+    mirror: Option<&Path>,
+    lock: &Lock,
+) -> Result<bool> {
+    let cache = root.join(CACHE_DIR);
+    let cached = cache.join(prebuilt.asset);
+
+    let mut found = None;
+
+    if let Some(mirror) = mirror {
+        let path = mirror_path(mirror).join(prebuilt.asset);
+        match copy(&path, &cached) {
+            Ok(()) => match prebuilt.verify_mirror_copy(&cached, lock) {
+                Ok(()) => found = Some(path.display().to_string()),
+                Err(why) => {
+                    fs2::remove_file(&cached)?;
+                    eprintln!(
+                        "{}: not using {}: {}",
+                        prebuilt.name,
+                        path.display(),
+                        why.error()
+                    );
+                }
+            },
+            Err(why) => eprintln!("{}: not found in mirror: {why}", prebuilt.name),
+        }
+    }
+
+    if found.is_none() && is_offline() {
+        if cached.is_file() {
+            found = Some(cached.display().to_string());
+        } else {
+            eprintln!(
+                "{}: not downloading prebuilt artifact offline",
+                prebuilt.name
+            );
+        }
+    } else if found.is_none() {
+        let urls = [prebuilt.release(&cache, lock)?, prebuilt.maven_url()];
+        for url in urls.into_iter().flatten() {
+            eprintln!(
+                "{}: downloading prebuilt artifact from {url}",
+                prebuilt.name
+            );
+            match download(&url, &cached) {
+                Ok(()) => {
+                    found = Some(url);
+                    break;
+                }
+                Err(why) => eprintln!("{}: download failed: {}", prebuilt.name, why.error()),
+            }
+        }
+    }
+
+    let Some(source) = found else {
+        eprintln!(
+            "{}: no prebuilt artifact found, building from source",
+            prebuilt.name
+        );
+        return Ok(false);
+    };
+
+    copy(&cached, &root.join(prebuilt.artifact))?;
+    eprintln!("{}: using prebuilt artifact from {source}", prebuilt.name);
+    Ok(true)
+}
+
+/// Copy a file, creating parent directories of `dest`.
+fn copy(source: &Path, dest: &Path) -> io::Result<()> {
+    if let Some(parent) = dest.parent() {
+        fs2::create_dir_all(parent)?;
+    }
+    fs2::copy(source, dest).map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    use super::*;
+
+    const PREBUILT: Prebuilt = Prebuilt {
+        name: "test",
+        repo: "Darkdustry-Coders/Test",
+        artifact: ".bin/Test.jar",
+        asset: "Test.jar",
+        maven: None,
+    };
+
+    /// New empty directory for a test, unique to the test and the process.
+    fn temp_dir(name: &str) -> PathBuf {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir().join(format!(
+            "buildscript-test-{name}-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        _ = fs::remove_dir_all(&dir);
+        fs2::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Lock with a release of [PREBUILT] whose asset has the contents `data`.
+    fn locked(data: &str) -> Lock {
+        let dir = temp_dir("prebuilt-hash");
+        fs2::write(dir.join("asset"), data).unwrap();
+        let mut lock = Lock::default();
+        lock.downloads.insert(
+            PREBUILT.release_url("v1"),
+            sha256_file(&dir.join("asset")).unwrap(),
+        );
+        lock
+    }
+
+    #[test]
+    fn fetch_from_mirror() {
+        let dir = temp_dir("prebuilt-mirror");
+        let (root, mirror) = (dir.join("workspace"), dir.join("mirror"));
+        fs2::create_dir_all(&mirror).unwrap();
+        fs2::write(mirror.join("Test.jar"), "jar").unwrap();
+
+        assert!(fetch_into(&PREBUILT, &root, Some(&mirror), &locked("jar")).unwrap());
+        assert_eq!(
+            fs::read_to_string(root.join(".bin/Test.jar")).unwrap(),
+            "jar"
+        );
+        assert_eq!(
+            fs::read_to_string(root.join(CACHE_DIR).join("Test.jar")).unwrap(),
+            "jar"
+        );
+    }
+
+    #[test]
+    fn fetch_from_file_url_mirror() {
+        let dir = temp_dir("prebuilt-file-url");
+        let (root, mirror) = (dir.join("workspace"), dir.join("mirror"));
+        fs2::create_dir_all(&mirror).unwrap();
+        fs2::write(mirror.join("Test.jar"), "jar").unwrap();
+        let url = PathBuf::from(format!("file://{}", mirror.display()));
+
+        assert!(fetch_into(&PREBUILT, &root, Some(&url), &locked("jar")).unwrap());
+        assert_eq!(
+            fs::read_to_string(root.join(".bin/Test.jar")).unwrap(),
+            "jar"
+        );
+    }
+
+    #[test]
+    fn verify_mirror_copy() {
+        let dir = temp_dir("prebuilt-verify");
+        fs2::write(dir.join("Test.jar"), "tampered").unwrap();
+        let path = dir.join("Test.jar");
+
+        assert!(
+            PREBUILT
+                .verify_mirror_copy(&path, &locked("tampered"))
+                .is_ok()
+        );
+        assert!(PREBUILT.verify_mirror_copy(&path, &locked("jar")).is_err());
+        // Without a locked release there is nothing to check the copy against.
+        assert!(
+            PREBUILT
+                .verify_mirror_copy(&path, &Lock::default())
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn fetch_missing_from_mirror() {
+        let dir = temp_dir("prebuilt-missing");
+        let (root, mirror) = (dir.join("workspace"), dir.join("mirror"));
+        fs2::create_dir_all(&mirror).unwrap();
+
+        // No release is locked and there is no maven artifact, so nothing is downloaded.
+        assert!(!fetch_into(&PREBUILT, &root, Some(&mirror), &Lock::default()).unwrap());
+        assert!(!root.join(".bin/Test.jar").exists());
+    }
+    #[test]
+    fn locked_release_url() {
+        let mut lock = Lock::default();
+        assert_eq!(PREBUILT.locked_release_url(&lock), None);
+
+        for url in [
+            "https://github.com/Darkdustry-Coders/Test/releases/download/v1/Other.jar",
+            "https://github.com/Darkdustry-Coders/Other/releases/download/v1/Test.jar",
+            "https://github.com/Darkdustry-Coders/Test/releases/latest/download/Test.jar",
+        ] {
+            lock.downloads.insert(url.into(), String::new());
+        }
+        assert_eq!(PREBUILT.locked_release_url(&lock), None);
+
+        let url = PREBUILT.release_url("v1.2");
+        lock.downloads.insert(url.clone(), String::new());
+        assert_eq!(PREBUILT.locked_release_url(&lock), Some(url));
+    }
+}
@@ -0,0 +1,220 @@
+//! Build summary.
+//!
+//! Every `./b build` records how long each target took in every phase and how it ended.
+//! The summary is printed as a table once the build is done (after the targets stopped
+//! running with `./b run`, so errors while running are included) and saved into [REPORT_PATH] along with the previous [HISTORY_LEN] runs, newest first.
+
+use std::{
+    io,
+    path::Path,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    clean::{human_size, size},
+    error::{Error, Phase},
+    fs2,
+    targets::Target,
+    util::Backtraced,
+};
+
+/// Path of the saved build reports.
+pub const REPORT_PATH: &str = ".cache/tools/buildscript/last-build.json";
+
+/// Number of runs kept in [REPORT_PATH].
+pub const HISTORY_LEN: usize = 20;
+
+/// How a step ended.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    /// Target was built, installed, cloned or prepared to run.
+    Built,
+    /// Nothing had to be done as the target was up to date.
+    Skipped,
+    /// Target was already available, or its last built artifact was reused.
+    Cached,
+    /// Target was initialized from a downloaded artifact.
+    Downloaded,
+    /// Step failed.
+    Failed,
+}
+impl Status {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Built => "built",
+            Self::Skipped => "skipped",
+            Self::Cached => "cached",
+            Self::Downloaded => "downloaded",
+            Self::Failed => "failed",
+        }
+    }
+}
+
+/// A single phase of a single target.
+#[derive(Serialize, Deserialize)]
+pub struct Step {
+    pub target: String,
+    /// `init`, `build` or `run init`.
+    pub phase: String,
+    pub status: Status,
+    /// Wall time in seconds.
+    pub seconds: f64,
+    /// Total size of the artifacts of the target in bytes, after building it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub artifact_size: Option<u64>,
+}
+
+/// A single `./b build`.
+#[derive(Serialize, Deserialize, Default)]
+pub struct Run {
+    /// Start of the build in seconds since the unix epoch.
+    pub started: u64,
+    /// Targets given on the command line.
+    pub targets: Vec<String>,
+    pub success: bool,
+    /// Error the build failed with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Wall time of the whole build in seconds.
+    pub seconds: f64,
+    pub steps: Vec<Step>,
+}
+
+/// Contents of [REPORT_PATH].
+#[derive(Serialize, Deserialize, Default)]
+struct History {
+    /// Recent runs, newest first.
+    runs: Vec<Run>,
+}
+
+/// Report of the current build.
+pub struct Report {
+    run: Run,
+    started: Instant,
+    finished: bool,
+}
+impl Report {
+    /// Start a report of a build of `targets`.
+    pub fn new(targets: &[String]) -> Self {
+        Self {
+            run: Run {
+                started: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |x| x.as_secs()),
+                targets: targets.to_vec(),
+                success: false,
+                error: None,
+                seconds: 0.0,
+                steps: vec![],
+            },
+            started: Instant::now(),
+            finished: false,
+        }
+    }
+
+    /// Record a finished step of `target`.
+    pub fn step(&mut self, target: Target, phase: Phase, status: Status, elapsed: Duration) {
+        let artifact_size = (phase == Phase::Build && status != Status::Failed)
+            .then(|| artifact_size(target))
+            .flatten();
+        self.run.steps.push(Step {
+            target: target.name().into(),
+            phase: phase.to_string(),
+            status,
+            seconds: elapsed.as_secs_f64(),
+            artifact_size,
+        });
+    }
+
+    /// Print the summary and save it into [REPORT_PATH].
+    ///
+    /// Does nothing if the report is already finished.
+    pub fn finish(&mut self, error: Option<&Backtraced<Error>>) {
+        if self.finished {
+            return;
+        }
+        self.finished = true;
+
+        self.run.success = error.is_none();
+        self.run.error = error.map(|x| x.error().to_string());
+        self.run.seconds = self.started.elapsed().as_secs_f64();
+
+        self.print();
+        if let Err(why) = self.save() {
+            eprintln!("warning: could not save build report: {why}");
+        }
+    }
+
+    fn print(&self) {
+        if self.run.steps.is_empty() {
+            return;
+        }
+
+        let width = self
+            .run
+            .steps
+            .iter()
+            .map(|x| x.target.len())
+            .max()
+            .unwrap_or(0)
+            .max("target".len());
+
+        eprintln!();
+        eprintln!(
+            "{:width$}  {:8}  {:10}  {:>8}  {:>10}",
+            "target", "phase", "status", "time", "size"
+        );
+        for x in &self.run.steps {
+            let line = format!(
+                "{:width$}  {:8}  {:10}  {:>7.1}s  {:>10}",
+                x.target,
+                x.phase,
+                x.status.name(),
+                x.seconds,
+                x.artifact_size.map(human_size).unwrap_or_default(),
+            );
+            eprintln!("{}", line.trim_end());
+        }
+        eprintln!(
+            "{:width$}  {:8}  {:10}  {:>7.1}s",
+            "total",
+            "",
+            if self.run.success { "ok" } else { "failed" },
+            self.run.seconds,
+        );
+    }
+
+    fn save(&mut self) -> io::Result<()> {
+        let path = Path::new(REPORT_PATH);
+
+        let mut history = match fs2::read_to_string(path) {
+            // A report from an incompatible version is dropped.
+            Ok(x) => serde_json::from_str(&x).unwrap_or_default(),
+            Err(why) if why.kind() == io::ErrorKind::NotFound => History::default(),
+            Err(why) => return Err(why),
+        };
+
+        history.runs.insert(0, std::mem::take(&mut self.run));
+        history.runs.truncate(HISTORY_LEN);
+
+        if let Some(parent) = path.parent() {
+            fs2::create_dir_all(parent)?;
+        }
+        fs2::write(path, serde_json::to_string_pretty(&history)? + "\n")
+    }
+}
+
+/// Total size of the artifacts of `target`, if it has any.
+fn artifact_size(target: Target) -> Option<u64> {
+    if target.artifacts().is_empty() {
+        return None;
+    }
+    let mut total = 0;
+    for x in target.artifacts() {
+        total += size(Path::new(x)).ok()?;
+    }
+    Some(total)
+}
@@ -141,7 +141,7 @@ impl SyncFs {
             }
 
             let path = self.root.join(path);
-            if fs::read(&path).is_ok_and(|x| x == &**value) {
+            if fs::read(&path).is_ok_and(|x| x == **value) {
                 continue;
             }
 
@@ -2,19 +2,38 @@ use std::{
     any::Any,
     collections::HashMap,
     ffi::{OsStr, OsString},
-    io::{Write, stderr, stdin},
+    fs::File,
+    io::{self, Write, stderr, stdin},
     ops::{Deref, DerefMut},
-    path::PathBuf,
-    process::{Command, exit},
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
     str::FromStr,
+    sync::{Mutex, OnceLock, mpsc},
+    thread,
+    time::{Duration, Instant},
 };
 
 use crate::{
     args::{BuildArgs, EnvTy, GitBackend},
+    error::{CommandExt, Context, Error, Phase, Result},
+    git::{self, Remotes},
+    incremental::BuildState,
+    patches,
+    report::{Report, Status},
     syncfs::SyncFs,
+    tools::{Release, Tool, ToolConfig},
     util::{self, current_dir},
 };
 
+/// Directory with build logs of targets built in parallel.
+pub const BUILD_LOG_DIR: &str = ".cache/tools/buildscript/logs";
+
+/// Lock for scratch files in `.cache/tools/buildscript` used when building native images.
+///
+/// These are shared between targets, so only one target may use them at a time.
+pub static NATIVE_IMAGE_LOCK: Mutex<()> = Mutex::new(());
+
 /// List of target flags.
 ///
 /// ## Version stability.
@@ -39,6 +58,12 @@ pub struct TargetFlags {
     pub always_local: bool,
     /// A target is to be removed in the future.
     pub deprecated: bool,
+    /// A target is built with gradle.
+    ///
+    /// Gradle builds share the gradle project of the workspace, so they are never run at
+    /// the same time. Targets ready at the same time with
+    /// [gradle tasks](TargetImplStatic::gradle_tasks) are built by one invocation instead.
+    pub gradle: bool,
 }
 impl TargetFlags {
     #[allow(unused)]
@@ -46,6 +71,7 @@ impl TargetFlags {
         TargetFlags {
             always_local: false,
             deprecated: false,
+            gradle: false,
         }
     }
 
@@ -60,12 +86,19 @@ impl TargetFlags {
         self.deprecated = true;
         self
     }
+
+    #[allow(unused)]
+    pub const fn gradle(mut self) -> TargetFlags {
+        self.gradle = true;
+        self
+    }
 }
 impl Default for TargetFlags {
     fn default() -> Self {
         Self {
             always_local: true,
             deprecated: false,
+            gradle: false,
         }
     }
 }
@@ -106,19 +139,88 @@ impl TargetEnabled {
     }
 }
 
+/// Git repository checked out into the workspace by a target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Repo {
+    /// Directory of the checkout relative to the workspace root.
+    ///
+    /// The checkout may be located elsewhere, see [Repo::path].
+    pub dir: &'static str,
+    /// Repository path on GitHub (e.g. "Darkdustry-Coders/Forts").
+    pub remote: &'static str,
+}
+impl Repo {
+    /// Path to the checkout.
+    ///
+    /// This is [dir](Self::dir) relative to the workspace root, unless the checkout is
+    /// located outside of the workspace.
+    pub fn path(&self) -> PathBuf {
+        checkout_path(self.dir)
+    }
+
+    /// Whether the checkout is located outside of the workspace.
+    pub fn is_external(&self) -> bool {
+        CHECKOUT_PATHS
+            .get()
+            .is_some_and(|x| x.contains_key(self.dir))
+    }
+}
+
+/// Checkouts located outside of the workspace, by [Repo::dir].
+static CHECKOUT_PATHS: OnceLock<HashMap<&'static str, PathBuf>> = OnceLock::new();
+
+/// Set paths of checkouts located outside of the workspace.
+///
+/// `paths` are keyed by target name or checkout directory, like `path` in `workspace.toml`.
+/// `~/` expands to the home directory and relative paths are relative to the workspace root.
+pub fn set_checkout_paths(paths: &HashMap<String, PathBuf>) {
+    let home = std::env::var_os("HOME").map(PathBuf::from);
+    let resolved = Target::ALL
+        .iter()
+        .flat_map(|x| x.repos())
+        .filter_map(|repo| {
+            let path = git::for_repo(paths, repo)?;
+            let path = match (path.strip_prefix("~"), &home) {
+                (Ok(rest), Some(home)) => home.join(rest),
+                _ => current_dir().join(path),
+            };
+            Some((repo.dir, path))
+        })
+        .collect();
+    _ = CHECKOUT_PATHS.set(resolved);
+}
+
+/// Path to a checkout directory or a file inside of it.
+///
+/// `path` is relative to the workspace root. If it's inside of a checkout located outside of
+/// the workspace, the path into that checkout is returned instead.
+pub fn checkout_path(path: &str) -> PathBuf {
+    let (dir, rest) = path.split_once('/').unwrap_or((path, ""));
+    match CHECKOUT_PATHS.get().and_then(|x| x.get(dir)) {
+        Some(x) if rest.is_empty() => x.clone(),
+        Some(x) => x.join(rest),
+        None => PathBuf::from(path),
+    }
+}
+
 /// Base trait for targets.
-pub trait TargetImpl: Any {
+///
+/// Targets are built on worker threads when building in parallel, hence the `Send` bound.
+/// Built targets are shared with every target depending on them, hence the `Sync` bound.
+pub trait TargetImpl: Any + Send + Sync {
     /// Build target.
-    fn build(&mut self, deps: Targets<'_>, params: &mut BuildParams);
+    fn build(&mut self, deps: Deps<'_>, params: &mut BuildParams) -> Result<()>;
 
     /// Initialize run target.
-    fn run_init(&mut self, deps: Targets<'_>, params: &mut RunParams) {
-        _ = (deps, params)
+    fn run_init(&mut self, deps: Targets<'_>, params: &mut RunParams) -> Result<()> {
+        _ = (deps, params);
+        Ok(())
     }
 
     /// Run target.
-    fn run(&mut self, deps: Targets<'_>, params: &mut RunParams) {
-        _ = (deps, params)
+    fn run(&mut self, deps: Targets<'_>, params: &mut RunParams) -> Result<()> {
+        _ = (deps, params);
+        Ok(())
     }
 }
 pub trait TargetImplStatic: TargetImpl
@@ -135,13 +237,41 @@ where
     fn depends(list: &mut TargetList) {
         _ = list
     }
+    /// Repositories this target clones into the workspace.
+    fn repos() -> &'static [Repo] {
+        &[]
+    }
+    /// Paths (relative to the workspace root) created by this target.
+    ///
+    /// These are removed by `./b clean <target>`. Source checkouts must not be listed here.
+    fn owned_paths() -> &'static [&'static str] {
+        &[]
+    }
+    /// Paths (relative to the workspace root) the build depends on.
+    ///
+    /// Building is skipped when these and the [artifacts](TargetImplStatic::artifacts) are
+    /// unchanged since the last successful build. Targets without inputs are always built.
+    fn inputs() -> &'static [&'static str] {
+        &[]
+    }
+    /// Paths (relative to the workspace root) produced by the build.
+    fn artifacts() -> &'static [&'static str] {
+        &[]
+    }
+    /// Tasks of the workspace gradle build that build this target.
+    ///
+    /// Targets with tasks run them with [BuildParams::run_gradle_tasks], so parallel builds
+    /// can run the tasks of all targets that are ready at the same time in one invocation.
+    fn gradle_tasks() -> &'static [&'static str] {
+        &[]
+    }
 
     /// Initialize target using host tools.
     fn initialize_host(
         enabled: TargetEnabled,
         deps: Targets<'_>,
         params: &mut InitParams,
-    ) -> Option<Self>;
+    ) -> Result<Option<Self>>;
     /// Try to initialize target locally.
     ///
     /// This will only attempt to use already available data.
@@ -149,10 +279,25 @@ where
         enabled: TargetEnabled,
         deps: Targets<'_>,
         params: &mut InitParams,
-    ) -> Option<Self>;
+    ) -> Result<Option<Self>>;
+    /// Initialize target from a prebuilt artifact (see [crate::prebuilt]).
+    ///
+    /// Only called for targets enabled as [TargetEnabled::Depend] which could not be
+    /// initialized from cache. Returning `None` falls back to
+    /// [initialize_local](TargetImplStatic::initialize_local).
+    fn initialize_prebuilt(
+        #[allow(unused)] enabled: TargetEnabled,
+        #[allow(unused)] deps: Targets<'_>,
+        #[allow(unused)] params: &mut InitParams,
+    ) -> Result<Option<Self>> {
+        Ok(None)
+    }
     /// Initialize target locally.
-    fn initialize_local(enabled: TargetEnabled, deps: Targets<'_>, params: &mut InitParams)
-    -> Self;
+    fn initialize_local(
+        enabled: TargetEnabled,
+        deps: Targets<'_>,
+        params: &mut InitParams,
+    ) -> Result<Self>;
 
     /// Check the environment and add the appropriate init parametets.
     ///
@@ -163,7 +308,8 @@ where
         #[allow(unused)] enabled: TargetEnabled,
         #[allow(unused)] deps: Targets<'_>,
         #[allow(unused)] params: &mut InitParams,
-    ) {
+    ) -> Result<()> {
+        Ok(())
     }
 }
 /// Extension trait for downcasting target implementations.
@@ -253,8 +399,35 @@ pub struct InitParams {
     pub host_rabbitmq: bool,
     /// Whether SurrealDB is hosted externally.
     pub host_surrealdb: bool,
+    /// Whether dependencies may be initialized from prebuilt artifacts.
+    pub prebuilt: bool,
+    /// Local directory to look for prebuilt artifacts in.
+    pub prebuilt_mirror: Option<PathBuf>,
+    /// Targets initialized from prebuilt artifacts.
+    pub prebuilt_targets: Vec<Target>,
+    /// Git refs to check out, by target name or checkout directory.
+    pub refs: HashMap<String, String>,
+    /// Where repositories are cloned from.
+    pub remotes: Remotes,
+    /// Versions and download URLs of tools by target name.
+    pub tools: HashMap<String, ToolConfig>,
 }
 impl InitParams {
+    /// Release of `tool` to install.
+    pub fn release(&self, tool: &Tool) -> Result<Release> {
+        tool.release(&self.tools)
+    }
+
+    /// Git ref to check out in `repo`, if one was requested.
+    pub fn git_ref(&self, repo: &Repo) -> Option<&str> {
+        git::for_repo(&self.refs, repo).map(String::as_str)
+    }
+
+    /// Whether a git ref was requested for any repository of `target`.
+    pub fn has_git_ref(&self, target: Target) -> bool {
+        target.repos().iter().any(|x| self.git_ref(x).is_some())
+    }
+
     /// Creates new initialization parameters from build arguments.
     pub fn new(args: &BuildArgs) -> Self {
         Self {
@@ -266,6 +439,12 @@ impl InitParams {
             root: current_dir().to_path_buf(),
             host_rabbitmq: !args.rabbitmq_url.is_empty(),
             host_surrealdb: !args.surrealdb_url.is_empty(),
+            prebuilt: args.prebuilt,
+            prebuilt_mirror: args.prebuilt_mirror.clone(),
+            prebuilt_targets: Default::default(),
+            refs: args.refs.clone(),
+            remotes: Remotes::new(args),
+            tools: args.tools.clone(),
         }
     }
 }
@@ -289,6 +468,19 @@ pub struct BuildParams {
     pub host_rabbitmq: bool,
     /// Whether SurrealDB is hosted externally.
     pub host_surrealdb: bool,
+    /// Build targets even if they are up to date.
+    pub force: bool,
+    /// Never use the network, passed to gradle and cargo.
+    pub offline: bool,
+    /// Targets initialized from prebuilt artifacts, these are not built.
+    pub prebuilt_targets: Vec<Target>,
+    /// Log file receiving output of build commands.
+    ///
+    /// If `None`, output goes straight to the terminal.
+    pub log: Option<File>,
+    /// The [gradle tasks](TargetImplStatic::gradle_tasks) of the target were already run
+    /// together with those of other targets.
+    pub gradle_tasks_run: bool,
 }
 impl BuildParams {
     /// Creates new build parameters from initialization parameters and arguments.
@@ -302,13 +494,47 @@ impl BuildParams {
             java_stacktrace: args.java_stackstrace,
             host_rabbitmq: !args.rabbitmq_url.is_empty(),
             host_surrealdb: !args.surrealdb_url.is_empty(),
+            force: args.force,
+            offline: args.offline,
+            prebuilt_targets: params.prebuilt_targets,
+            log: None,
+            gradle_tasks_run: false,
+        }
+    }
+
+    /// Copy parameters for a single target writing its output into `log`.
+    pub fn fork(&self, log: File) -> Self {
+        Self {
+            git_backend: self.git_backend,
+            native_image: self.native_image,
+            env: self.env.clone(),
+            path: self.path.clone(),
+            root: self.root.clone(),
+            java_stacktrace: self.java_stacktrace,
+            host_rabbitmq: self.host_rabbitmq,
+            host_surrealdb: self.host_surrealdb,
+            force: self.force,
+            offline: self.offline,
+            prebuilt_targets: self.prebuilt_targets.clone(),
+            log: Some(log),
+            gradle_tasks_run: false,
+        }
+    }
+
+    /// Apply environment changes made by a target built with [BuildParams::fork].
+    pub fn merge(&mut self, other: Self) {
+        self.env.extend(other.env);
+        for x in other.path {
+            if !self.path.contains(&x) {
+                self.path.push(x);
+            }
         }
     }
 
     /// Create a [Command] with correctly set up environment.
     ///
     /// This does not set anything except for environment
-    /// variables.
+    /// variables and output redirection (see [BuildParams::log]).
     ///
     /// It's recommended to always pass absolute paths to `cmd`
     /// as the requested application may not be available on `PATH`.
@@ -331,6 +557,17 @@ impl BuildParams {
         }
         cmd.envs(&self.env);
         cmd.env("PATH", path);
+        if let Some(log) = &self.log {
+            cmd.stdin(Stdio::null());
+            cmd.stdout(
+                log.try_clone()
+                    .expect("failed to duplicate log file handle"),
+            );
+            cmd.stderr(
+                log.try_clone()
+                    .expect("failed to duplicate log file handle"),
+            );
+        }
         cmd
     }
 
@@ -352,14 +589,33 @@ impl BuildParams {
         if self.java_stacktrace {
             cmd.arg("--stacktrace");
         }
+        if self.offline {
+            cmd.arg("--offline");
+        }
         cmd
     }
 
-    #[must_use]
-    pub fn cargo(&self) -> Command {
+    /// Run gradle `tasks` of the workspace build, unless they were already run.
+    ///
+    /// See [BuildParams::gradle_tasks_run].
+    pub fn run_gradle_tasks(&self, tasks: &[&str]) -> Result<()> {
+        if self.gradle_tasks_run {
+            return Ok(());
+        }
+        self.gradle().args(tasks).run()
+    }
+
+    /// Creates a cargo command.
+    ///
+    /// Fails if cargo is not on [PATH](Self::path).
+    pub fn cargo(&self) -> Result<Command> {
         let cargo = util::find_executable_on_path("cargo", &self.path)
-            .expect("Could not find cargo executable");
-        self.cmd(cargo)
+            .ok_or_else(|| Error::other("could not find cargo executable"))?;
+        let mut cmd = self.cmd(cargo);
+        if self.offline {
+            cmd.env("CARGO_NET_OFFLINE", "true");
+        }
+        Ok(cmd)
     }
 }
 
@@ -434,11 +690,206 @@ impl RunParams {
         cmd
     }
 
-    #[must_use]
-    pub fn cargo(&self) -> Command {
+    /// Creates a cargo command.
+    ///
+    /// Fails if cargo is not on [PATH](Self::path).
+    pub fn cargo(&self) -> Result<Command> {
         let cargo = util::find_executable_on_path("cargo", &self.path)
-            .expect("Could not find cargo executable");
-        self.cmd(cargo)
+            .ok_or_else(|| Error::other("could not find cargo executable"))?;
+        Ok(self.cmd(cargo))
+    }
+}
+
+/// Result of [build_target].
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum BuildOutcome {
+    /// Target was built.
+    Built,
+    /// Target was not built as it's up to date.
+    UpToDate,
+    /// Target is only a dependency and the last good artifact was reused.
+    Reused,
+    /// Target was initialized from a prebuilt artifact.
+    Prebuilt,
+}
+impl BuildOutcome {
+    fn describe(self) -> &'static str {
+        match self {
+            Self::Built => "done",
+            Self::UpToDate => "up to date",
+            Self::Reused => "reusing last built artifact",
+            Self::Prebuilt => "using prebuilt artifact",
+        }
+    }
+
+    /// Status of a build in the [Report].
+    fn status(result: &Result<Self>) -> Status {
+        match result {
+            Ok(Self::Built) => Status::Built,
+            Ok(Self::UpToDate) => Status::Skipped,
+            Ok(Self::Reused) => Status::Cached,
+            Ok(Self::Prebuilt) => Status::Downloaded,
+            Err(_) => Status::Failed,
+        }
+    }
+}
+
+/// What [build_target] does with a target.
+enum Plan {
+    /// The target is not built.
+    Skip(BuildOutcome),
+    /// The target is built, recording the given inputs afterwards.
+    Build(Option<String>),
+}
+
+/// Decide whether a target has to be built.
+///
+/// Targets enabled as [TargetEnabled::Depend] reuse the last good artifact if there is one.
+fn plan_build(target: Target, enabled: TargetEnabled, params: &BuildParams) -> Result<Plan> {
+    if params.prebuilt_targets.contains(&target) {
+        BuildState::record_prebuilt(target)?;
+        return Ok(Plan::Skip(BuildOutcome::Prebuilt));
+    }
+
+    let inputs = BuildState::inputs(target, params.native_image)?;
+
+    if !params.force {
+        if let Some(inputs) = &inputs
+            && BuildState::is_up_to_date(target, inputs)
+        {
+            return Ok(Plan::Skip(BuildOutcome::UpToDate));
+        }
+        if enabled == TargetEnabled::Depend
+            && BuildState::load(target).is_some_and(|x| x.is_intact(target))
+        {
+            return Ok(Plan::Skip(BuildOutcome::Reused));
+        }
+    }
+
+    Ok(Plan::Build(inputs))
+}
+
+/// Build a target [planned](plan_build) to be built and record its `inputs`.
+fn run_build(
+    target: Target,
+    inputs: Option<String>,
+    x: &mut dyn TargetImpl,
+    deps: Deps<'_>,
+    params: &mut BuildParams,
+) -> Result<BuildOutcome> {
+    let Some(inputs) = inputs else {
+        x.build(deps, params)?;
+        return Ok(BuildOutcome::Built);
+    };
+
+    // Forget the record first, so a failed build is never considered up to date.
+    BuildState::forget(target)?;
+    x.build(deps, params)?;
+    BuildState::record(target, inputs)?;
+    Ok(BuildOutcome::Built)
+}
+
+/// Build a single target unless it's up to date.
+///
+/// Targets enabled as [TargetEnabled::Depend] reuse the last good artifact if there is one.
+fn build_target(
+    target: Target,
+    enabled: TargetEnabled,
+    x: &mut dyn TargetImpl,
+    deps: Deps<'_>,
+    params: &mut BuildParams,
+) -> Result<BuildOutcome> {
+    match plan_build(target, enabled, params)? {
+        Plan::Skip(outcome) => Ok(outcome),
+        Plan::Build(inputs) => run_build(target, inputs, x, deps, params),
+    }
+}
+
+/// A target built by a thread of [Targets::build_all].
+struct Job<'a> {
+    target: Target,
+    enabled: TargetEnabled,
+    x: &'a mut dyn TargetImpl,
+    deps: Deps<'a>,
+    params: BuildParams,
+    log_path: PathBuf,
+}
+
+/// A target built by [build_jobs], with how long it took and the result.
+type Finished<'a> = (Job<'a>, Duration, Result<BuildOutcome>);
+
+/// Build `jobs` and send each one back through `tx` once it is done.
+///
+/// If more than one of them has to be built, their [gradle
+/// tasks](TargetImplStatic::gradle_tasks) are run in one gradle invocation first, with the
+/// output going into the log of the first one.
+fn build_jobs<'a>(jobs: Vec<Job<'a>>, tx: &mpsc::Sender<Finished<'a>>) {
+    fn catch<T>(target: Target, f: impl FnOnce() -> Result<T>) -> Result<T> {
+        panic::catch_unwind(AssertUnwindSafe(f))
+            .unwrap_or_else(|_| Err(Error::other("build thread panicked")))
+            .within(target, Phase::Build)
+    }
+    let started = Instant::now();
+    let send = |job: Job<'a>, result| {
+        tx.send((job, started.elapsed(), result)).unwrap();
+    };
+
+    let mut planned = vec![];
+    for job in jobs {
+        match catch(job.target, || {
+            plan_build(job.target, job.enabled, &job.params)
+        }) {
+            Ok(Plan::Build(inputs)) => planned.push((job, inputs)),
+            Ok(Plan::Skip(outcome)) => send(job, Ok(outcome)),
+            Err(why) => send(job, Err(why)),
+        }
+    }
+
+    if let [(first, _), rest @ ..] = &mut planned[..]
+        && !rest.is_empty()
+    {
+        for (job, _) in rest.iter_mut() {
+            if let Some(log) = &mut job.params.log {
+                _ = writeln!(
+                    log,
+                    "gradle tasks run together with {}, see {}",
+                    first.target.name(),
+                    first.log_path.display()
+                );
+            }
+        }
+        let tasks: Vec<&str> = std::iter::once(&*first)
+            .chain(rest.iter().map(|x| &x.0))
+            .flat_map(|x| x.target.gradle_tasks().iter().copied())
+            .collect();
+        let result = first
+            .params
+            .gradle()
+            .arg("--parallel")
+            .args(tasks)
+            .run()
+            .within(first.target, Phase::Build);
+        if let Err(why) = result {
+            let log_path = first.log_path.display().to_string();
+            let mut planned = planned.into_iter();
+            send(planned.next().unwrap().0, Err(why));
+            for (job, _) in planned {
+                let why = Err(Error::other(format!("gradle failed, see {log_path}")));
+                let result = why.within(job.target, Phase::Build);
+                send(job, result);
+            }
+            return;
+        }
+        for (job, _) in &mut planned {
+            job.params.gradle_tasks_run = true;
+        }
+    }
+
+    for (mut job, inputs) in planned {
+        let result = catch(job.target, || {
+            run_build(job.target, inputs, &mut *job.x, job.deps, &mut job.params)
+        });
+        send(job, result);
     }
 }
 
@@ -451,27 +902,43 @@ macro_rules! targets {
 
         /// List of targets.
         #[derive(Default, PartialEq, Eq, Clone, Copy)]
-        pub struct TargetList {$(
-            $(#[$doc])*
-            $name: TargetEnabled,
-        )*}
+        pub struct TargetList {
+            $(
+                $(#[$doc])*
+                $name: TargetEnabled,
+            )*
+            /// Do not enable dependencies of dependencies.
+            shallow: bool,
+        }
         impl TargetList {
             pub fn set_build(&mut self, target: Target) {
                 match target {$(
                     Target::$enumname => {
+                        let was = self.$name;
                         self.$name = TargetEnabled::Build;
-                        $name::Impl::depends(self);
+                        if was == TargetEnabled::No {
+                            $name::Impl::depends(self);
+                        }
                     }
                 )*}
             }
             pub fn set_depend(&mut self, target: Target) {
                 match target {$(
                     Target::$enumname => {
+                        let was = self.$name;
                         self.$name.upgrade(TargetEnabled::Depend);
-                        $name::Impl::depends(self);
+                        if was == TargetEnabled::No && !self.shallow {
+                            $name::Impl::depends(self);
+                        }
                     }
                 )*}
             }
+            /// Obtain the enabled state of a target.
+            pub fn get(&self, target: Target) -> TargetEnabled {
+                match target {$(
+                    Target::$enumname => self.$name,
+                )*}
+            }
         }
 
         /// Named target.
@@ -481,11 +948,147 @@ macro_rules! targets {
             $enumname,
         )*}
         impl Target {
+            /// All targets in declaration order.
+            pub const ALL: &[Target] = &[$(Target::$enumname),*];
+
             pub fn flags(&self) -> TargetFlags {
                 match self {
                     $(Self::$enumname => $name::Impl::flags(),)*
                 }
             }
+
+            /// Name of the target as accepted on the command line.
+            pub const fn name(&self) -> &'static str {
+                match self {
+                    $(Self::$enumname => stringify!($name),)*
+                }
+            }
+
+            /// Repositories this target clones into the workspace.
+            pub fn repos(&self) -> &'static [Repo] {
+                match self {
+                    $(Self::$enumname => $name::Impl::repos(),)*
+                }
+            }
+
+            /// Tasks of the workspace gradle build that build this target.
+            pub fn gradle_tasks(&self) -> &'static [&'static str] {
+                match self {
+                    $(Self::$enumname => $name::Impl::gradle_tasks(),)*
+                }
+            }
+
+            /// Paths (relative to the workspace root) created by this target.
+            pub fn owned_paths(&self) -> &'static [&'static str] {
+                match self {
+                    $(Self::$enumname => $name::Impl::owned_paths(),)*
+                }
+            }
+
+            /// Paths (relative to the workspace root) the build depends on.
+            pub fn inputs(&self) -> &'static [&'static str] {
+                match self {
+                    $(Self::$enumname => $name::Impl::inputs(),)*
+                }
+            }
+
+            /// Paths (relative to the workspace root) produced by the build.
+            pub fn artifacts(&self) -> &'static [&'static str] {
+                match self {
+                    $(Self::$enumname => $name::Impl::artifacts(),)*
+                }
+            }
+
+            /// Direct dependencies of this target.
+            pub fn dependencies(&self) -> Vec<Target> {
+                let mut list = TargetList {
+                    shallow: true,
+                    ..Default::default()
+                };
+                match self {
+                    $(Self::$enumname => $name::Impl::depends(&mut list),)*
+                }
+                Self::ALL
+                    .iter()
+                    .copied()
+                    .filter(|x| list.get(*x) != TargetEnabled::No)
+                    .collect()
+            }
+
+            /// Dependencies of this target, including dependencies of dependencies.
+            pub fn transitive_dependencies(&self) -> Vec<Target> {
+                let mut list = self.dependencies();
+                let mut i = 0;
+                while i < list.len() {
+                    for dep in list[i].dependencies() {
+                        if !list.contains(&dep) {
+                            list.push(dep);
+                        }
+                    }
+                    i += 1;
+                }
+                list
+            }
+
+            /// All targets ordered so that every target comes after its dependencies.
+            ///
+            /// Targets that do not depend on each other keep declaration order.
+            ///
+            /// Returns the cycle if targets depend on each other.
+            pub fn build_order() -> Result<Vec<Target>, Vec<Target>> {
+                #[derive(Clone, Copy, PartialEq, Eq)]
+                enum Mark {
+                    None,
+                    Visiting,
+                    Done,
+                }
+
+                fn visit(
+                    target: Target,
+                    marks: &mut [Mark],
+                    stack: &mut Vec<Target>,
+                    order: &mut Vec<Target>,
+                ) -> Result<(), Vec<Target>> {
+                    let i = target as usize;
+                    match marks[i] {
+                        Mark::Done => return Ok(()),
+                        Mark::Visiting => {
+                            let start = stack.iter().position(|x| *x == target).unwrap();
+                            let mut cycle = stack[start..].to_vec();
+                            cycle.push(target);
+                            return Err(cycle);
+                        }
+                        Mark::None => (),
+                    }
+
+                    marks[i] = Mark::Visiting;
+                    stack.push(target);
+                    for dep in target.dependencies() {
+                        visit(dep, marks, stack, order)?;
+                    }
+                    stack.pop();
+                    marks[i] = Mark::Done;
+                    order.push(target);
+
+                    Ok(())
+                }
+
+                let mut marks = [Mark::None; Self::ALL.len()];
+                let mut stack = vec![];
+                let mut order = Vec::with_capacity(Self::ALL.len());
+                for target in Self::ALL {
+                    visit(*target, &mut marks, &mut stack, &mut order)?;
+                }
+                Ok(order)
+            }
+
+            /// Same as [`Target::build_order`], but reports a cycle as an [Error].
+            fn checked_build_order() -> Result<Vec<Target>> {
+                Self::build_order().map_err(|cycle| {
+                    let cycle: Vec<_> = cycle.iter().map(|x| x.name()).collect();
+                    Error::other(format!("dependency cycle between targets: {}", cycle.join(" -> ")))
+                })
+            }
         }
         impl FromStr for Target {
             type Err = ();
@@ -502,6 +1105,25 @@ macro_rules! targets {
         pub struct Targets<'a> {
             $(pub $name: Option<BorrowedMut<'a, $name::Impl>>,)*
         }
+
+        /// Built dependencies of a target being built.
+        #[allow(unused)]
+        #[derive(Clone, Copy)]
+        pub struct Deps<'a> {
+            $(pub $name: Option<&'a $name::Impl>,)*
+        }
+        impl<'a> Deps<'a> {
+            /// Dependencies of `target` among the `built` targets.
+            fn new(target: Target, built: &HashMap<Target, &'a dyn TargetImpl>) -> Self {
+                let deps = target.transitive_dependencies();
+                Self {
+                    $($name: built
+                        .get(&Target::$enumname)
+                        .filter(|_| deps.contains(&Target::$enumname))
+                        .and_then(|x| (*x as &dyn Any).downcast_ref()),)*
+                }
+            }
+        }
         impl<'a> Targets<'a> {
             #[allow(unused)]
             pub fn target(&self, target: Target) -> Option<&dyn TargetImpl> {
@@ -515,6 +1137,17 @@ macro_rules! targets {
                 )*}
             }
 
+            /// Separate mutable references to every initialized target.
+            fn split_mut(&mut self) -> HashMap<Target, &mut dyn TargetImpl> {
+                let mut targets = HashMap::new();
+                $(
+                    if let Some(x) = &mut self.$name {
+                        targets.insert(Target::$enumname, x.as_mut() as &mut dyn TargetImpl);
+                    }
+                )*
+                targets
+            }
+
             pub fn target_deps<'b>(&mut self, target: Target) -> (Option<&'b mut dyn TargetImpl>, Targets<'b>)
             where 'a: 'b
             { unsafe {
@@ -541,10 +1174,52 @@ macro_rules! targets {
                 )
             } }
 
-            pub fn init_all(&mut self, env: EnvTy, recipe: &mut TargetList, params: &mut InitParams) {
-                $(
-                    self.$name = 'a: {
+            pub fn init_all(
+                &mut self,
+                env: EnvTy,
+                recipe: &mut TargetList,
+                params: &mut InitParams,
+                report: &mut Report,
+            ) -> Result<()> {
+                let order = Target::checked_build_order()?;
+                for target in &order {
+                    let started = Instant::now();
+                    match self.init(*target, env, recipe, params).within(*target, Phase::Init) {
+                        Ok(Some(status)) => report.step(*target, Phase::Init, status, started.elapsed()),
+                        Ok(None) => {}
+                        Err(why) => {
+                            report.step(*target, Phase::Init, Status::Failed, started.elapsed());
+                            return Err(why);
+                        }
+                    }
+                }
+                for target in &order {
+                    match target {$(
+                        Target::$enumname => $name::Impl::postinit(
+                            recipe.$name,
+                            self.target_deps(Target::$enumname).1,
+                            params,
+                        ).within(Target::$enumname, Phase::Init)?,
+                    )*}
+                }
+                Ok(())
+            }
+
+            /// Initialize a single target. Dependencies must be initialized first.
+            ///
+            /// Returns how the target was initialized, or `None` if it's not enabled.
+            fn init(
+                &mut self,
+                target: Target,
+                env: EnvTy,
+                recipe: &mut TargetList,
+                params: &mut InitParams,
+            ) -> Result<Option<Status>> {
+                let mut status = Some(Status::Cached);
+                match target {$(
+                    Target::$enumname => self.$name = 'a: {
                         if recipe.$name == TargetEnabled::No {
+                            status = None;
                             break 'a None;
                         }
 
@@ -553,7 +1228,7 @@ macro_rules! targets {
                                 recipe.$name,
                                 self.target_deps(Target::$enumname).1,
                                 params,
-                            ) {
+                            )? {
                                 Some(x) => break 'a Some(BorrowedMut::new_owned(x)),
                                 None => true,
                             } {
@@ -561,7 +1236,22 @@ macro_rules! targets {
                                 recipe.$name,
                                 self.target_deps(Target::$enumname).1,
                                 params,
-                            ) {
+                            )? {
+                                break 'a Some(BorrowedMut::new_owned(x));
+                            }
+
+                            if recipe.$name == TargetEnabled::Depend
+                                && params.prebuilt
+                                && !params.has_git_ref(Target::$enumname)
+                                && !patches::has_patches(Target::$enumname)
+                                && let Some(x) = $name::Impl::initialize_prebuilt(
+                                    recipe.$name,
+                                    self.target_deps(Target::$enumname).1,
+                                    params,
+                                )?
+                            {
+                                params.prebuilt_targets.push(Target::$enumname);
+                                status = Some(Status::Downloaded);
                                 break 'a Some(BorrowedMut::new_owned(x));
                             }
 
@@ -575,51 +1265,189 @@ macro_rules! targets {
                                 if stdin().lines().next()
                                     .is_some_and(|x|
                                         x.is_ok_and(|x| !x.to_lowercase().starts_with("y"))) {
-                                    exit(1);
+                                    return Err(Error::other("installation declined"));
                                 }
                             }
 
+                            status = Some(Status::Built);
                             Some(BorrowedMut::new_owned($name::Impl::initialize_local(
                                 recipe.$name,
                                 self.target_deps(Target::$enumname).1,
                                 params,
-                            )))
+                            )?))
                         } else {
                             unreachable!();
                         }
+                    },
+                )*}
+                if status.is_some() {
+                    for repo in target.repos() {
+                        if let Some(git_ref) = params.git_ref(repo) {
+                            git::checkout_ref(repo, git_ref)?;
+                        }
+                        patches::apply(repo)?;
+                    }
+                }
+                Ok(status)
+            }
+
+            /// Build all enabled targets.
+            ///
+            /// Up to `jobs` targets are built at the same time, each one starting once all
+            /// of its dependencies are built, except that only one [gradle](TargetFlags::gradle)
+            /// target is built at a time. Built targets are only accessed through shared
+            /// references. With more than one job, output of each target is written into
+            /// [BUILD_LOG_DIR] and printed once the target is done.
+            pub fn build_all(
+                &mut self,
+                recipe: &TargetList,
+                params: &mut BuildParams,
+                jobs: usize,
+                report: &mut Report,
+            ) -> Result<()> {
+                let mut pending: Vec<Target> = Target::checked_build_order()?
+                    .into_iter()
+                    .filter(|x| self.target(*x).is_some())
+                    .collect();
+                let mut unbuilt = self.split_mut();
+                let mut built: HashMap<Target, &dyn TargetImpl> = HashMap::new();
+
+                if jobs <= 1 {
+                    for target in pending {
+                        let x = unbuilt.remove(&target).unwrap();
+                        let started = Instant::now();
+                        let result = build_target(target, recipe.get(target), x, Deps::new(target, &built), params)
+                            .within(target, Phase::Build);
+                        report.step(target, Phase::Build, BuildOutcome::status(&result), started.elapsed());
+                        let outcome = result?;
+                        if outcome != BuildOutcome::Built {
+                            eprintln!("==> {}: {}", target.name(), outcome.describe());
+                        }
+                        built.insert(target, x);
+                    }
+                    return Ok(());
+                }
+
+                crate::fs2::create_dir_all(BUILD_LOG_DIR)?;
+
+                let mut running: Vec<Target> = vec![];
+                let mut failed = None;
+                let (tx, rx) = mpsc::channel();
+
+                thread::scope(|scope| loop {
+                    while running.len() < jobs && failed.is_none() {
+                        // Gradle invocations share the `.gradle` caches and build directories of
+                        // the workspace build and its included builds, a second one would only
+                        // wait on their locks. Ready targets with gradle tasks are built by one
+                        // invocation instead, see `build_jobs`.
+                        let gradle_running = running.iter().any(|x| x.flags().gradle);
+                        let ready = |x: &Target| {
+                            x.dependencies()
+                                .iter()
+                                .all(|dep| !pending.contains(dep) && !running.contains(dep))
+                                && !(gradle_running && x.flags().gradle)
+                        };
+                        let Some(i) = pending.iter().position(ready) else {
+                            break;
+                        };
+                        let mut batch = vec![pending[i]];
+                        if !pending[i].gradle_tasks().is_empty() {
+                            batch = pending
+                                .iter()
+                                .copied()
+                                .filter(|x| ready(x) && !x.gradle_tasks().is_empty())
+                                .collect();
+                        }
+                        pending.retain(|x| !batch.contains(x));
+
+                        let mut batch_jobs = vec![];
+                        for target in batch {
+                            let log_path = Path::new(BUILD_LOG_DIR).join(format!("{}.log", target.name()));
+                            let log = match crate::fs2::create(&log_path).within(target, Phase::Build) {
+                                Ok(x) => x,
+                                Err(why) => {
+                                    failed = Some(why);
+                                    break;
+                                }
+                            };
+                            eprintln!("==> {}: building", target.name());
+                            batch_jobs.push(Job {
+                                target,
+                                enabled: recipe.get(target),
+                                x: unbuilt.remove(&target).unwrap(),
+                                deps: Deps::new(target, &built),
+                                params: params.fork(log),
+                                log_path,
+                            });
+                            running.push(target);
+                        }
+
+                        let tx = tx.clone();
+                        thread::Builder::new()
+                            .name(batch_jobs.iter().map(|x| x.target.name()).collect::<Vec<_>>().join(","))
+                            .spawn_scoped(scope, move || build_jobs(batch_jobs, &tx))
+                            .expect("failed to spawn build thread");
+                    }
+
+                    if running.is_empty() {
+                        break;
+                    }
+
+                    let (Job { target, x, params: job_params, log_path, .. }, elapsed, result) =
+                        rx.recv().unwrap();
+                    running.retain(|x| *x != target);
+                    built.insert(target, x);
+                    params.merge(job_params);
+                    report.step(target, Phase::Build, BuildOutcome::status(&result), elapsed);
+
+                    let mut stderr = stderr().lock();
+                    let state = match &result {
+                        Ok(x) => x.describe(),
+                        Err(_) => "failed",
                     };
-                )*
-                $(
-                    $name::Impl::postinit(
-                        recipe.$name,
-                        self.target_deps(Target::$enumname).1,
-                        params,
+                    _ = writeln!(
+                        stderr,
+                        "==> {}: {state} in {:.1}s ({})",
+                        target.name(),
+                        elapsed.as_secs_f32(),
+                        log_path.display(),
                     );
-                )*
-            }
+                    if let Ok(mut log) = File::open(&log_path) {
+                        _ = io::copy(&mut log, &mut stderr);
+                    }
+                    drop(stderr);
 
-            pub fn build_all(&mut self, params: &mut BuildParams) {
-                $(
-                    if let (Some(x), targets) = self.target_deps(Target::$enumname) {
-                        x.build(targets, params);
+                    if let Err(why) = result {
+                        failed.get_or_insert(why);
                     }
-                )*
+                });
+
+                match failed {
+                    Some(why) => Err(why),
+                    None => Ok(()),
+                }
             }
 
-            pub fn run_init_all(&mut self, params: &mut RunParams) {
-                $(
-                    if let (Some(x), targets) = self.target_deps(Target::$enumname) {
-                        x.run_init(targets, params);
+            pub fn run_init_all(&mut self, params: &mut RunParams, report: &mut Report) -> Result<()> {
+                for target in Target::checked_build_order()? {
+                    if let (Some(x), targets) = self.target_deps(target) {
+                        let started = Instant::now();
+                        let result = x.run_init(targets, params).within(target, Phase::RunInit);
+                        let status = if result.is_ok() { Status::Built } else { Status::Failed };
+                        report.step(target, Phase::RunInit, status, started.elapsed());
+                        result?;
                     }
-                )*
+                }
+                Ok(())
             }
 
-            pub fn run_all(&mut self, params: &mut RunParams) {
-                $(
-                    if let (Some(x), targets) = self.target_deps(Target::$enumname) {
-                        x.run(targets, params);
+            pub fn run_all(&mut self, params: &mut RunParams) -> Result<()> {
+                for target in Target::checked_build_order()? {
+                    if let (Some(x), targets) = self.target_deps(target) {
+                        x.run(targets, params).within(target, Phase::Run)?;
                     }
-                )*
+                }
+                Ok(())
             }
         }
 
@@ -660,34 +1488,27 @@ macro_rules! simple_server_target {
             }
         }
         impl super::TargetImpl for Impl {
-            fn build(&mut self, deps: super::Targets<'_>, params: &mut super::BuildParams) {
+            fn build(&mut self, deps: super::Deps<'_>, params: &mut super::BuildParams) -> crate::error::Result<()> {
+                use crate::error::{CommandExt, Error};
+
                 // On Forts side it should copy resulting jar into `.bin/$jar.jar`.
-                if !params
-                    .gradle()
-                    .arg(concat!(":", $target, ":build"))
-                    .status()
-                    .unwrap()
-                    .success()
-                {
-                    panic!(concat!("building ", $jar, " failed"));
-                }
+                params.run_gradle_tasks(<Self as super::TargetImplStatic>::gradle_tasks())?;
 
                 if params.native_image {
+                    let _lock = super::NATIVE_IMAGE_LOCK.lock().unwrap_or_else(|x| x.into_inner());
+
                     println!("Merging jars! (will take a while!)");
 
                     let mut output = zip::ZipWriter::new(
-                        ::std::fs::File::create(".cache/tools/buildscript/tmp.jar")
-                            .expect("failed to open '.cache/tools/buildscript/tmp.jar'"),
+                        crate::fs2::create(".cache/tools/buildscript/tmp.jar")?,
                     );
 
                     let mut buffer = vec![0; 1024 * 1024 * 16];
 
                     {
                         let mut input = zip::ZipArchive::new(
-                            ::std::fs::File::open(".bin/server-release.jar")
-                                .expect("failed to open '.bin/server-release.jar'"),
-                        )
-                        .expect("failed to open zip archive");
+                            crate::fs2::open(".bin/server-release.jar")?,
+                        )?;
                         for name in input
                             .file_names()
                             .map(|x| x.to_owned().into_boxed_str())
@@ -702,9 +1523,8 @@ macro_rules! simple_server_target {
                                     &name,
                                     ::zip::write::FileOptions::DEFAULT
                                         .compression_method(zip::CompressionMethod::Deflated),
-                                )
-                                .unwrap();
-                            let mut reader = Some(input.by_name(&name).unwrap());
+                                )?;
+                            let mut reader = Some(input.by_name(&name)?);
                             let mut pos = 0usize;
 
                             while pos != 0 || reader.is_some() {
@@ -714,7 +1534,7 @@ macro_rules! simple_server_target {
                                             Ok(0) => _ = reader.take(),
                                             Ok(l) => pos += l,
                                             Err(why) => {
-                                                panic!("Reading of {name:?} failed: {why:#?}")
+                                                return Err(Error::other(format!("reading of {name:?} failed: {why}")));
                                             }
                                         }
                                     }
@@ -725,16 +1545,16 @@ macro_rules! simple_server_target {
                                             &mut buffer[..pos],
                                         ) {
                                             Ok(0) => {
-                                                panic!(
-                                                    "Could not write into {name:?}: unexpected EOF"
-                                                )
+                                                return Err(Error::other(format!(
+                                                    "could not write into {name:?}: unexpected EOF"
+                                                )));
                                             }
                                             Ok(l) => {
                                                 buffer.copy_within(l..pos, 0);
                                                 pos -= l;
                                             }
                                             Err(why) => {
-                                                panic!("Could not write into {name:?}: {why:#?}")
+                                                return Err(Error::other(format!("could not write into {name:?}: {why}")));
                                             }
                                         }
                                     }
@@ -747,11 +1567,7 @@ macro_rules! simple_server_target {
                         (".bin/CorePlugin.jar", "coreplugin/"),
                         (concat!(".bin/", $jar, ".jar"), concat!($prefix, "/")),
                     ] {
-                        let mut input = ::zip::ZipArchive::new(match ::std::fs::File::open(name) {
-                            Ok(x) => x,
-                            Err(why) => panic!("failed to open {name:?}: {why:#?}"),
-                        })
-                        .expect("failed to open zip archive");
+                        let mut input = ::zip::ZipArchive::new(crate::fs2::open(name)?)?;
                         for name in input
                             .file_names()
                             .map(|x| x.to_owned().into_boxed_str())
@@ -781,9 +1597,8 @@ macro_rules! simple_server_target {
                                     .as_ref(),
                                     ::zip::write::FileOptions::DEFAULT
                                         .compression_method(zip::CompressionMethod::Deflated),
-                                )
-                                .unwrap();
-                            let mut reader = Some(input.by_name(&name).unwrap());
+                                )?;
+                            let mut reader = Some(input.by_name(&name)?);
                             let mut pos = 0usize;
 
                             while pos != 0 || reader.is_some() {
@@ -793,7 +1608,7 @@ macro_rules! simple_server_target {
                                             Ok(0) => _ = reader.take(),
                                             Ok(l) => pos += l,
                                             Err(why) => {
-                                                panic!("Reading of {name:?} failed: {why:#?}")
+                                                return Err(Error::other(format!("reading of {name:?} failed: {why}")));
                                             }
                                         }
                                     }
@@ -804,16 +1619,16 @@ macro_rules! simple_server_target {
                                             &mut buffer[..pos],
                                         ) {
                                             Ok(0) => {
-                                                panic!(
-                                                    "Could not write into {name:?}: unexpected EOF"
-                                                )
+                                                return Err(Error::other(format!(
+                                                    "could not write into {name:?}: unexpected EOF"
+                                                )));
                                             }
                                             Ok(l) => {
                                                 buffer.copy_within(l..pos, 0);
                                                 pos -= l;
                                             }
                                             Err(why) => {
-                                                panic!("Could not write into {name:?}: {why:#?}")
+                                                return Err(Error::other(format!("could not write into {name:?}: {why}")));
                                             }
                                         }
                                     }
@@ -827,18 +1642,16 @@ macro_rules! simple_server_target {
                             "META-INF/mods",
                             ::zip::write::FileOptions::DEFAULT
                                 .compression_method(zip::CompressionMethod::Stored),
-                        )
-                        .unwrap();
+                        )?;
                     ::std::io::Write::write_all(
                         &mut output,
                         concat!("coreplugin\n", $prefix).as_ref(),
-                    )
-                    .unwrap();
+                    )?;
 
-                    output.finish().expect("failed to finish 'tmp.jar'");
+                    output.finish()?;
 
                     ::std::fs::remove_dir_all(".cache/tools/buildscript/genenv").ok();
-                    crate::fs2::create_dir_all(".cache/tools/buildscript/genenv/config").unwrap();
+                    crate::fs2::create_dir_all(".cache/tools/buildscript/genenv/config")?;
 
                     crate::fs2::write(
                         ".cache/tools/buildscript/genenv/config/corePlugin.toml",
@@ -846,15 +1659,13 @@ macro_rules! simple_server_target {
                             "serverName = {:?}\ngamemode = {:?}\nsharedConfigPath = {:?}",
                             $server, $server, "config/sharedConfig.toml"
                         ),
-                    )
-                    .unwrap();
+                    )?;
                     crate::fs2::write(
                         ".cache/tools/buildscript/genenv/config/sharedConfig.toml",
                         "serverIp = \"127.0.0.1\"\nrabbitMqUrl=\"\"\nsurrealDbUrl=\"\"",
-                    )
-                    .unwrap();
+                    )?;
 
-                    let code = params
+                    params
                         .cmd(
                             deps.java
                                 .as_ref()
@@ -866,17 +1677,9 @@ macro_rules! simple_server_target {
                         .arg("-jar")
                         .arg(crate::current_dir().join(".cache/tools/buildscript/tmp.jar"))
                         .current_dir(crate::current_dir().join(".cache/tools/buildscript/genenv"))
-                        .spawn()
-                        .unwrap()
-                        .wait()
-                        .unwrap()
-                        .code()
-                        .unwrap_or(-1);
-                    if code != 0 {
-                        panic!("'java' exited with error code {code}");
-                    }
+                        .run()?;
 
-                    let code = params
+                    params
                         .cmd(
                             deps.java
                                 .as_ref()
@@ -887,26 +1690,17 @@ macro_rules! simple_server_target {
                         .arg("buildscript/src/targets/NiMetadata.java")
                         .arg("-d")
                         .arg(".cache/tools/buildscript")
-                        .spawn()
-                        .unwrap()
-                        .wait()
-                        .unwrap()
-                        .code()
-                        .unwrap_or(-1);
-                    if code != 0 {
-                        panic!("'java' exited with error code {code}");
-                    }
+                        .run()?;
 
                     {
                         // Because zip is fucking ass
                         println!("Re-merging the archive! (will while take a)");
 
                         let mut reader = zip::ZipArchive::new(
-                            ::std::fs::File::open(".cache/tools/buildscript/tmp.jar").unwrap(),
-                        )
-                        .unwrap();
+                            crate::fs2::open(".cache/tools/buildscript/tmp.jar")?,
+                        )?;
                         let mut writer = zip::ZipWriter::new(
-                            ::std::fs::File::create(".cache/tools/buildscript/tmp2.jar").unwrap(),
+                            crate::fs2::create(".cache/tools/buildscript/tmp2.jar")?,
                         );
 
                         let names: Vec<_> = reader.file_names().map(String::from).collect();
@@ -916,26 +1710,20 @@ macro_rules! simple_server_target {
                             }
 
                             if name != "mindustry/NiMetadata.class" {
-                                writer
-                                    .raw_copy_file(reader.by_name(&name).unwrap())
-                                    .unwrap();
+                                writer.raw_copy_file(reader.by_name(&name)?)?;
                                 continue;
                             }
 
-                            let mut reader = Some(
-                                ::std::fs::File::open(
-                                    ".cache/tools/buildscript/mindustry/NiMetadata.class",
-                                )
-                                .unwrap(),
-                            );
+                            let mut reader = Some(crate::fs2::open(
+                                ".cache/tools/buildscript/mindustry/NiMetadata.class",
+                            )?);
 
                             writer
                                 .start_file(
                                     &name,
                                     ::zip::write::FileOptions::DEFAULT
                                         .compression_method(zip::CompressionMethod::Stored),
-                                )
-                                .unwrap();
+                                )?;
 
                             let mut pos = 0usize;
 
@@ -946,7 +1734,7 @@ macro_rules! simple_server_target {
                                             Ok(0) => _ = reader.take(),
                                             Ok(l) => pos += l,
                                             Err(why) => {
-                                                panic!("Reading of {name:?} failed: {why:#?}")
+                                                return Err(Error::other(format!("reading of {name:?} failed: {why}")));
                                             }
                                         }
                                     }
@@ -957,16 +1745,16 @@ macro_rules! simple_server_target {
                                             &mut buffer[..pos],
                                         ) {
                                             Ok(0) => {
-                                                panic!(
-                                                    "Could not write into {name:?}: unexpected EOF"
-                                                )
+                                                return Err(Error::other(format!(
+                                                    "could not write into {name:?}: unexpected EOF"
+                                                )));
                                             }
                                             Ok(l) => {
                                                 buffer.copy_within(l..pos, 0);
                                                 pos -= l;
                                             }
                                             Err(why) => {
-                                                panic!("Could not write into {name:?}: {why:#?}")
+                                                return Err(Error::other(format!("could not write into {name:?}: {why}")));
                                             }
                                         }
                                     }
@@ -980,17 +1768,16 @@ macro_rules! simple_server_target {
                                     "META-INF/native-image/mindurka/workspace/reachability-metadata.json",
                                     ::zip::write::FileOptions::DEFAULT
                                         .compression_method(zip::CompressionMethod::Stored),
-                                )
-                                .unwrap();
+                                )?;
 
-                            ::std::io::Write::write_all(&mut writer, x.as_bytes()).unwrap();
+                            ::std::io::Write::write_all(&mut writer, x.as_bytes())?;
                         }
 
 
-                        writer.finish().unwrap();
+                        writer.finish()?;
                     }
 
-                    let code = {
+                    {
                         let mut cmd = params.cmd(
                                 deps.java
                                     .as_ref()
@@ -1021,7 +1808,7 @@ macro_rules! simple_server_target {
                                         .join(".cache/tools/buildscript/genenv/reflect-config.json")
                                         .display(),
                                     crate::current_dir()
-                                        .join("coreplugin/assets/reflect-config.json")
+                                        .join(super::checkout_path("coreplugin/assets/reflect-config.json"))
                                         .display()
                                 ))
                                 .arg(format!(
@@ -1040,20 +1827,14 @@ macro_rules! simple_server_target {
                             .arg("--enable-url-protocols=http")
                             .arg("-o")
                             .arg(crate::exe_path!(concat!(".bin/", $jar)))
-                            .spawn()
-                            .unwrap()
-                            .wait()
-                            .unwrap()
-                            .code()
-                            .unwrap_or(-1)
-                        };
-                    if code != 0 {
-                        panic!("'native-image' exited with error code {code}");
+                            .run()?;
                     }
                 }
+
+                Ok(())
             }
 
-            fn run_init(&mut self, deps: super::Targets<'_>, mut params: &mut super::RunParams) {
+            fn run_init(&mut self, deps: super::Targets<'_>, mut params: &mut super::RunParams) -> crate::error::Result<()> {
                 let root = ::std::path::Path::new(concat!(".run/", $dir));
 
                 if !params.native_image {
@@ -1118,7 +1899,7 @@ macro_rules! simple_server_target {
 
                 if params.native_image {
                     let mut cmd = params.cmd(
-                        std::fs::canonicalize(crate::exe_path!(concat!(".bin/", $jar))).unwrap(),
+                        crate::fs2::canonicalize(crate::exe_path!(concat!(".bin/", $jar)))?,
                     );
                     cmd.current_dir(root);
                     self.command = Some(cmd);
@@ -1130,14 +1911,16 @@ macro_rules! simple_server_target {
                     cmd.arg("-jar").arg(mindustry).current_dir(root);
                     self.command = Some(cmd);
                 }
+
+                Ok(())
             }
 
-            fn run(&mut self, deps: super::Targets<'_>, params: &mut super::RunParams) {
+            fn run(&mut self, deps: super::Targets<'_>, params: &mut super::RunParams) -> crate::error::Result<()> {
                 deps.mprocs.as_ref().unwrap().spawn_task(
                     params,
                     &mut self.command.take().unwrap(),
                     $server,
-                );
+                )
             }
         }
 
@@ -1147,11 +1930,51 @@ macro_rules! simple_server_target {
                 list.set_depend(super::Target::CorePlugin);
             }
 
+            fn flags() -> super::TargetFlags {
+                super::TargetFlags {
+                    gradle: true,
+                    ..Default::default()
+                }
+            }
+
+            fn gradle_tasks() -> &'static [&'static str] {
+                &[concat!(":", $target, ":build")]
+            }
+
+            fn repos() -> &'static [super::Repo] {
+                &[super::Repo {
+                    dir: $dir,
+                    remote: $repo,
+                }]
+            }
+
+            fn owned_paths() -> &'static [&'static str] {
+                &[
+                    concat!(".bin/", $jar, ".jar"),
+                    crate::exe_path!(concat!(".bin/", $jar)),
+                    concat!(".run/", $dir),
+                ]
+            }
+
+            fn inputs() -> &'static [&'static str] {
+                &[
+                    $dir,
+                    "build.gradle",
+                    "settings.gradle",
+                    "gradle.properties",
+                    "buildscript/assets/shared.settings.gradle",
+                ]
+            }
+
+            fn artifacts() -> &'static [&'static str] {
+                &[concat!(".bin/", $jar, ".jar")]
+            }
+
             fn initialize_host(
                 _: super::TargetEnabled,
                 _: super::Targets<'_>,
                 _: &mut super::InitParams,
-            ) -> Option<Self> {
+            ) -> crate::error::Result<Option<Self>> {
                 unimplemented!()
             }
 
@@ -1159,41 +1982,51 @@ macro_rules! simple_server_target {
                 _: super::TargetEnabled,
                 _: super::Targets<'_>,
                 _: &mut super::InitParams,
-            ) -> Option<Self> {
-                if ::std::fs::read_dir($dir).is_err() {
-                    return None;
+            ) -> crate::error::Result<Option<Self>> {
+                if ::std::fs::read_dir(super::checkout_path($dir)).is_err() {
+                    return Ok(None);
                 }
 
-                Some(Self::new(::std::fs::canonicalize($dir).unwrap()))
+                Ok(Some(Self::new(crate::fs2::canonicalize(super::checkout_path($dir))?)))
+            }
+
+            fn initialize_prebuilt(
+                _: super::TargetEnabled,
+                _: super::Targets<'_>,
+                params: &mut super::InitParams,
+            ) -> crate::error::Result<Option<Self>> {
+                const PREBUILT: crate::prebuilt::Prebuilt = crate::prebuilt::Prebuilt {
+                    name: $target,
+                    repo: $repo,
+                    artifact: concat!(".bin/", $jar, ".jar"),
+                    asset: concat!($jar, ".jar"),
+                    maven: None,
+                };
+
+                Ok(crate::prebuilt::fetch(&PREBUILT, params)?.then(|| Self::new(params.root.join(super::checkout_path($dir)))))
             }
 
             fn initialize_local(
                 _: super::TargetEnabled,
                 _: super::Targets<'_>,
                 params: &mut super::InitParams,
-            ) -> Self {
-                if !::std::process::Command::new("git")
-                    .arg("clone")
-                    .arg(params.git_backend.repo_url($repo))
-                    .arg(params.root.join($dir))
-                    .status()
-                    .unwrap()
-                    .success()
-                {
-                    panic!("failed to fetch repo");
+            ) -> crate::error::Result<Self> {
+                for repo in <Self as super::TargetImplStatic>::repos() {
+                    crate::git::clone(repo, &params.remotes)?;
                 }
 
-                Self::new(::std::fs::canonicalize($dir).unwrap())
+                Ok(Self::new(crate::fs2::canonicalize(super::checkout_path($dir))?))
             }
 
             fn postinit(
                 _: super::TargetEnabled,
                 _: super::Targets<'_>,
                 params: &mut super::InitParams,
-            ) {
-                if ::std::fs::read_dir($dir).is_ok() {
+            ) -> crate::error::Result<()> {
+                if ::std::fs::read_dir(super::checkout_path($dir)).is_ok() {
                     params.java_workspace_members.push($dir.into());
                 }
+                Ok(())
             }
         }
     };
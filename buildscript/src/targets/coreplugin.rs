@@ -7,14 +7,25 @@
 use std::{
     fs::{self, read_dir},
     path::PathBuf,
-    process::Command,
 };
 
-use crate::util::current_dir;
+use crate::{
+    error::Result,
+    fs2, git,
+    prebuilt::{self, Prebuilt},
+    util::current_dir,
+};
 
-use super::{Target, TargetImpl, TargetImplStatic};
+use super::{Repo, Target, TargetImpl, TargetImplStatic, checkout_path};
 
-// TODO: Download if enabled status is `Depend` instead of `Build`.
+/// Released CorePlugin jar, used when CorePlugin is only a dependency.
+const PREBUILT: Prebuilt = Prebuilt {
+    name: "coreplugin",
+    repo: "Darkdustry-Coders/CorePlugin",
+    artifact: ".bin/CorePlugin.jar",
+    asset: "CorePlugin.jar",
+    maven: None,
+};
 
 /// CorePlugin target implementation.
 pub struct Impl {
@@ -40,21 +51,9 @@ impl Impl {
 }
 
 impl TargetImpl for Impl {
-    fn build(&mut self, _: super::Targets<'_>, params: &mut super::BuildParams) {
+    fn build(&mut self, _: super::Deps<'_>, params: &mut super::BuildParams) -> Result<()> {
         // On CorePlugin side it should copy resulting jar into `.bin/CorePlugin.jar`.
-        if !params
-            .gradle()
-            .arg(":coreplugin:build")
-            .arg(":coreplugin:publishAllPublicationsToMavenRepository")
-            .arg(":coreplugin:processor:publishAllPublicationsToMavenRepository")
-            .arg(":coreplugin:buildExtras:publishAllPublicationsToMavenRepository")
-            .arg(":coreplugin:annotations:publishAllPublicationsToMavenRepository")
-            .status()
-            .unwrap()
-            .success()
-        {
-            panic!("building CorePlugin failed");
-        }
+        params.run_gradle_tasks(Self::gradle_tasks())
     }
 }
 
@@ -66,11 +65,55 @@ impl TargetImplStatic for Impl {
         list.set_depend(Target::Mindustry);
     }
 
+    fn flags() -> super::TargetFlags {
+        super::TargetFlags {
+            gradle: true,
+            ..Default::default()
+        }
+    }
+
+    fn gradle_tasks() -> &'static [&'static str] {
+        &[
+            ":coreplugin:build",
+            ":coreplugin:publishAllPublicationsToMavenRepository",
+            ":coreplugin:processor:publishAllPublicationsToMavenRepository",
+            ":coreplugin:buildExtras:publishAllPublicationsToMavenRepository",
+            ":coreplugin:annotations:publishAllPublicationsToMavenRepository",
+        ]
+    }
+
+    fn repos() -> &'static [Repo] {
+        &[Repo {
+            dir: "coreplugin",
+            remote: "Darkdustry-Coders/CorePlugin",
+        }]
+    }
+
+    fn owned_paths() -> &'static [&'static str] {
+        &[
+            ".bin/CorePlugin.jar",
+        ]
+    }
+
+    fn inputs() -> &'static [&'static str] {
+        &[
+            "coreplugin",
+            "build.gradle",
+            "settings.gradle",
+            "gradle.properties",
+            "buildscript/assets/shared.settings.gradle",
+        ]
+    }
+
+    fn artifacts() -> &'static [&'static str] {
+        &[".bin/CorePlugin.jar"]
+    }
+
     fn initialize_host(
         _: super::TargetEnabled,
         _: super::Targets<'_>,
         _: &mut super::InitParams,
-    ) -> Option<Self> {
+    ) -> Result<Option<Self>> {
         unimplemented!()
     }
 
@@ -78,36 +121,42 @@ impl TargetImplStatic for Impl {
         _: super::TargetEnabled,
         _: super::Targets<'_>,
         _: &mut super::InitParams,
-    ) -> Option<Self> {
-        if read_dir("coreplugin").is_err() {
-            return None;
+    ) -> Result<Option<Self>> {
+        if read_dir(checkout_path("coreplugin")).is_err() {
+            return Ok(None);
         }
 
-        Some(Self::new(fs::canonicalize("coreplugin").unwrap()))
+        Ok(Some(Self::new(fs2::canonicalize(checkout_path("coreplugin"))?)))
+    }
+
+    fn initialize_prebuilt(
+        _: super::TargetEnabled,
+        _: super::Targets<'_>,
+        params: &mut super::InitParams,
+    ) -> Result<Option<Self>> {
+        Ok(prebuilt::fetch(&PREBUILT, params)?.then(|| Self::new(params.root.join(checkout_path("coreplugin")))))
     }
 
     fn initialize_local(
         _: super::TargetEnabled,
         _: super::Targets<'_>,
         params: &mut super::InitParams,
-    ) -> Self {
-        if !Command::new("git")
-            .arg("clone")
-            .arg(params.git_backend.repo_url("Darkdustry-Coders/CorePlugin"))
-            .arg(params.root.join("coreplugin"))
-            .status()
-            .unwrap()
-            .success()
-        {
-            panic!("failed to fetch repo");
+    ) -> Result<Self> {
+        for repo in Self::repos() {
+            git::clone(repo, &params.remotes)?;
         }
 
-        Self::new(fs::canonicalize("coreplugin").unwrap())
+        Ok(Self::new(fs2::canonicalize(checkout_path("coreplugin"))?))
     }
 
-    fn postinit(_: super::TargetEnabled, _: super::Targets<'_>, params: &mut super::InitParams) {
-        if fs::read_dir("coreplugin").is_ok() {
+    fn postinit(
+        _: super::TargetEnabled,
+        _: super::Targets<'_>,
+        params: &mut super::InitParams,
+    ) -> Result<()> {
+        if fs::read_dir(checkout_path("coreplugin")).is_ok() {
             params.java_workspace_members.push("coreplugin".into());
         }
+        Ok(())
     }
 }
@@ -3,24 +3,58 @@
 //! This module autoinstalls coreutils in isolated environments.
 
 use std::{
-    fs::{self, read_dir},
-    path::{Path, PathBuf},
+    fs,
+    path::PathBuf,
     process::Command,
 };
 
-use crate::util::{self, download, find_executable, is_executable};
+use crate::{
+    error::{IoContext, Result},
+    fs2,
+    platform::{self, Platform},
+    tools::Tool,
+    util::{self, find_executable, is_executable},
+};
 
 use super::{TargetFlags, TargetImpl, TargetImplStatic};
 
-/// URL for the busybox binary (Linux x86_64 musl).
-const LINUX_BIN: &str = "https://busybox.net/downloads/binaries/1.35.0-x86_64-linux-musl/busybox";
+/// Busybox binary.
+///
+/// busybox.net only has static x86_64 builds of recent versions. On other platforms the
+/// coreutils of the host are used, unless `url` is set in `[tools.coreutils]`.
+pub const TOOL: Tool = Tool {
+    name: "coreutils",
+    version: "1.35.0",
+    url: "https://busybox.net/downloads/binaries/{version}-{platform}/busybox",
+    assets: &[(Platform::LinuxX86_64, "x86_64-linux-musl")],
+    files: &["busybox"],
+    sha256: &[],
+};
+
+/// Directory of the coreutils installed on the host, if any.
+fn host_dir() -> Option<PathBuf> {
+    if cfg!(unix) {
+        let path = find_executable("xargs")?;
+        let path = path.parent()?;
+        // Surely that's enough
+        for x in ["uname", "yes", "[", "cat", "touch"] {
+            if !is_executable(path.join(x)) {
+                return None;
+            }
+        }
+        Some(path.to_path_buf())
+    } else {
+        unimplemented!()
+    }
+}
 
 /// Coreutils target implementation using busybox.
 pub struct Impl(PathBuf);
 
 impl TargetImpl for Impl {
-    fn build(&mut self, _: super::Targets<'_>, params: &mut super::BuildParams) {
+    fn build(&mut self, _: super::Deps<'_>, params: &mut super::BuildParams) -> Result<()> {
         params.path.push(self.0.clone());
+        Ok(())
     }
 }
 
@@ -32,79 +66,88 @@ impl TargetImplStatic for Impl {
         }
     }
 
+    fn owned_paths() -> &'static [&'static str] {
+        &[
+            ".cache/tools/coreutils",
+        ]
+    }
+
     fn initialize_host(
         _: super::TargetEnabled,
         _: super::Targets<'_>,
         _: &mut super::InitParams,
-    ) -> Option<Self> {
-        if cfg!(unix) {
-            let path = find_executable("xargs")?;
-            let path = path.parent()?;
-            // Surely that's enough
-            for x in ["uname", "yes", "[", "cat", "touch"] {
-                if !is_executable(path.join(x)) {
-                    return None;
-                }
-            }
-            Some(Self(path.to_path_buf()))
-        } else {
-            unimplemented!()
-        }
+    ) -> Result<Option<Self>> {
+        Ok(host_dir().map(Self))
     }
 
     fn initialize_cached(
         _: super::TargetEnabled,
         _: super::Targets<'_>,
-        _: &mut super::InitParams,
-    ) -> Option<Self> {
-        if read_dir(".cache/tools/coreutils").is_ok() {
-            Some(Self(fs::canonicalize(".cache/tools/coreutils").unwrap()))
+        params: &mut super::InitParams,
+    ) -> Result<Option<Self>> {
+        let release = match params.release(&TOOL) {
+            Ok(x) => x,
+            // No busybox for the host, java and rabbitmq still build with its own coreutils.
+            Err(why) => {
+                let Some(path) = host_dir() else {
+                    return Err(why);
+                };
+                eprintln!(
+                    "coreutils: busybox has no release for {}, using {}",
+                    platform::host_name(),
+                    path.display()
+                );
+                return Ok(Some(Self(path)));
+            }
+        };
+        if TOOL.is_installed(&release) {
+            Ok(Some(Self(fs2::canonicalize(TOOL.dir())?)))
         } else {
-            None
+            Ok(None)
         }
     }
 
     fn initialize_local(
         _: super::TargetEnabled,
         _: super::Targets<'_>,
-        _: &mut super::InitParams,
-    ) -> Self {
-        eprintln!("Downloading coreutils...");
-        fs::create_dir_all(".cache/tools/coreutils").unwrap();
-        let path = fs::canonicalize(".cache/tools/coreutils").unwrap();
-        download(LINUX_BIN, ".cache/tools/coreutils/busybox");
-
-        #[cfg(unix)]
-        {
-            use std::os::unix::fs::PermissionsExt;
-            let metadata = fs::metadata(".cache/tools/coreutils/busybox").unwrap();
-            let mut permissions = metadata.permissions();
-            permissions.set_mode(0o700);
-            fs::set_permissions(".cache/tools/coreutils/busybox", permissions).unwrap();
-        }
+        params: &mut super::InitParams,
+    ) -> Result<Self> {
+        let release = params.release(&TOOL)?;
+        eprintln!("Downloading coreutils (busybox {})...", release.version);
+        let path = TOOL.install(&release, "busybox", |download, dir| {
+            let busybox = dir.join("busybox");
+            fs2::rename(download, &busybox)?;
 
-        let commands = String::from_utf8(
-            Command::new(".cache/tools/coreutils/busybox")
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                let metadata = fs2::metadata(&busybox)?;
+                let mut permissions = metadata.permissions();
+                permissions.set_mode(0o700);
+                fs::set_permissions(&busybox, permissions).at(&busybox)?;
+            }
+
+            // Busybox prints its usage and exits with an error without arguments.
+            let output = Command::new(&busybox)
                 .env("LANG", "C")
                 .output()
-                .unwrap()
-                .stdout,
-        )
-        .unwrap();
-        let commands = commands
-            .lines()
-            .map(|x| x.trim())
-            .skip_while(|x| !x.starts_with("Currently defined functions"))
-            .skip(1)
-            .flat_map(|x| x.split(',').map(str::trim).filter(|x| !x.is_empty()));
-
-        let coreutils = path.join("busybox");
-        for x in commands {
-            util::symlink_file(&coreutils, Path::new(".cache/tools/coreutils").join(x)).unwrap();
-        }
+                .at(&busybox)?;
+            let commands = String::from_utf8_lossy(&output.stdout);
+            let commands = commands
+                .lines()
+                .map(|x| x.trim())
+                .skip_while(|x| !x.starts_with("Currently defined functions"))
+                .skip(1)
+                .flat_map(|x| x.split(',').map(str::trim).filter(|x| !x.is_empty()));
 
-        Self(fs::canonicalize(".cache/tools/coreutils").unwrap())
-    }
+            // Relative links, so they still work once the directory is moved into place.
+            for x in commands {
+                let link = dir.join(x);
+                util::symlink_file("busybox", &link).at(link)?;
+            }
+            Ok(())
+        })?;
 
-    fn postinit(_: super::TargetEnabled, _: super::Targets<'_>, _: &mut super::InitParams) {}
+        Ok(Self(path))
+    }
 }
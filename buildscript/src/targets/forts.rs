@@ -15,7 +15,7 @@ simple_server_target!(
 
     fn setup_server(params) {
         params.run.link_global(
-            params.root.join("forts/assets/testmap.msav"),
+            params.root.join(super::checkout_path("forts/assets/testmap.msav")),
             "forts/config/maps/testmap.msav",
         );
     },
@@ -48,7 +48,7 @@ simple_server_target!(
 // }
 //
 // impl TargetImpl for Impl {
-//     fn build(&mut self, deps: super::Targets<'_>, params: &mut super::BuildParams) {
+//     fn build(&mut self, deps: super::Deps<'_>, params: &mut super::BuildParams) {
 //         // On Forts side it should copy resulting jar into `.bin/Forts.jar`.
 //         if !params
 //             .gradle()
@@ -411,7 +411,7 @@ simple_server_target!(
 //             "forts/config/mods/Forts.jar",
 //         );
 //         params.run.link_global(
-//             params.root.join("forts/assets/testmap.msav"),
+//             params.root.join(super::checkout_path("forts/assets/testmap.msav")),
 //             "forts/config/maps/testmap.msav",
 //         );
 //         params.run.write(
@@ -4,8 +4,6 @@
 //! Hexed is a strategic hex-based territory control game mode.
 //! Repository: https://github.com/Darkdustry-Coders/HexedPlugin
 
-// TODO: Download if enabled status is `Depend` instead of `Build`.
-
 simple_server_target!(
     jar = "Hexed",
     dir = "hexed",
@@ -17,7 +15,7 @@ simple_server_target!(
 
     fn setup_server(params) {
         params.run.link_global(
-            params.root.join("hexed/assets/patch.hjson"),
+            params.root.join(super::checkout_path("hexed/assets/patch.hjson")),
             "hexed/config/patches/patch.hjson",
         );
     },
@@ -50,7 +48,7 @@ simple_server_target!(
 // }
 
 // impl TargetImpl for Impl {
-//     fn build(&mut self, _: super::Targets<'_>, params: &mut super::BuildParams) {
+//     fn build(&mut self, _: super::Deps<'_>, params: &mut super::BuildParams) {
 //         // On Hexed side it should copy resulting jar into `.bin/Hexed.jar`.
 //         if !params
 //             .gradle()
@@ -76,7 +74,7 @@ simple_server_target!(
 //         );
 //
 //         params.run.link_global(
-//             params.root.join("hexed/assets/patch.hjson"),
+//             params.root.join(super::checkout_path("hexed/assets/patch.hjson")),
 //             "hexed/config/patches/patch.hjson",
 //         );
 //
@@ -15,7 +15,7 @@ simple_server_target!(
 
     fn setup_server(params) {
         params.run.link_global(
-            params.root.join("hub/assets/testmap.msav"),
+            params.root.join(super::checkout_path("hub/assets/testmap.msav")),
             "hub/config/maps/testmap.msav",
         );
     },
@@ -60,7 +60,7 @@ simple_server_target!(
 // }
 //
 // impl TargetImpl for Impl {
-//     fn build(&mut self, _: super::Targets<'_>, params: &mut super::BuildParams) {
+//     fn build(&mut self, _: super::Deps<'_>, params: &mut super::BuildParams) {
 //         if !params
 //             .gradle()
 //             .arg(":hub:build")
@@ -84,7 +84,7 @@ simple_server_target!(
 //             "hub/config/mods/LightweightHub.jar",
 //         );
 //         params.run.link_global(
-//             params.root.join("hub/assets/testmap.msav"),
+//             params.root.join(super::checkout_path("hub/assets/testmap.msav")),
 //             "hub/config/maps/testmap.msav",
 //         );
 //         params.run.write(
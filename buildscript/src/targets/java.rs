@@ -9,10 +9,30 @@ use std::{
     process::Command,
 };
 
-use crate::util::{download, is_executable, untar_gz};
+use crate::{
+    error::{IoContext, Result},
+    extract::extract,
+    fs2,
+    platform::Platform,
+    tools::Tool,
+    util::is_executable,
+};
 
 use super::{TargetFlags, TargetImpl, TargetImplStatic};
 
+/// Temurin JDK release.
+pub const TOOL: Tool = Tool {
+    name: "java",
+    version: "21.0.7+6",
+    url: "https://github.com/adoptium/temurin{major}-binaries/releases/download/jdk-{version_encoded}/OpenJDK{major}U-jdk_{platform}_hotspot_{version_underscored}.tar.gz",
+    assets: &[
+        (Platform::LinuxX86_64, "x64_linux"),
+        (Platform::LinuxAarch64, "aarch64_linux"),
+    ],
+    files: &["bin/java", "bin/javac"],
+    sha256: &[],
+};
+
 /// Java JDK target implementation.
 pub struct Impl {
     /// Path to JAVA_HOME directory.
@@ -34,12 +54,13 @@ impl Impl {
     }
 }
 impl TargetImpl for Impl {
-    fn build(&mut self, _: super::Targets<'_>, params: &mut super::BuildParams) {
+    fn build(&mut self, _: super::Deps<'_>, params: &mut super::BuildParams) -> Result<()> {
         params.env.insert(
             "JAVA_HOME".into(),
             self.java_home.as_os_str().to_os_string(),
         );
         params.path.push(self.java_home.join("bin"));
+        Ok(())
     }
 }
 impl TargetImplStatic for Impl {
@@ -54,11 +75,17 @@ impl TargetImplStatic for Impl {
         }
     }
 
+    fn owned_paths() -> &'static [&'static str] {
+        &[
+            ".cache/tools/java",
+        ]
+    }
+
     fn initialize_host(
         _: super::TargetEnabled,
         _: super::Targets<'_>,
         params: &mut super::InitParams,
-    ) -> Option<Self> {
+    ) -> Result<Option<Self>> {
         'a: {
             if let Ok(java_home) = std::env::var("JAVA_HOME") {
                 let java_home = PathBuf::from(java_home);
@@ -67,11 +94,10 @@ impl TargetImplStatic for Impl {
                     break 'a;
                 }
 
-                if params.native_image {
-                    if !is_executable(java_home.join("bin/native-image")) {
+                if params.native_image
+                    && !is_executable(java_home.join("bin/native-image")) {
                         break 'a;
                     }
-                }
 
                 let Ok(out) = Command::new(java_home.join("bin/java"))
                     .arg(
@@ -96,12 +122,12 @@ impl TargetImplStatic for Impl {
                     break 'a;
                 }
 
-                return Some(Self::new(java_home));
+                return Ok(Some(Self::new(java_home)));
             }
         }
 
-        if cfg!(unix) {
-            if let Ok(x) = fs::read_dir("/usr/lib/jvm") {
+        if cfg!(unix)
+            && let Ok(x) = fs::read_dir("/usr/lib/jvm") {
                 for x in x {
                     let Ok(x) = x else { continue };
 
@@ -111,11 +137,10 @@ impl TargetImplStatic for Impl {
                         continue;
                     }
 
-                    if params.native_image {
-                        if !is_executable(java_home.join("bin/native-image")) {
+                    if params.native_image
+                        && !is_executable(java_home.join("bin/native-image")) {
                             continue;
                         }
-                    }
 
                     let Ok(out) = Command::new(java_home.join("bin/java"))
                         .arg(
@@ -140,27 +165,24 @@ impl TargetImplStatic for Impl {
                         continue;
                     }
 
-                    return Some(Self::new(java_home));
+                    return Ok(Some(Self::new(java_home)));
                 }
             }
-        }
 
         // TODO: Implement for Windows
 
-        None
+        Ok(None)
     }
 
     fn initialize_cached(
         _: super::TargetEnabled,
         _: super::Targets<'_>,
-        _: &mut super::InitParams,
-    ) -> Option<Self> {
-        if is_executable(".cache/tools/java/bin/javac")
-            && is_executable(".cache/tools/java/bin/java")
-        {
-            Some(Self::new(fs::canonicalize(".cache/tools/java").unwrap()))
+        params: &mut super::InitParams,
+    ) -> Result<Option<Self>> {
+        if TOOL.is_installed(&params.release(&TOOL)?) {
+            Ok(Some(Self::new(fs2::canonicalize(TOOL.dir())?)))
         } else {
-            None
+            Ok(None)
         }
     }
 
@@ -168,27 +190,22 @@ impl TargetImplStatic for Impl {
     fn initialize_local(
         _: super::TargetEnabled,
         _: super::Targets<'_>,
-        _: &mut super::InitParams,
-    ) -> Self {
+        params: &mut super::InitParams,
+    ) -> Result<Self> {
         #[cfg(unix)]
         {
-            eprintln!("Downloading JDK21");
+            let release = params.release(&TOOL)?;
+            eprintln!("Downloading JDK {}", release.version);
 
-            let url = "https://github.com/adoptium/temurin21-binaries/releases/download/jdk-21.0.7%2B6/OpenJDK21U-jdk_x64_linux_hotspot_21.0.7_6.tar.gz";
-            let archive = ".cache/tools/java/archive.tar.gz";
+            let java_home = TOOL.install(&release, "archive.tar.gz", |archive, dir| {
+                extract(archive, dir, 1).at(archive)
+            })?;
 
-            fs::create_dir_all(".cache/tools/java").unwrap();
-
-            download(url, archive);
-            untar_gz(archive, ".cache/tools/java", 1);
-
-            return Self::new(fs::canonicalize(".cache/tools/java").unwrap());
+            return Ok(Self::new(java_home));
         }
 
         // TODO: Implement for Windows
 
         todo!()
     }
-
-    fn postinit(_: super::TargetEnabled, _: super::Targets<'_>, _: &mut super::InitParams) {}
 }
@@ -1,15 +1,15 @@
 use std::{
     fs::{self, read_dir},
     path::PathBuf,
-    process::Command,
 };
 
 use serde::Serialize;
 use tera::{Context, Tera};
 
 use crate::{
-    exe_path,
-    targets::{Target, TargetImpl, TargetImplStatic},
+    error::{CommandExt, Error, Result},
+    exe_path, fs2, git,
+    targets::{Repo, Target, TargetImpl, TargetImplStatic, checkout_path},
     util::current_dir,
 };
 
@@ -35,39 +35,34 @@ impl Impl {
 }
 
 impl TargetImpl for Impl {
-    fn build(&mut self, _deps: super::Targets<'_>, params: &mut super::BuildParams) {
+    fn build(&mut self, _deps: super::Deps<'_>, params: &mut super::BuildParams) -> Result<()> {
         // TODO: Add --release flag for building in release mode.
-        if !params
-            .cargo()
+        params
+            .cargo()?
             .args(["build", "--release", "-p", "mindurka-bot"])
-            .status()
-            .unwrap()
-            .success()
-        {
-            panic!("Building mindurka-bot failed")
-        }
+            .run()?;
 
-        fs::rename(
+        fs2::rename(
             exe_path!(".cache/rust/release/mindurka-bot"),
             exe_path!(".bin/mindurka-bot"),
-        )
-        .expect("failed to move bot binary");
+        )?;
+        Ok(())
     }
 
-    fn run_init(&mut self, _deps: super::Targets<'_>, params: &mut super::RunParams) {
+    fn run_init(&mut self, _deps: super::Targets<'_>, params: &mut super::RunParams) -> Result<()> {
         if let Some(template) = params.templates.get("mindurka-bot") {
             let mut tera = Tera::default();
             tera.add_template_files([(template, Some("mindurka-bot"))])
-                .expect("Failed to add template to tera");
+                .map_err(|x| Error::other(format!("template {:?}: {x}", template.display())))?;
 
             let context = Context::from_serialize(BotTemplateParams {
                 shared_config_path: params.root.join(".run/sharedConfig.toml"),
             })
-            .unwrap();
+            .map_err(|x| Error::other(x.to_string()))?;
 
             let config_content = tera
                 .render("mindurka-bot", &context)
-                .expect("Failed to render template");
+                .map_err(|x| Error::other(format!("template {:?}: {x}", template.display())))?;
             params.run.write("mindurka-bot/config.toml", config_content);
         } else {
             params.run.write(
@@ -80,17 +75,18 @@ impl TargetImpl for Impl {
                 ),
             );
         }
+        Ok(())
     }
 
-    fn run(&mut self, deps: super::Targets<'_>, params: &mut super::RunParams) {
+    fn run(&mut self, deps: super::Targets<'_>, params: &mut super::RunParams) -> Result<()> {
         let mut cmd = params.cmd(&self.path);
         deps.mprocs.as_ref().unwrap().spawn_task(
-            &params,
+            params,
             cmd.current_dir(".run/mindurka-bot")
                 .arg("-c")
                 .arg("config.toml"),
             "mindurka-bot",
-        );
+        )
     }
 }
 
@@ -100,11 +96,33 @@ impl TargetImplStatic for Impl {
         list.set_depend(Target::SurrealDb);
     }
 
+    fn repos() -> &'static [Repo] {
+        &[Repo {
+            dir: "mindurka-bot",
+            remote: "Darkdustry-Coders/MindurkaBot",
+        }]
+    }
+
+    fn owned_paths() -> &'static [&'static str] {
+        &[
+            exe_path!(".bin/mindurka-bot"),
+            ".run/mindurka-bot",
+        ]
+    }
+
+    fn inputs() -> &'static [&'static str] {
+        &["mindurka-bot", "Cargo.toml"]
+    }
+
+    fn artifacts() -> &'static [&'static str] {
+        &[exe_path!(".bin/mindurka-bot")]
+    }
+
     fn initialize_host(
         _: super::TargetEnabled,
         _: super::Targets<'_>,
         _: &mut super::InitParams,
-    ) -> Option<Self> {
+    ) -> Result<Option<Self>> {
         unimplemented!()
     }
 
@@ -112,36 +130,34 @@ impl TargetImplStatic for Impl {
         _: super::TargetEnabled,
         _: super::Targets<'_>,
         _: &mut super::InitParams,
-    ) -> Option<Self> {
-        if read_dir("mindurka-bot").is_err() {
-            return None;
+    ) -> Result<Option<Self>> {
+        if read_dir(checkout_path("mindurka-bot")).is_err() {
+            return Ok(None);
         }
 
-        Some(Self::new(fs::canonicalize("mindurka-bot").unwrap()))
+        Ok(Some(Self::new(fs2::canonicalize(checkout_path("mindurka-bot"))?)))
     }
 
     fn initialize_local(
         _: super::TargetEnabled,
         _: super::Targets<'_>,
         params: &mut super::InitParams,
-    ) -> Self {
-        if !Command::new("git")
-            .arg("clone")
-            .arg(params.git_backend.repo_url("Darkdustry-Coders/MindurkaBot"))
-            .arg(params.root.join("mindurka-bot"))
-            .status()
-            .unwrap()
-            .success()
-        {
-            panic!("failed to fetch repo");
+    ) -> Result<Self> {
+        for repo in Self::repos() {
+            git::clone(repo, &params.remotes)?;
         }
 
-        Self::new(fs::canonicalize("mindurka-bot").unwrap())
+        Ok(Self::new(fs2::canonicalize(checkout_path("mindurka-bot"))?))
     }
 
-    fn postinit(_: super::TargetEnabled, _: super::Targets<'_>, params: &mut super::InitParams) {
-        if fs::read_dir("mindurka-bot").is_ok() {
+    fn postinit(
+        _: super::TargetEnabled,
+        _: super::Targets<'_>,
+        params: &mut super::InitParams,
+    ) -> Result<()> {
+        if fs::read_dir(checkout_path("mindurka-bot")).is_ok() {
             params.rust_workspace_members.push("mindurka-bot".into());
         }
+        Ok(())
     }
 }
@@ -1,5 +1,5 @@
 use std::{
-    fs::{self, read_dir},
+    fs::read_dir,
     path::PathBuf,
     process::Command,
 };
@@ -8,7 +8,9 @@ use serde::Serialize;
 use tera::{Context, Tera};
 
 use crate::{
-    targets::{Target, TargetImpl, TargetImplStatic},
+    error::{CommandExt, Error, Result},
+    fs2, git,
+    targets::{Repo, Target, TargetImpl, TargetImplStatic, checkout_path},
     util::current_dir,
 };
 
@@ -36,40 +38,35 @@ impl Impl {
 }
 
 impl TargetImpl for Impl {
-    fn build(&mut self, _deps: super::Targets<'_>, params: &mut super::BuildParams) {
-        if !params
-            .cargo()
+    fn build(&mut self, _deps: super::Deps<'_>, params: &mut super::BuildParams) -> Result<()> {
+        params
+            .cargo()?
             .args(["build", "--release", "-p", "mindurka-nsfw-detector"])
-            .status()
-            .unwrap()
-            .success()
-        {
-            panic!("Building mindurka-nsfw-detector failed")
-        }
+            .run()
     }
 
-    fn run_init(&mut self, _deps: super::Targets<'_>, params: &mut super::RunParams) {
+    fn run_init(&mut self, _deps: super::Targets<'_>, params: &mut super::RunParams) -> Result<()> {
         let root = params.root.join(".run/mindurka-nsfw-detector");
-        fs::create_dir_all(&root).unwrap();
+        fs2::create_dir_all(&root)?;
 
         let config = root.join("config.toml");
-        fs::File::create(&config).expect("Failed to create config file");
+        fs2::create(&config)?;
         if let Some(template) = params.templates.get("mindurka-nsfw-detector") {
             let mut tera = Tera::default();
             tera.add_template_files([(template, Some("mindurka-nsfw-detector"))])
-                .expect("Failed to add template to tera");
+                .map_err(|x| Error::other(format!("template {:?}: {x}", template.display())))?;
 
             let context = Context::from_serialize(NsfwDetectorTemplateParams {
                 shared_config_path: params.root.join(".run/sharedConfig.toml"),
             })
-            .unwrap();
+            .map_err(|x| Error::other(x.to_string()))?;
 
             let config_content = tera
                 .render("mindurka-nsfw-detector", &context)
-                .expect("Failed to render template");
-            fs::write(&config, config_content).expect("Failed to write rendered config");
+                .map_err(|x| Error::other(format!("template {:?}: {x}", template.display())))?;
+            fs2::write(&config, config_content)?;
         } else {
-            fs::write(
+            fs2::write(
                 &config,
                 format!(
                     r#"
@@ -77,11 +74,10 @@ impl TargetImpl for Impl {
                 "#,
                     params.root.join(".run/sharedConfig.toml")
                 ),
-            )
-            .unwrap();
+            )?;
         }
 
-        let mut cmd = params.cargo();
+        let mut cmd = params.cargo()?;
         cmd.current_dir(root)
             .args([
                 "run",
@@ -94,14 +90,15 @@ impl TargetImpl for Impl {
             ])
             .env("RUST_LOG", "info");
         self.command = Some(cmd);
+        Ok(())
     }
 
-    fn run(&mut self, deps: super::Targets<'_>, params: &mut super::RunParams) {
+    fn run(&mut self, deps: super::Targets<'_>, params: &mut super::RunParams) -> Result<()> {
         deps.mprocs.as_ref().unwrap().spawn_task(
-            &params,
-            &mut self.command.as_mut().unwrap(),
+            params,
+            self.command.as_mut().unwrap(),
             "mindurka-bot",
-        );
+        )
     }
 }
 
@@ -111,11 +108,28 @@ impl TargetImplStatic for Impl {
         list.set_depend(Target::SurrealDb);
     }
 
+    fn repos() -> &'static [Repo] {
+        &[Repo {
+            dir: "mindurka-nsfw-detector",
+            remote: "Darkdustry-Coders/MindurkaNsfwDetector",
+        }]
+    }
+
+    fn owned_paths() -> &'static [&'static str] {
+        &[
+            ".run/mindurka-nsfw-detector",
+        ]
+    }
+
+    fn inputs() -> &'static [&'static str] {
+        &["mindurka-nsfw-detector", "Cargo.toml"]
+    }
+
     fn initialize_host(
         _: super::TargetEnabled,
         _: super::Targets<'_>,
         _: &mut super::InitParams,
-    ) -> Option<Self> {
+    ) -> Result<Option<Self>> {
         unimplemented!()
     }
 
@@ -123,39 +137,30 @@ impl TargetImplStatic for Impl {
         _: super::TargetEnabled,
         _: super::Targets<'_>,
         params: &mut super::InitParams,
-    ) -> Option<Self> {
-        if read_dir("mindurka-nsfw-detector").is_err() {
-            return None;
+    ) -> Result<Option<Self>> {
+        if read_dir(checkout_path("mindurka-nsfw-detector")).is_err() {
+            return Ok(None);
         }
 
         params
             .rust_workspace_members
             .push("mindurka-nsfw-detector".into());
-        Some(Self::new(
-            fs::canonicalize("mindurka-nsfw-detector").unwrap(),
-        ))
+        Ok(Some(Self::new(fs2::canonicalize(checkout_path(
+            "mindurka-nsfw-detector",
+        ))?)))
     }
 
     fn initialize_local(
         _: super::TargetEnabled,
         _: super::Targets<'_>,
         params: &mut super::InitParams,
-    ) -> Self {
-        if !Command::new("git")
-            .arg("clone")
-            .arg(
-                params
-                    .git_backend
-                    .repo_url("Darkdustry-Coders/MindurkaNsfwDetector"),
-            )
-            .arg(params.root.join("mindurka-nsfw-detector"))
-            .status()
-            .unwrap()
-            .success()
-        {
-            panic!("failed to fetch repo");
+    ) -> Result<Self> {
+        for repo in Self::repos() {
+            git::clone(repo, &params.remotes)?;
         }
 
-        Self::new(fs::canonicalize("mindurka-nsfw-detector").unwrap())
+        Ok(Self::new(fs2::canonicalize(checkout_path(
+            "mindurka-nsfw-detector",
+        ))?))
     }
 }
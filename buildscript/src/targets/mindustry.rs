@@ -5,12 +5,27 @@
 use std::{
     fs::{self, read_dir},
     path::{Path, PathBuf},
-    process::Command,
 };
 
-use crate::util::current_dir;
+use crate::{
+    error::{CommandExt, Result},
+    fs2, git,
+    prebuilt::{self, Prebuilt},
+    util::current_dir,
+};
 
-use super::{TargetImpl, TargetImplStatic};
+use super::{Repo, TargetImpl, TargetImplStatic, checkout_path};
+
+/// Released server jar, used when Mindustry is only a dependency.
+///
+/// Arc and Mindustry libraries are resolved from the mindurka maven repository then.
+const PREBUILT: Prebuilt = Prebuilt {
+    name: "mindustry",
+    repo: "Darkdustry-Coders/MindustryServer",
+    artifact: ".bin/server-release.jar",
+    asset: "server-release.jar",
+    maven: Some("anuken.mindustry:server:release"),
+};
 
 /// Mindustry server target implementation.
 pub struct Impl {
@@ -24,52 +39,36 @@ impl Impl {
 }
 
 impl TargetImpl for Impl {
-    fn build(&mut self, _: super::Targets<'_>, params: &mut super::BuildParams) {
+    fn build(&mut self, _: super::Deps<'_>, params: &mut super::BuildParams) -> Result<()> {
         // If it works, it works. Just you wait till you learn how you UPDATE this thing.
-        if !params
+        params
             .gradle()
-            .current_dir(fs::canonicalize("mindustry").unwrap())
+            .current_dir(fs2::canonicalize(checkout_path("mindustry"))?)
             .arg(":server:dist")
             .arg("-Pbuildversion=157")
             .arg(format!("-Pnativeimage={}", params.native_image))
-            .status()
-            .unwrap()
-            .success()
-        {
-            panic!("building Mindustry failed");
-        }
+            .run()?;
 
-        if !params
+        params
             .gradle()
-            .current_dir(fs::canonicalize("arc").unwrap())
+            .current_dir(fs2::canonicalize(checkout_path("arc"))?)
             .arg("publishAllPublicationsToMavenRepository")
-            .status()
-            .unwrap()
-            .success()
-        {
-            panic!("publishing Arc failed");
-        }
+            .run()?;
 
-        fs::copy(
-            "mindustry/server/build/libs/server-release.jar",
+        fs2::copy(
+            checkout_path("mindustry/server/build/libs/server-release.jar"),
             ".bin/server-release.jar",
-        )
-        .unwrap();
+        )?;
 
         // Build so nice I'll do it twice (otherwise server-release.jar has no shit).
-        if !params
+        params
             .gradle()
-            .current_dir(fs::canonicalize("mindustry").unwrap())
+            .current_dir(fs2::canonicalize(checkout_path("mindustry"))?)
             .arg(":core:publishAllPublicationsToMavenRepository")
             .arg(":server:publishAllPublicationsToMavenRepository")
             .arg("-Pbuildversion=157")
             .arg(format!("-Pnativeimage={}", params.native_image))
-            .status()
-            .unwrap()
-            .success()
-        {
-            panic!("building Mindustry failed");
-        }
+            .run()
     }
 }
 
@@ -78,11 +77,45 @@ impl TargetImplStatic for Impl {
         list.set_depend(super::Target::Java);
     }
 
+    fn flags() -> super::TargetFlags {
+        super::TargetFlags {
+            gradle: true,
+            ..Default::default()
+        }
+    }
+
+    fn repos() -> &'static [Repo] {
+        &[
+            Repo {
+                dir: "mindustry",
+                remote: "Darkdustry-Coders/MindustryServer",
+            },
+            Repo {
+                dir: "arc",
+                remote: "Darkdustry-Coders/Arc",
+            },
+        ]
+    }
+
+    fn owned_paths() -> &'static [&'static str] {
+        &[
+            ".bin/server-release.jar",
+        ]
+    }
+
+    fn inputs() -> &'static [&'static str] {
+        &["mindustry", "arc"]
+    }
+
+    fn artifacts() -> &'static [&'static str] {
+        &[".bin/server-release.jar"]
+    }
+
     fn initialize_host(
         _: super::TargetEnabled,
         _: super::Targets<'_>,
         _: &mut super::InitParams,
-    ) -> Option<Self> {
+    ) -> Result<Option<Self>> {
         unimplemented!()
     }
 
@@ -90,58 +123,51 @@ impl TargetImplStatic for Impl {
         _: super::TargetEnabled,
         _: super::Targets<'_>,
         _: &mut super::InitParams,
-    ) -> Option<Self> {
-        if read_dir("mindustry").is_err() {
-            return None;
+    ) -> Result<Option<Self>> {
+        if read_dir(checkout_path("mindustry")).is_err() {
+            return Ok(None);
         }
 
-        Some(Self {
+        Ok(Some(Self {
             path: current_dir().join(".bin/server-release.jar"),
-        })
+        }))
     }
 
-    fn initialize_local(
+    fn initialize_prebuilt(
         _: super::TargetEnabled,
         _: super::Targets<'_>,
         params: &mut super::InitParams,
-    ) -> Self {
-        if !Command::new("git")
-            .arg("clone")
-            .arg(
-                params
-                    .git_backend
-                    .repo_url("Darkdustry-Coders/MindustryServer"),
-            )
-            .arg(params.root.join("mindustry"))
-            .status()
-            .unwrap()
-            .success()
-        {
-            panic!("failed to fetch repo");
-        }
+    ) -> Result<Option<Self>> {
+        Ok(prebuilt::fetch(&PREBUILT, params)?.then(|| Self {
+            path: current_dir().join(PREBUILT.artifact),
+        }))
+    }
 
-        if !Command::new("git")
-            .arg("clone")
-            .arg(params.git_backend.repo_url("Darkdustry-Coders/Arc"))
-            .arg(params.root.join("arc"))
-            .status()
-            .unwrap()
-            .success()
-        {
-            panic!("failed to fetch repo");
+    fn initialize_local(
+        _: super::TargetEnabled,
+        _: super::Targets<'_>,
+        params: &mut super::InitParams,
+    ) -> Result<Self> {
+        for repo in Self::repos() {
+            git::clone(repo, &params.remotes)?;
         }
 
-        Self {
+        Ok(Self {
             path: current_dir().join(".bin/server-release.jar"),
-        }
+        })
     }
 
-    fn postinit(_: super::TargetEnabled, _: super::Targets<'_>, params: &mut super::InitParams) {
-        if fs::read_dir("mindustry").is_ok() {
+    fn postinit(
+        _: super::TargetEnabled,
+        _: super::Targets<'_>,
+        params: &mut super::InitParams,
+    ) -> Result<()> {
+        if fs::read_dir(checkout_path("mindustry")).is_ok() {
             params.java_masked_members.push("mindustry".into());
         }
-        if fs::read_dir("arc").is_ok() {
+        if fs::read_dir(checkout_path("arc")).is_ok() {
             params.java_masked_members.push("arc".into());
         }
+        Ok(())
     }
 }
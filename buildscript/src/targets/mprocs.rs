@@ -4,20 +4,38 @@
 //! processes in parallel with a TUI interface.
 
 use std::{
-    fs,
     num::NonZeroU16,
-    path::{Path, PathBuf},
+    path::PathBuf,
     process::{Child, Command},
     thread::sleep,
     time::Duration,
 };
 
-use crate::util::{download, find_executable, is_executable, untar_gz};
-
-use super::{RunParams, TargetEnabled, TargetFlags, TargetImpl, TargetImplStatic, Targets};
+use crate::{
+    error::{CommandExt, IoContext, Result},
+    exe_path,
+    extract::extract,
+    fs2,
+    platform::Platform,
+    tools::Tool,
+    util::find_executable,
+};
 
-/// Base URL for mprocs releases.
-static BASE_URL: &str = "https://github.com/pvolok/mprocs/releases/download/v0.7.3/mprocs-0.7.3";
+use super::{Deps, RunParams, TargetEnabled, TargetFlags, TargetImpl, TargetImplStatic, Targets};
+
+/// mprocs release.
+pub const TOOL: Tool = Tool {
+    name: "mprocs",
+    version: "0.7.3",
+    url: "https://github.com/pvolok/mprocs/releases/download/v{version}/mprocs-{version}-{platform}",
+    assets: &[
+        (Platform::LinuxX86_64, "linux-x86_64-musl.tar.gz"),
+        (Platform::LinuxAarch64, "linux-aarch64-musl.tar.gz"),
+        (Platform::WindowsX86_64, "windows-x86_64.zip"),
+    ],
+    files: &[exe_path!("mprocs")],
+    sha256: &[],
+};
 
 /// Mprocs task runner target implementation.
 pub struct Impl {
@@ -40,7 +58,7 @@ impl Impl {
     /// # Arguments
     /// * `command` - Command to spawn
     /// * `name` - Task name for display
-    pub fn spawn_task(&self, _: &RunParams, command: &mut Command, name: &str) {
+    pub fn spawn_task(&self, _: &RunParams, command: &mut Command, name: &str) -> Result<()> {
         let mut cmd = String::new();
         if let Some(x) = command.get_current_dir() {
             cmd.push_str(&format!("cd {x:?} && "));
@@ -59,50 +77,46 @@ impl Impl {
             cmd.push_str(&format!(" {x:?}"));
         }
 
-        if !Command::new(&self.mprocs)
+        Command::new(&self.mprocs)
             .arg("--server")
             .arg(format!("127.0.0.1:{}", self.port))
             .arg("--ctl")
             .arg(format!("{{c: add-proc, cmd: {cmd:?}, name: {name:?}}}"))
-            .status()
-            .unwrap()
-            .success()
-        {
-            panic!("failed starting a task");
-        }
+            .run()
     }
 
     /// Waits for mprocs to exit and returns success status.
     pub fn wait(&mut self) -> bool {
-        if let Some(mut x) = self.process.take() {
-            if !x.wait().unwrap().success() {
+        if let Some(mut x) = self.process.take()
+            && !x.wait().is_ok_and(|x| x.success()) {
                 return false;
             }
-        }
         true
     }
 }
 impl TargetImpl for Impl {
-    fn build(&mut self, _: Targets<'_>, _: &mut super::BuildParams) {
+    fn build(&mut self, _: Deps<'_>, _: &mut super::BuildParams) -> Result<()> {
         // STUB: This target is not compiled from source.
+        Ok(())
     }
 
-    fn run_init(&mut self, _: Targets<'_>, _: &mut super::RunParams) {
+    fn run_init(&mut self, _: Targets<'_>, _: &mut super::RunParams) -> Result<()> {
         // TODO: Windows
+        Ok(())
     }
 
-    fn run(&mut self, _: Targets<'_>, params: &mut super::RunParams) {
+    fn run(&mut self, _: Targets<'_>, params: &mut super::RunParams) -> Result<()> {
         let port = params.next_port();
         self.process = Some(
             params
                 .cmd(&self.mprocs)
                 .arg("--server")
                 .arg(format!("127.0.0.1:{port}"))
-                .spawn()
-                .unwrap(),
+                .start()?,
         );
         self.port = port;
         sleep(Duration::from_millis(100));
+        Ok(())
     }
 }
 impl TargetImplStatic for Impl {
@@ -113,64 +127,61 @@ impl TargetImplStatic for Impl {
         }
     }
 
+    fn owned_paths() -> &'static [&'static str] {
+        &[
+            ".cache/tools/mprocs",
+        ]
+    }
+
     fn depends(_: &mut super::TargetList) {}
 
     fn initialize_host(
         _: TargetEnabled,
         _: Targets<'_>,
         _: &mut super::InitParams,
-    ) -> Option<Self> {
-        find_executable("mprocs")
-            .map(|x| fs::canonicalize(x).unwrap())
-            .map(|mprocs| Impl {
-                mprocs,
-                port: 0,
-                process: None,
-            })
+    ) -> Result<Option<Self>> {
+        let Some(mprocs) = find_executable("mprocs") else {
+            return Ok(None);
+        };
+        Ok(Some(Impl {
+            mprocs: fs2::canonicalize(mprocs)?,
+            port: 0,
+            process: None,
+        }))
     }
     fn initialize_cached(
         _: TargetEnabled,
         _: Targets<'_>,
-        _: &mut super::InitParams,
-    ) -> Option<Self> {
-        if is_executable(".cache/tools/mprocs/mprocs") {
-            Some(Self {
-                mprocs: PathBuf::from(".cache/tools/mprocs/mprocs"),
+        params: &mut super::InitParams,
+    ) -> Result<Option<Self>> {
+        if TOOL.is_installed(&params.release(&TOOL)?) {
+            Ok(Some(Self {
+                mprocs: PathBuf::from(exe_path!(".cache/tools/mprocs/mprocs")),
                 port: 0,
                 process: None,
-            })
+            }))
         } else {
-            None
+            Ok(None)
         }
     }
     #[allow(unreachable_code)]
-    fn initialize_local(_: TargetEnabled, _: Targets<'_>, _: &mut super::InitParams) -> Self {
-        eprintln!("Downloading mprocs");
-
-        let exe = ".cache/tools/mprocs/mprocs";
-        let archive = ".cache/tools/mprocs/archive";
-        let dir = Path::new(exe).parent().unwrap();
-        fs::create_dir_all(dir).unwrap();
-        download(
-            &(BASE_URL.to_string()
-                + if cfg!(unix) {
-                    "-linux-x86_64-musl.tar.gz"
-                } else {
-                    "-windows-x86_64.zip"
-                }),
-            archive,
-        );
-        #[cfg(unix)]
-        {
-            untar_gz(archive, ".cache/tools/mprocs", 1);
-        }
-        fs::remove_file(archive).ok();
-        Self {
-            mprocs: PathBuf::from(".cache/tools/mprocs/mprocs"),
+    fn initialize_local(
+        _: TargetEnabled,
+        _: Targets<'_>,
+        params: &mut super::InitParams,
+    ) -> Result<Self> {
+        let release = params.release(&TOOL)?;
+        eprintln!("Downloading mprocs {}", release.version);
+
+        // The Windows release is a zip with just the executable.
+        let skip_segments = if cfg!(unix) { 1 } else { 0 };
+        TOOL.install(&release, "archive", |archive, dir| {
+            extract(archive, dir, skip_segments).at(archive)
+        })?;
+        Ok(Self {
+            mprocs: PathBuf::from(exe_path!(".cache/tools/mprocs/mprocs")),
             port: 0,
             process: None,
-        }
+        })
     }
-
-    fn postinit(_: super::TargetEnabled, _: super::Targets<'_>, _: &mut super::InitParams) {}
 }
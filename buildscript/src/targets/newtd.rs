@@ -5,7 +5,9 @@ use std::{
 };
 
 use crate::{
-    targets::{Target, TargetImpl, TargetImplStatic},
+    error::{IoContext, Result},
+    fs2, git,
+    targets::{Repo, Target, TargetImpl, TargetImplStatic, checkout_path},
     util::{self, current_dir},
 };
 
@@ -28,42 +30,33 @@ impl Impl {
 }
 
 impl TargetImpl for Impl {
-    fn build(&mut self, _deps: super::Targets<'_>, params: &mut super::BuildParams) {
-        if !params
-            .gradle()
-            .arg(":newtd:build")
-            .status()
-            .unwrap()
-            .success()
-        {
-            panic!("Building new tower defence failed")
-        }
+    fn build(&mut self, _deps: super::Deps<'_>, params: &mut super::BuildParams) -> Result<()> {
+        params.run_gradle_tasks(Self::gradle_tasks())
     }
 
-    fn run_init(&mut self, deps: super::Targets<'_>, params: &mut super::RunParams) {
+    fn run_init(&mut self, deps: super::Targets<'_>, params: &mut super::RunParams) -> Result<()> {
         let root = params.root.join(".run/newtd");
 
-        fs::create_dir_all(root.join("config/mods")).unwrap();
-        fs::create_dir_all(root.join("config/maps")).unwrap();
+        fs2::create_dir_all(root.join("config/mods"))?;
+        fs2::create_dir_all(root.join("config/maps"))?;
 
         util::symlink_file(
             params.root.join(".bin/CorePlugin.jar"),
             root.join("config/mods/CorePlugin.jar"),
         )
-        .unwrap();
+        .at(root.join("config/mods/CorePlugin.jar"))?;
         util::symlink_file(
             params.root.join(".bin/Newtd.jar"),
             root.join("config/mods/Newtd.jar"),
         )
-        .unwrap();
+        .at(root.join("config/mods/Newtd.jar"))?;
 
-        fs::copy(
-            params.root.join("newtd/assets/testmap.msav"),
+        fs2::copy(
+            params.root.join(checkout_path("newtd/assets/testmap.msav")),
             root.join("config/maps/testmap.msav"),
-        )
-        .unwrap();
+        )?;
 
-        fs::write(
+        fs2::write(
             root.join("config/corePlugin.toml"),
             format!(
                 r#"
@@ -73,8 +66,7 @@ impl TargetImpl for Impl {
                 "#,
                 params.root.join(".run/sharedConfig.toml")
             ),
-        )
-        .unwrap();
+        )?;
 
         let port = params.next_port();
 
@@ -107,7 +99,7 @@ impl TargetImpl for Impl {
             contents.extend_from_slice(&(commands.len() as u16).to_be_bytes());
             contents.extend_from_slice(commands.as_bytes());
 
-            fs::write(root.join("config/settings.bin"), contents).unwrap();
+            fs2::write(root.join("config/settings.bin"), contents)?;
         }
 
         let java = deps.java.as_ref().unwrap().home().join("bin/java");
@@ -116,14 +108,15 @@ impl TargetImpl for Impl {
         let mut cmd = params.cmd(java);
         cmd.arg("-jar").arg(mindustry).current_dir(root);
         self.command = Some(cmd);
+        Ok(())
     }
 
-    fn run(&mut self, deps: super::Targets<'_>, params: &mut super::RunParams) {
+    fn run(&mut self, deps: super::Targets<'_>, params: &mut super::RunParams) -> Result<()> {
         deps.mprocs.as_ref().unwrap().spawn_task(
-            &params,
-            &mut self.command.as_mut().unwrap(),
+            params,
+            self.command.as_mut().unwrap(),
             "newtd",
-        );
+        )
     }
 }
 
@@ -133,11 +126,50 @@ impl TargetImplStatic for Impl {
         list.set_depend(Target::CorePlugin);
     }
 
+    fn flags() -> super::TargetFlags {
+        super::TargetFlags {
+            gradle: true,
+            ..Default::default()
+        }
+    }
+
+    fn gradle_tasks() -> &'static [&'static str] {
+        &[":newtd:build"]
+    }
+
+    fn repos() -> &'static [Repo] {
+        &[Repo {
+            dir: "newtd",
+            remote: "Darkdustry-Coders/Newtd",
+        }]
+    }
+
+    fn owned_paths() -> &'static [&'static str] {
+        &[
+            ".bin/Newtd.jar",
+            ".run/newtd",
+        ]
+    }
+
+    fn inputs() -> &'static [&'static str] {
+        &[
+            "newtd",
+            "build.gradle",
+            "settings.gradle",
+            "gradle.properties",
+            "buildscript/assets/shared.settings.gradle",
+        ]
+    }
+
+    fn artifacts() -> &'static [&'static str] {
+        &[".bin/Newtd.jar"]
+    }
+
     fn initialize_host(
         _: super::TargetEnabled,
         _: super::Targets<'_>,
         _: &mut super::InitParams,
-    ) -> Option<Self> {
+    ) -> Result<Option<Self>> {
         unimplemented!()
     }
 
@@ -145,36 +177,34 @@ impl TargetImplStatic for Impl {
         _: super::TargetEnabled,
         _: super::Targets<'_>,
         _: &mut super::InitParams,
-    ) -> Option<Self> {
-        if read_dir("newtd").is_err() {
-            return None;
+    ) -> Result<Option<Self>> {
+        if read_dir(checkout_path("newtd")).is_err() {
+            return Ok(None);
         }
 
-        Some(Self::new(fs::canonicalize("newtd").unwrap()))
+        Ok(Some(Self::new(fs2::canonicalize(checkout_path("newtd"))?)))
     }
 
     fn initialize_local(
         _: super::TargetEnabled,
         _: super::Targets<'_>,
         params: &mut super::InitParams,
-    ) -> Self {
-        if !Command::new("git")
-            .arg("clone")
-            .arg(params.git_backend.repo_url("Darkdustry-Coders/Newtd"))
-            .arg(params.root.join("newtd"))
-            .status()
-            .unwrap()
-            .success()
-        {
-            panic!("failed to fetch repo");
+    ) -> Result<Self> {
+        for repo in Self::repos() {
+            git::clone(repo, &params.remotes)?;
         }
 
-        Self::new(fs::canonicalize("newtd").unwrap())
+        Ok(Self::new(fs2::canonicalize(checkout_path("newtd"))?))
     }
 
-    fn postinit(_: super::TargetEnabled, _: super::Targets<'_>, params: &mut super::InitParams) {
-        if fs::read_dir("newtd").is_ok() {
+    fn postinit(
+        _: super::TargetEnabled,
+        _: super::Targets<'_>,
+        params: &mut super::InitParams,
+    ) -> Result<()> {
+        if fs::read_dir(checkout_path("newtd")).is_ok() {
             params.java_workspace_members.push("newtd".into());
         }
+        Ok(())
     }
 }
@@ -4,18 +4,31 @@
 //! for the message queue infrastructure.
 
 use std::{
-    fs::{self, File},
     io::{BufWriter, Write},
     path::{Path, PathBuf},
     process::Command,
 };
 
-use crate::util::{download, find_executable, is_executable, untar_xz};
+use crate::{
+    error::{IoContext, Result},
+    extract::extract,
+    fs2,
+    tools::Tool,
+    util::find_executable,
+};
 
 use super::{Target, TargetImpl, TargetImplStatic};
 
-/// URL for RabbitMQ generic Unix release.
-static URL: &str = "https://github.com/rabbitmq/rabbitmq-server/releases/download/v4.1.2/rabbitmq-server-generic-unix-4.1.2.tar.xz";
+/// RabbitMQ generic Unix release.
+pub const TOOL: Tool = Tool {
+    name: "rabbitmq",
+    version: "4.1.2",
+    url: "https://github.com/rabbitmq/rabbitmq-server/releases/download/v{version}/rabbitmq-server-generic-unix-{version}.tar.xz",
+    files: &["sbin/rabbitmq-server"],
+    // Runs on the host's Erlang, so there is one release for every platform.
+    assets: &[],
+    sha256: &[],
+};
 
 /// RabbitMQ target implementation.
 pub struct Impl {
@@ -47,20 +60,23 @@ impl Impl {
 }
 
 impl TargetImpl for Impl {
-    fn build(&mut self, _: super::Targets<'_>, _: &mut super::BuildParams) {}
+    fn build(&mut self, _: super::Deps<'_>, _: &mut super::BuildParams) -> Result<()> {
+        Ok(())
+    }
 
-    fn run_init(&mut self, _: super::Targets<'_>, params: &mut super::RunParams) {
+    fn run_init(&mut self, _: super::Targets<'_>, params: &mut super::RunParams) -> Result<()> {
         if params.host_rabbitmq {
-            return;
+            return Ok(());
         }
 
         self.port = params.next_port();
         self.management_port = params.next_port();
 
         let rabbitmq_root = params.root.join(".run/rabbitmq");
-        fs::create_dir_all(&rabbitmq_root).unwrap();
+        fs2::create_dir_all(&rabbitmq_root)?;
 
-        let mut config = BufWriter::new(File::create(rabbitmq_root.join("rabbitmq.conf")).unwrap());
+        let path = rabbitmq_root.join("rabbitmq.conf");
+        let mut config = BufWriter::new(fs2::create(&path)?);
         config
             .write_all(
                 format!(
@@ -69,20 +85,21 @@ impl TargetImpl for Impl {
                 )
                 .as_bytes(),
             )
-            .unwrap();
-        config.flush().unwrap();
+            .at(&path)?;
+        config.flush().at(&path)?;
 
-        let mut config =
-            BufWriter::new(File::create(rabbitmq_root.join("enabled-plugins")).unwrap());
+        let path = rabbitmq_root.join("enabled-plugins");
+        let mut config = BufWriter::new(fs2::create(&path)?);
         config
             .write_all("[rabbitmq_management].".as_bytes())
-            .unwrap();
-        config.flush().unwrap();
+            .at(&path)?;
+        config.flush().at(&path)?;
+        Ok(())
     }
 
-    fn run(&mut self, mut deps: super::Targets<'_>, params: &mut super::RunParams) {
+    fn run(&mut self, mut deps: super::Targets<'_>, params: &mut super::RunParams) -> Result<()> {
         if params.host_rabbitmq {
-            return;
+            return Ok(());
         }
 
         let rabbitmq_root = params.root.join(".run/rabbitmq");
@@ -102,7 +119,7 @@ impl TargetImpl for Impl {
         deps.mprocs
             .as_mut()
             .unwrap()
-            .spawn_task(params, &mut command, "rabbitmq");
+            .spawn_task(params, &mut command, "rabbitmq")
     }
 }
 
@@ -118,36 +135,42 @@ impl TargetImplStatic for Impl {
         }
     }
 
+    fn owned_paths() -> &'static [&'static str] {
+        &[
+            ".cache/tools/rabbitmq",
+            ".run/rabbitmq",
+        ]
+    }
+
     fn initialize_host(
         _: super::TargetEnabled,
         _: super::Targets<'_>,
         params: &mut super::InitParams,
-    ) -> Option<Self> {
+    ) -> Result<Option<Self>> {
         if params.host_rabbitmq {
-            return Some(Impl::new(PathBuf::new()));
+            return Ok(Some(Impl::new(PathBuf::new())));
         }
 
-        find_executable("rabbitmq-server")
-            .map(|x| fs::canonicalize(x).unwrap())
-            .map(|x| x.parent().unwrap().parent().unwrap().to_path_buf())
-            .map(Impl::new)
+        let Some(server) = find_executable("rabbitmq-server") else {
+            return Ok(None);
+        };
+        let server = fs2::canonicalize(server)?;
+        Ok(server.parent().and_then(Path::parent).map(|x| Impl::new(x.to_path_buf())))
     }
 
     fn initialize_cached(
         _: super::TargetEnabled,
         _: super::Targets<'_>,
         params: &mut super::InitParams,
-    ) -> Option<Self> {
+    ) -> Result<Option<Self>> {
         if params.host_rabbitmq {
-            return Some(Impl::new(PathBuf::new()));
+            return Ok(Some(Impl::new(PathBuf::new())));
         }
 
-        if is_executable(".cache/tools/rabbitmq/sbin/rabbitmq-server") {
-            Some(Self::new(
-                fs::canonicalize(".cache/tools/rabbitmq").unwrap(),
-            ))
+        if TOOL.is_installed(&params.release(&TOOL)?) {
+            Ok(Some(Self::new(fs2::canonicalize(TOOL.dir())?)))
         } else {
-            None
+            Ok(None)
         }
     }
 
@@ -155,20 +178,16 @@ impl TargetImplStatic for Impl {
         _: super::TargetEnabled,
         _: super::Targets<'_>,
         params: &mut super::InitParams,
-    ) -> Self {
+    ) -> Result<Self> {
         if params.host_rabbitmq {
-            return Impl::new(PathBuf::new());
+            return Ok(Impl::new(PathBuf::new()));
         }
 
-        let archive = ".cache/tools/rabbitmq/archive.tar.xz";
-        let dir = Path::new(archive).parent().unwrap();
-        fs::create_dir_all(dir).unwrap();
-        download(URL, archive);
-        untar_xz(archive, dir, 1);
-        fs::remove_file(archive).ok();
+        let release = params.release(&TOOL)?;
+        let dir = TOOL.install(&release, "archive.tar.xz", |archive, dir| {
+            extract(archive, dir, 1).at(archive)
+        })?;
 
-        Self::new(fs::canonicalize(dir).unwrap())
+        Ok(Self::new(dir))
     }
-
-    fn postinit(_: super::TargetEnabled, _: super::Targets<'_>, _: &mut super::InitParams) {}
 }
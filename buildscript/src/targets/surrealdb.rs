@@ -3,28 +3,41 @@
 //! This module manages SurrealDB installation.
 
 use std::{
-    fs::{self, File},
-    path::{Path, PathBuf},
+    fs::File,
+    path::PathBuf,
     process::Command,
 };
 
 use crate::{
+    error::{IoContext, Result},
     exe_path,
-    util::{download, find_executable, is_executable},
+    extract::extract,
+    fs2,
+    platform::Platform,
+    tools::Tool,
+    util::{download_sha256, find_executable},
 };
 
-use super::{RunParams, TargetEnabled, TargetFlags, TargetImpl, TargetImplStatic, Targets};
-
-/// URL for SurrealDB binary release.
-static URL: &str = if cfg!(target_os = "linux") {
-    "https://github.com/surrealdb/surrealdb/releases/download/v3.0.1/surreal-v3.0.1.linux-amd64.tgz"
-} else {
-    "https://github.com/surrealdb/surrealdb/releases/download/v3.0.1/surreal-v3.0.1.windows-amd64.exe"
+use super::{Deps, RunParams, TargetEnabled, TargetFlags, TargetImpl, TargetImplStatic, Targets};
+
+/// SurrealDB binary release.
+pub const TOOL: Tool = Tool {
+    name: "surrealdb",
+    version: "3.0.1",
+    url: "https://github.com/surrealdb/surrealdb/releases/download/v{version}/surreal-v{version}.{platform}",
+    assets: &[
+        (Platform::LinuxX86_64, "linux-amd64.tgz"),
+        (Platform::LinuxAarch64, "linux-arm64.tgz"),
+        (Platform::WindowsX86_64, "windows-amd64.exe"),
+    ],
+    files: &[exe_path!("surreal")],
+    sha256: &[],
 };
-
 static TRY_CAST_URL: &str = "https://mindurka.online/files/world-try-cast-1.0.0.surli";
+/// SHA-256 of [`TRY_CAST_URL`], checked against `workspace.lock` if not built in.
+static TRY_CAST_SHA256: Option<&str> = None;
 fn try_cast_file() -> &'static str {
-    TRY_CAST_URL.split('/').last().unwrap()
+    TRY_CAST_URL.split('/').next_back().unwrap()
 }
 
 /// SurrealDB target implementation.
@@ -49,28 +62,29 @@ impl Impl {
         format!("ws://admin:password@localhost:{}/main/mindustry", self.port)
     }
 
-    fn extras_init(params: &mut super::InitParams) {
+    fn extras_init(params: &mut super::InitParams) -> Result<()> {
         let dest = params
             .root
             .join(".cache/tools/surrealdb")
             .join(try_cast_file());
         if File::open(&dest).is_ok() {
-            return;
+            return Ok(());
         }
 
         println!("Downloading try_cast");
-        download(TRY_CAST_URL, dest);
+        download_sha256(TRY_CAST_URL, dest, TRY_CAST_SHA256)
     }
 }
 
 impl TargetImpl for Impl {
-    fn build(&mut self, _: Targets<'_>, _: &mut super::BuildParams) {
+    fn build(&mut self, _: Deps<'_>, _: &mut super::BuildParams) -> Result<()> {
         // STUB: This target is not compiled from source.
+        Ok(())
     }
 
-    fn run_init(&mut self, _: Targets<'_>, params: &mut RunParams) {
+    fn run_init(&mut self, _: Targets<'_>, params: &mut RunParams) -> Result<()> {
         if params.host_surrealdb {
-            return;
+            return Ok(());
         }
 
         self.port = params.next_port();
@@ -89,30 +103,28 @@ impl TargetImpl for Impl {
             format!("127.0.0.1:{}", self.port).into(),
         );
 
-        fs::write(
+        fs2::write(
             ".cache/tools/surrealdb/init.surrealql",
-            fs::read_to_string("sql/init.surrealql.in")
-                .unwrap()
+            fs2::read_to_string("sql/init.surrealql.in")?
                 .replace(
                     "PLUGINS_BACKEND",
                     &format!(
                         "{:?}",
                         "file://".to_string()
-                            + &fs::canonicalize(".cache/tools/surrealdb/")
-                                .unwrap()
+                            + &fs2::canonicalize(".cache/tools/surrealdb/")?
                                 .to_string_lossy()
                                 .replace("\\", "/")
                     ),
                 )
                 .replace("mod::try::", "type::try_"), // This one shouldn't be necessary, but it has
                                                       // to be because surrealdb sucks.
-        )
-        .unwrap();
+        )?;
+        Ok(())
     }
 
-    fn run(&mut self, mut deps: Targets<'_>, params: &mut RunParams) {
+    fn run(&mut self, mut deps: Targets<'_>, params: &mut RunParams) -> Result<()> {
         if params.host_surrealdb {
-            return;
+            return Ok(());
         }
 
         deps.mprocs.as_mut().unwrap().spawn_task(
@@ -120,13 +132,13 @@ impl TargetImpl for Impl {
             Command::new(self.surreal.join(exe_path!("surreal")))
                 .arg("start")
                 .arg("--import-file")
-                .arg(fs::canonicalize(".cache/tools/surrealdb/init.surrealql").unwrap())
+                .arg(fs2::canonicalize(".cache/tools/surrealdb/init.surrealql")?)
                 .arg(format!(
                     "surrealkv://{}",
                     params.root.join(".run/surrealdb").to_str().unwrap()
                 )),
             "surreal",
-        );
+        )
     }
 }
 
@@ -138,87 +150,93 @@ impl TargetImplStatic for Impl {
         }
     }
 
+    fn owned_paths() -> &'static [&'static str] {
+        &[
+            ".cache/tools/surrealdb",
+            ".run/surrealdb",
+        ]
+    }
+
     fn initialize_host(
         _: TargetEnabled,
         _: Targets<'_>,
         params: &mut super::InitParams,
-    ) -> Option<Self> {
+    ) -> Result<Option<Self>> {
         if params.host_surrealdb {
-            return Some(Impl {
+            return Ok(Some(Impl {
                 surreal: PathBuf::new(),
                 port: 0,
-            });
+            }));
         }
 
-        let surreal = find_executable("surreal").map(|x| x.parent().unwrap().to_path_buf());
-        surreal.map(|surreal| Impl { surreal, port: 0 }).map(|x| {
-            Self::extras_init(params);
-            x
-        })
+        let Some(surreal) = find_executable("surreal").map(|x| x.parent().unwrap().to_path_buf())
+        else {
+            return Ok(None);
+        };
+        Self::extras_init(params)?;
+        Ok(Some(Impl { surreal, port: 0 }))
     }
 
     fn initialize_cached(
         _: TargetEnabled,
         _: Targets<'_>,
         params: &mut super::InitParams,
-    ) -> Option<Self> {
+    ) -> Result<Option<Self>> {
         if params.host_surrealdb {
-            return Some(Impl {
+            return Ok(Some(Impl {
                 surreal: PathBuf::new(),
                 port: 0,
-            });
+            }));
         }
 
-        if is_executable(exe_path!(".cache/tools/surrealdb/surreal")) {
-            Self::extras_init(params);
-            Some(Self {
-                surreal: fs::canonicalize(".cache/tools/surrealdb").unwrap(),
+        if TOOL.is_installed(&params.release(&TOOL)?) {
+            Self::extras_init(params)?;
+            Ok(Some(Self {
+                surreal: fs2::canonicalize(".cache/tools/surrealdb")?,
                 port: 0,
-            })
+            }))
         } else {
-            None
+            Ok(None)
         }
     }
 
     #[allow(unreachable_code)]
-    fn initialize_local(_: TargetEnabled, _: Targets<'_>, params: &mut super::InitParams) -> Self {
+    fn initialize_local(
+        _: TargetEnabled,
+        _: Targets<'_>,
+        params: &mut super::InitParams,
+    ) -> Result<Self> {
         if params.host_surrealdb {
-            return Impl {
+            return Ok(Impl {
                 surreal: PathBuf::new(),
                 port: 0,
-            };
+            });
         }
 
         #[cfg(target_os = "linux")]
         {
-            use crate::util::untar_gz;
-
-            let archive = ".cache/tools/surrealdb/archive.tar.gz";
-            let dir = Path::new(archive).parent().unwrap();
-            fs::create_dir_all(dir).unwrap();
-            download(URL, archive);
-            untar_gz(archive, dir, 1);
-            fs::remove_file(archive).ok();
+            let release = params.release(&TOOL)?;
+            let dir = TOOL.install(&release, "archive.tar.gz", |archive, dir| {
+                extract(archive, dir, 1).at(archive)
+            })?;
 
-            Self::extras_init(params);
+            Self::extras_init(params)?;
 
-            return Self::new(fs::canonicalize(dir).unwrap());
+            return Ok(Self::new(dir));
         }
 
         #[cfg(target_os = "windows")]
         {
-            let exe = ".cache/tools/surrealdb/surreal.exe";
-            let dir = Path::new(archive).parent().unwrap();
-            fs::create_dir_all(dir).unwrap();
-            download(URL, archive);
+            let release = params.release(&TOOL)?;
+            let dir = TOOL.install(&release, "surreal.exe", |exe, dir| {
+                Ok(fs2::rename(exe, dir.join("surreal.exe"))?)
+            })?;
 
-            Self::extras_init(params);
+            Self::extras_init(params)?;
 
-            return Self::new(fs::canonicalize(dir).unwrap());
+            return Ok(Self::new(dir));
         }
 
         todo!();
     }
-
-    fn postinit(_: super::TargetEnabled, _: super::Targets<'_>, _: &mut super::InitParams) {}
 }
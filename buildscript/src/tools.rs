@@ -0,0 +1,301 @@
+//! Versions of tools installed into `.cache/tools`.
+//!
+//! Every tool target downloads a default version from a default URL. Both can be changed in
+//! the `[tools.NAME]` table of `workspace.toml`:
+//!
+//! ```toml
+//! [tools.surrealdb]
+//! version = "3.0.2"
+//!
+//! [tools.java]
+//! version = "21.0.8+9"
+//! url = "https://cache.internal/jdk/{version_underscored}.tar.gz"
+//! ```
+//!
+//! Tools are extracted into a staging directory and only moved into `.cache/tools/NAME` once
+//! complete, along with a record of the version, URL and hash of the download in
+//! `.cache/tools/NAME/.tool.toml`. Installs without a record or with missing files are
+//! repaired, and an install of another version or from another URL is replaced.
+
+use std::{
+    collections::HashMap,
+    io,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::CONFIG_FILE,
+    error::{Error, IoContext, Result},
+    fs2,
+    platform::{self, Platform},
+    targets::{coreutils, java, mprocs, rabbitmq, surrealdb},
+    util::{self, is_executable, sha256_file},
+};
+
+/// Directory tools are installed into.
+pub const TOOLS_DIR: &str = ".cache/tools";
+
+/// File in the directory of a tool recording the installed release.
+const RECORD_FILE: &str = ".tool.toml";
+
+/// Every tool with a configurable version.
+pub const TOOLS: &[&Tool] = &[
+    &java::TOOL,
+    &surrealdb::TOOL,
+    &rabbitmq::TOOL,
+    &mprocs::TOOL,
+    &coreutils::TOOL,
+];
+
+/// A tool downloaded into `.cache/tools`.
+pub struct Tool {
+    /// Name of the target, also the directory in `.cache/tools`.
+    pub name: &'static str,
+    /// Version installed by default.
+    pub version: &'static str,
+    /// Template of the download URL, see [`expand`].
+    pub url: &'static str,
+    /// Executables every complete install has, relative to its directory.
+    pub files: &'static [&'static str],
+    /// Value of `{platform}` in the URL on each supported platform.
+    pub assets: &'static [(Platform, &'static str)],
+    /// SHA-256 of the default version downloaded from the default URL, by value of
+    /// `{platform}` (`""` if the URL has none). Downloads of overridden versions or URLs and
+    /// of platforms missing here are checked against `workspace.lock` instead.
+    pub sha256: &'static [(&'static str, &'static str)],
+}
+
+/// Record of a complete install, in `.cache/tools/NAME/.tool.toml`.
+#[derive(Serialize, Deserialize)]
+pub struct Record {
+    /// Installed version.
+    pub version: String,
+    /// URL the install was downloaded from.
+    pub url: String,
+    /// SHA-256 of the download.
+    pub sha256: String,
+    /// When the tool was installed, in seconds since the Unix epoch.
+    pub installed: u64,
+}
+
+/// Settings of a tool in `workspace.toml`.
+#[derive(Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ToolConfig {
+    /// Version to install.
+    pub version: Option<String>,
+    /// Template of the download URL, see [`expand`].
+    pub url: Option<String>,
+}
+
+/// Version of a tool and the URL it is downloaded from.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Release {
+    /// Version of the tool.
+    pub version: String,
+    /// Download URL.
+    pub url: String,
+    /// Built-in SHA-256 of the download, see [`Tool::sha256`].
+    #[serde(skip)]
+    pub sha256: Option<String>,
+}
+
+/// Fill in a URL template.
+///
+/// Placeholders are `{version}`, `{major}` (the part before the first `.`),
+/// `{version_encoded}` (`+` encoded as `%2B`), `{version_underscored}` (`+` replaced
+/// with `_`) and `{platform}` (the release asset name for the host, see [`Tool::assets`]).
+pub fn expand(template: &str, version: &str, platform: &str) -> String {
+    let major = version.split(['.', '+']).next().unwrap_or(version);
+    template
+        .replace("{platform}", platform)
+        .replace("{version}", version)
+        .replace("{major}", major)
+        .replace("{version_encoded}", &version.replace('+', "%2B"))
+        .replace("{version_underscored}", &version.replace('+', "_"))
+}
+
+/// Leftovers of interrupted installs of the tool `name` in `.cache/tools`.
+///
+/// These are the staging directory, the previous install being replaced and the download
+/// (or its `.part` file), see [`Tool::install`].
+pub fn leftovers(name: &str) -> io::Result<Vec<PathBuf>> {
+    let readdir = match fs2::read_dir(TOOLS_DIR) {
+        Ok(x) => x,
+        Err(why) if why.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(why) => return Err(why),
+    };
+    let staging = format!(".{name}.staging");
+    let old = format!(".{name}.old");
+    let download = format!(".{name}-");
+    let mut paths = vec![];
+    for x in readdir {
+        let x = x?;
+        let file_name = x.file_name();
+        let file_name = file_name.to_string_lossy();
+        if file_name == staging || file_name == old || file_name.starts_with(&download) {
+            paths.push(x.path());
+        }
+    }
+    Ok(paths)
+}
+
+/// Whether `name` is a tool with a configurable version.
+pub fn is_tool(name: &str) -> bool {
+    TOOLS.iter().any(|x| x.name == name)
+}
+
+impl Tool {
+    /// Directory of the tool in `.cache/tools`.
+    pub fn dir(&self) -> PathBuf {
+        Path::new(TOOLS_DIR).join(self.name)
+    }
+
+    /// Value of `{platform}` for the host, `None` if the tool has no release for it.
+    pub fn asset(&self) -> Option<&'static str> {
+        let host = Platform::host()?;
+        self.assets.iter().find(|x| x.0 == host).map(|x| x.1)
+    }
+
+    /// Release to install with the settings in `tools`.
+    ///
+    /// Fails if the URL depends on the platform and the tool has no release for the host.
+    pub fn release(&self, tools: &HashMap<String, ToolConfig>) -> Result<Release> {
+        let config = tools.get(self.name);
+        let version = config
+            .and_then(|x| x.version.clone())
+            .unwrap_or_else(|| self.version.to_string());
+        let template = config.and_then(|x| x.url.as_deref()).unwrap_or(self.url);
+        let platform = if template.contains("{platform}") {
+            self.asset().ok_or_else(|| {
+                Error::other(format!(
+                    "unsupported platform {}, set `url` in `[tools.{}]` of {CONFIG_FILE} to install it from elsewhere",
+                    platform::host_name(),
+                    self.name,
+                ))
+            })?
+        } else {
+            ""
+        };
+        let sha256 = (version == self.version && template == self.url)
+            .then(|| self.sha256.iter().find(|x| x.0 == platform))
+            .flatten()
+            .map(|x| x.1.to_string());
+        Ok(Release {
+            url: expand(template, &version, platform),
+            version,
+            sha256,
+        })
+    }
+
+    /// Record of a complete install, if any.
+    pub fn record(&self) -> Option<Record> {
+        let path = self.dir().join(RECORD_FILE);
+        toml::from_str(&fs2::read_to_string(path).ok()?).ok()
+    }
+
+    /// Whether `.cache/tools` has a complete install of `release`.
+    ///
+    /// Incomplete and broken installs and installs of another release are reported.
+    pub fn is_installed(&self, release: &Release) -> bool {
+        let dir = self.dir();
+        let Some(record) = self.record() else {
+            if dir.exists() {
+                eprintln!(
+                    "{}: {} has no install record, it was interrupted or made by an older buildscript, reinstalling",
+                    self.name,
+                    dir.display()
+                );
+            }
+            return false;
+        };
+        if let Some(file) = self.files.iter().find(|x| !is_executable(dir.join(x))) {
+            eprintln!(
+                "{}: {file} is missing from {}, reinstalling",
+                self.name,
+                dir.display()
+            );
+            return false;
+        }
+
+        if record.version != release.version {
+            eprintln!(
+                "{}: {} is installed, but {} is configured, reinstalling",
+                self.name, record.version, release.version
+            );
+            false
+        } else if record.url != release.url {
+            eprintln!(
+                "{}: installed from {}, but {} is configured, reinstalling",
+                self.name, record.url, release.url
+            );
+            false
+        } else {
+            true
+        }
+    }
+
+    /// Download and install `release`, replacing any previous install.
+    ///
+    /// The URL is downloaded next to the install as `.NAME-FILE`. `extract` then sets up the
+    /// install from it in a staging directory, which replaces the install once it has all
+    /// [`files`](Self::files) and a record. The download is removed afterwards unless
+    /// `extract` moved it. Returns the canonical path of the install.
+    pub fn install(
+        &self,
+        release: &Release,
+        file: &str,
+        extract: impl FnOnce(&Path, &Path) -> Result<()>,
+    ) -> Result<PathBuf> {
+        let dir = self.dir();
+        let staging = Path::new(TOOLS_DIR).join(format!(".{}.staging", self.name));
+        let old = Path::new(TOOLS_DIR).join(format!(".{}.old", self.name));
+        remove_dir(&staging)?;
+        fs2::create_dir_all(&staging)?;
+
+        let download = Path::new(TOOLS_DIR).join(format!(".{}-{file}", self.name));
+        util::download_sha256(&release.url, &download, release.sha256.as_deref())?;
+        let record = Record {
+            version: release.version.clone(),
+            url: release.url.clone(),
+            sha256: sha256_file(&download)?,
+            installed: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |x| x.as_secs()),
+        };
+        extract(&download, &staging)?;
+        if download.exists() {
+            fs2::remove_file(&download)?;
+        }
+
+        if let Some(file) = self.files.iter().find(|x| !is_executable(staging.join(x))) {
+            return Err(Error::other(format!(
+                "{}: {} does not contain {file}",
+                self.name, release.url
+            )));
+        }
+        let path = staging.join(RECORD_FILE);
+        let data = toml::to_string(&record).map_err(|why| Error::other(why.to_string()))?;
+        fs2::write(&path, data).at(path)?;
+
+        remove_dir(&old)?;
+        if dir.exists() {
+            fs2::rename(&dir, &old)?;
+        }
+        fs2::rename(&staging, &dir)?;
+        remove_dir(&old)?;
+        Ok(fs2::canonicalize(&dir)?)
+    }
+}
+
+/// Remove the directory at `path` if it exists.
+fn remove_dir(path: &Path) -> Result<()> {
+    match fs2::remove_dir_all(path) {
+        Ok(()) => Ok(()),
+        Err(why) if why.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(why) => Err(why.into()),
+    }
+}
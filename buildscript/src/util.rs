@@ -19,92 +19,119 @@ use std::{
 
 use sha2::Digest;
 
-pub fn hash_files<P: AsRef<Path>>(roots: impl IntoIterator<Item = P>) -> [u8; 64] {
-    let mut buf = vec![0; 1024 * 1024 * 16];
-    let mut hash = sha2::Sha512::new();
-    for root in roots {
-        match fs::read_dir(root.as_ref()) {
-            Ok(x) => {
-                let mut stack = vec![x];
-                while let Some(head) = stack.last_mut() {
-                    match head.next() {
-                        Some(Ok(x)) => match fs::read_dir(x.path()) {
-                            Ok(x) => stack.push(x),
-                            Err(why) if why.kind() == io::ErrorKind::NotFound => (),
-                            Err(why) if why.kind() == io::ErrorKind::NotADirectory => {
-                                let mut file = match File::open(x.path()) {
-                                    Ok(x) => x,
-                                    Err(why) => {
-                                        panic!(
-                                            "Failed to calculate hash of {:?}: {why:#?}",
-                                            x.path().display()
-                                        )
-                                    }
-                                };
-                                hash.update(x.path().as_os_str().as_encoded_bytes());
-                                loop {
-                                    match file.read(&mut buf) {
-                                        Ok(0) => break,
-                                        Ok(l) => {
-                                            hash.update(&buf[..l]);
-                                        }
-                                        Err(why) => {
-                                            panic!(
-                                                "Failed to calculate hash of {:?}: {why:#?}",
-                                                x.path().display()
-                                            )
-                                        }
-                                    }
-                                }
-                            }
-                            Err(why) => {
-                                panic!(
-                                    "Failed to calculate hash of {:?}: {why:#?}",
-                                    x.path().display()
-                                )
-                            }
-                        },
-                        Some(Err(why)) => {
-                            panic!("readdir() failed: {why:#?}",)
-                        }
-                        None => _ = stack.pop(),
-                    }
+/// Directory names skipped by [hash_files], with files next to them that mark them as build
+/// outputs.
+///
+/// These hold VCS data or build outputs which change on every build. Directories with markers
+/// are only skipped in projects of the build tool writing them (e.g. `build` next to a
+/// `build.gradle`), so source packages of the same name are still hashed.
+pub const HASH_IGNORED: &[(&str, &[&str])] = &[
+    (".git", &[]),
+    (".gradle", &[]),
+    ("build", &["build.gradle", "build.gradle.kts"]),
+    ("target", &["Cargo.toml"]),
+    ("out", &["build.gradle", "build.gradle.kts", ".idea"]),
+];
+
+/// Whether [hash_files] skips the directory at `path`, see [HASH_IGNORED].
+fn is_hash_ignored(path: &Path) -> bool {
+    let (Some(name), Some(parent)) = (path.file_name(), path.parent()) else {
+        return false;
+    };
+    HASH_IGNORED.iter().any(|(x, markers)| {
+        name == *x && (markers.is_empty() || markers.iter().any(|y| parent.join(y).exists()))
+    })
+}
+
+/// Hash files at `roots` (recursively for directories).
+///
+/// File paths are hashed along with their contents, so renaming a file changes the hash.
+/// Missing roots are skipped and directories from [HASH_IGNORED] are not entered. Symlinks
+/// inside of directories are not followed, the path they point to is hashed instead.
+pub fn hash_files<P: AsRef<Path>>(roots: impl IntoIterator<Item = P>) -> io::Result<[u8; 64]> {
+    fn hash_file(path: &Path, hash: &mut sha2::Sha512, buf: &mut [u8]) -> io::Result<()> {
+        let mut file = crate::fs2::open(path)?;
+        hash.update(path.as_os_str().as_encoded_bytes());
+        loop {
+            match file.read(buf) {
+                Ok(0) => break Ok(()),
+                Ok(l) => hash.update(&buf[..l]),
+                Err(why) => {
+                    break Err(io::Error::new(
+                        why.kind(),
+                        format!("read({:?}): {why}", path.display()),
+                    ));
                 }
             }
+        }
+    }
+
+    fn hash_dir(path: &Path, hash: &mut sha2::Sha512, buf: &mut [u8]) -> io::Result<()> {
+        let mut entries = crate::fs2::read_dir(path)?
+            .map(|x| x.map(|x| x.path()))
+            .collect::<io::Result<Vec<_>>>()?;
+        // Order of `read_dir` is not guaranteed.
+        entries.sort();
+        for x in entries {
+            let metadata = crate::fs2::symlink_metadata(&x)?;
+            if metadata.is_dir() && is_hash_ignored(&x) {
+                continue;
+            }
+            if metadata.is_symlink() {
+                let target = crate::fs2::read_link(&x)?;
+                hash.update(x.as_os_str().as_encoded_bytes());
+                hash.update(b" -> ");
+                hash.update(target.as_os_str().as_encoded_bytes());
+            } else if metadata.is_dir() {
+                hash_dir(&x, hash, buf)?;
+            } else {
+                hash_file(&x, hash, buf)?;
+            }
+        }
+        Ok(())
+    }
+
+    let mut buf = [0; 64 * 1024];
+    let mut hash = sha2::Sha512::new();
+    for root in roots {
+        let root = root.as_ref();
+        match crate::fs2::metadata(root) {
+            Ok(x) if x.is_dir() => hash_dir(root, &mut hash, &mut buf)?,
+            Ok(_) => hash_file(root, &mut hash, &mut buf)?,
             Err(why) if why.kind() == io::ErrorKind::NotFound => (),
-            Err(why) if why.kind() == io::ErrorKind::NotADirectory => {
-                let mut file = match File::open(root.as_ref()) {
-                    Ok(x) => x,
-                    Err(why) => {
-                        panic!(
-                            "Failed to calculate hash of {:?}: {why:#?}",
-                            root.as_ref().display()
-                        )
-                    }
-                };
-                hash.update(root.as_ref().as_os_str().as_encoded_bytes());
-                loop {
-                    match file.read(&mut buf) {
-                        Ok(0) => break,
-                        Ok(l) => {
-                            hash.update(&buf[..l]);
-                        }
-                        Err(why) => {
-                            panic!(
-                                "Failed to calculate hash of {:?}: {why:#?}",
-                                root.as_ref().display()
-                            )
-                        }
-                    }
-                }
+            Err(why) => return Err(why),
+        }
+    }
+    Ok(hash.finalize().into())
+}
+
+/// SHA-256 of the file at `path` as a lowercase hex string.
+pub fn sha256_file(path: &Path) -> io::Result<String> {
+    let mut file = crate::fs2::open(path)?;
+    let mut hash = sha2::Sha256::new();
+    let mut buf = vec![0; 1024 * 1024];
+    loop {
+        match file.read(&mut buf) {
+            Ok(0) => break,
+            Ok(l) => hash.update(&buf[..l]),
+            Err(why) => {
+                return Err(io::Error::new(
+                    why.kind(),
+                    format!("read({:?}): {why}", path.display()),
+                ));
             }
-            Err(why) => panic!(
-                "Failed to calculate hash of {:?}: {why:#?}",
-                root.as_ref().display()
-            ),
         }
     }
-    hash.finalize().into()
+    Ok(hex(&hash.finalize()))
+}
+
+/// Encode bytes as a lowercase hex string.
+pub fn hex(bytes: &[u8]) -> String {
+    let mut s = String::with_capacity(bytes.len() * 2);
+    for x in bytes {
+        s += &format!("{x:02x}");
+    }
+    s
 }
 
 /// Remove extra whitespace.
@@ -168,6 +195,19 @@ impl<E: DynError> Backtraced<E> {
     pub const fn error(&self) -> &E {
         &self.error
     }
+
+    /// Obtain the error mutably.
+    pub const fn error_mut(&mut self) -> &mut E {
+        &mut self.error
+    }
+
+    /// Convert the error, keeping the backtrace.
+    pub fn map<F: DynError>(self, f: impl FnOnce(E) -> F) -> Backtraced<F> {
+        Backtraced {
+            error: f(self.error),
+            backtrace: self.backtrace,
+        }
+    }
 }
 impl<E: DynError> fmt::Display for Backtraced<E> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -229,6 +269,82 @@ pub fn current_dir() -> &'static Path {
     }
 }
 
+/// Whether the network must not be used, see [set_offline].
+static OFFLINE: std::sync::OnceLock<bool> = std::sync::OnceLock::new();
+
+/// Forbid downloads and fetching from remote repositories.
+pub fn set_offline(offline: bool) {
+    _ = OFFLINE.set(offline);
+}
+
+/// Whether the network must not be used.
+pub fn is_offline() -> bool {
+    OFFLINE.get().copied().unwrap_or(false)
+}
+
+/// Error for something that has to be fetched from the network in offline mode.
+pub fn not_cached(what: impl fmt::Display) -> crate::error::Result<()> {
+    Err(crate::error::Error::other(format!(
+        "{what} is not cached; run online once"
+    )))
+}
+
+/// Where files are downloaded from, see [set_download_sources].
+struct DownloadSources {
+    mirrors: std::collections::HashMap<String, String>,
+    agent: ureq::Agent,
+}
+
+static DOWNLOAD_SOURCES: std::sync::OnceLock<DownloadSources> = std::sync::OnceLock::new();
+
+/// Set download mirrors and the HTTP proxy downloads go through.
+///
+/// `mirrors` replace URL prefixes, see [mirror_url]. Without `proxy`, the proxy from
+/// `HTTPS_PROXY`, `HTTP_PROXY` or `ALL_PROXY` is used.
+pub fn set_download_sources(
+    mirrors: std::collections::HashMap<String, String>,
+    proxy: Option<&str>,
+) {
+    let mut config = ureq::Agent::config_builder();
+    if let Some(proxy) = proxy {
+        // The proxy is validated along with the arguments.
+        config = config.proxy(ureq::Proxy::new(proxy).ok());
+    }
+    _ = DOWNLOAD_SOURCES.set(DownloadSources {
+        mirrors,
+        agent: config.build().new_agent(),
+    });
+}
+
+/// URL to download `url` from, with the longest matching prefix from `mirrors` replaced.
+pub fn mirror_url(mirrors: &std::collections::HashMap<String, String>, url: &str) -> String {
+    mirrors
+        .iter()
+        .filter(|(prefix, _)| url.starts_with(prefix.as_str()))
+        .max_by_key(|(prefix, _)| prefix.len())
+        .map_or_else(
+            || url.to_string(),
+            |(prefix, mirror)| format!("{mirror}{}", &url[prefix.len()..]),
+        )
+}
+
+/// Path of a file on the local filesystem `url` refers to, if it's a `file://` URL or a path.
+fn local_path(url: &str) -> Option<PathBuf> {
+    match url.strip_prefix("file://") {
+        Some(x) => Some(PathBuf::from(x)),
+        None if Path::new(url).is_absolute() => Some(PathBuf::from(url)),
+        None => None,
+    }
+}
+
+/// URL to actually download `url` from, after applying download mirrors.
+fn source_url(url: &str) -> String {
+    match DOWNLOAD_SOURCES.get() {
+        Some(x) => mirror_url(&x.mirrors, url),
+        None => url.to_string(),
+    }
+}
+
 /// Identity function - returns its argument unchanged.
 ///
 /// Useful for explicit type annotations in closures.
@@ -373,13 +489,8 @@ pub fn find_executable(cmd: impl AsRef<std::ffi::OsStr>) -> Option<PathBuf> {
         }
     });
 
-    for path in path.map(|x| PathBuf::from(x.as_str()).join(cmd.as_ref())) {
-        if is_executable(&path) {
-            return Some(path);
-        }
-    }
-
-    None
+    path.map(|x| PathBuf::from(x.as_str()).join(cmd.as_ref()))
+        .find(|path| is_executable(path))
 }
 
 /// Finds an executable in the system PATH.
@@ -428,7 +539,7 @@ pub fn write_if_diff<P: AsRef<Path>, S: AsRef<[u8]>>(path: P, data: S) -> io::Re
                     if l >= data.len() {
                         break 'a false;
                     }
-                    if &buf[0..l] != &data[0..l] {
+                    if buf[0..l] != data[0..l] {
                         break 'a false;
                     }
                     data = &data[l..];
@@ -437,7 +548,7 @@ pub fn write_if_diff<P: AsRef<Path>, S: AsRef<[u8]>>(path: P, data: S) -> io::Re
         },
         Err(_) => false,
     } {
-        return Ok((()));
+        return Ok(());
     }
 
     fs::write(path, data)
@@ -446,161 +557,220 @@ pub fn write_if_diff<P: AsRef<Path>, S: AsRef<[u8]>>(path: P, data: S) -> io::Re
 /// Downloads a file from a URL with progress display.
 ///
 /// Displays a progress bar when stderr is a terminal.
+/// Fails right away in offline mode.
+///
+/// The SHA-256 of the file is checked against the one recorded for `url` in
+/// `workspace.lock` (and recorded if there is none yet). On a mismatch the file is removed.
 ///
 /// # Arguments
 /// * `url` - URL to download from
 /// * `path` - Local path to save the file
-pub fn download(url: &str, path: impl AsRef<Path>) {
+pub fn download(url: &str, path: impl AsRef<Path>) -> crate::error::Result<()> {
+    download_sha256(url, path, None)
+}
+
+/// Downloads a file from a URL with progress display.
+///
+/// Same as [download], but checks the file against `sha256` instead of `workspace.lock`
+/// if it is given.
+pub fn download_sha256(
+    url: &str,
+    path: impl AsRef<Path>,
+    sha256: Option<&str>,
+) -> crate::error::Result<()> {
+    if is_offline() && local_path(&source_url(url)).is_none() {
+        return not_cached(format!("{} (from {url})", path.as_ref().display()));
+    }
     let path = path.as_ref();
+    try_download(url, path).map_err(|x| crate::error::Error::download(url, x))?;
+    if let Err(why) = crate::lock::verify_download(url, path, sha256) {
+        fs::remove_file(path).ok();
+        return Err(why);
+    }
+    Ok(())
+}
 
-    fs::create_dir_all(path.parent().unwrap()).unwrap();
+/// Number of attempts [try_download] makes before giving up.
+const DOWNLOAD_ATTEMPTS: u32 = 5;
 
-    let mut resp = ureq::get(url).call().unwrap();
-    let max_len: usize = if stderr().is_terminal() {
-        resp.headers()
-            .get("content-length")
-            .map(|x| x.to_str().unwrap().parse().unwrap())
-            .unwrap()
-    } else {
-        0
-    };
-    let mut buf = [0; 16384];
-    let mut body = resp.body_mut().as_reader();
-    let mut file = File::create(path).unwrap();
+/// Downloads a file from a URL with progress display.
+///
+/// Same as [download], but returns a plain io error.
+/// Data is written into `PATH.part`, which is renamed to `path` once complete. Failed
+/// downloads are retried with backoff, resuming from what is already in `PATH.part`
+/// (also left by earlier runs) if the server supports range requests.
+///
+/// Download mirrors are applied to `url`. Mirrors on the local filesystem are copied from.
+///
+/// # Arguments
+/// * `url` - URL to download from
+/// * `path` - Local path to save the file
+pub fn try_download(url: &str, path: impl AsRef<Path>) -> io::Result<()> {
+    let path = path.as_ref();
+
+    crate::fs2::create_dir_all(path.parent().unwrap())?;
 
-    if stderr().is_terminal() {
-        eprint!("[          ] 0% (0/{max_len})");
-        stderr().flush().unwrap();
+    let mut part = path.as_os_str().to_os_string();
+    part.push(".part");
+    let part = PathBuf::from(part);
+
+    let source = source_url(url);
+    if source != url {
+        eprintln!("{url}: using mirror {source}");
+    }
+    if let Some(local) = local_path(&source) {
+        crate::fs2::copy(&local, &part)?;
+        return crate::fs2::rename(&part, path);
     }
 
-    let mut total_len = 0usize;
+    let url = source.as_str();
+    let mut attempt = 1;
     loop {
-        let len = body.read(&mut buf).unwrap();
-        if len == 0 {
-            break;
-        }
-        file.write_all(&buf[0..len]).unwrap();
-        if stderr().is_terminal() {
-            total_len += len;
-            let perc = total_len.mul(100) / max_len;
-            eprint!(
-                "\r\x1b[K[{}{}] {perc}% ({:.02}/{:.02}MiB)",
-                "#".repeat(perc.div(10)),
-                " ".repeat(10 - perc.div(10)),
-                total_len as f32 / 1024.0 / 1024.0,
-                max_len as f32 / 1024.0 / 1024.0,
-            );
-            stderr().flush().unwrap();
+        match download_part(url, &part) {
+            Ok(()) => return crate::fs2::rename(&part, path),
+            Err(DownloadError::Transient(why)) if attempt < DOWNLOAD_ATTEMPTS => {
+                let delay = std::time::Duration::from_secs(1 << attempt);
+                eprintln!(
+                    "{url}: {why}, retrying in {}s ({attempt}/{DOWNLOAD_ATTEMPTS})",
+                    delay.as_secs()
+                );
+                std::thread::sleep(delay);
+                attempt += 1;
+            }
+            Err(DownloadError::Transient(why) | DownloadError::Permanent(why)) => return Err(why),
         }
     }
-
-    if stderr().is_terminal() {
-        println!(
-            "\r\x1b[K[##########] 100% ({:.02}MiB)",
-            max_len as f32 / 1024.0 / 1024.0
-        );
-    }
-
-    file.flush().unwrap();
 }
 
-/// Extracts a gzip-compressed tar archive.
-///
-/// # Arguments
-/// * `archive` - Path to the tar.gz archive
-/// * `path` - Destination directory
-/// * `skip_segments` - Number of path segments to skip when extracting
-#[cfg(unix)]
-pub fn untar_gz(archive: impl AsRef<Path>, path: impl AsRef<Path>, skip_segments: usize) {
-    use std::{io::BufReader, os::unix::fs::PermissionsExt};
+/// Failure of a single download attempt.
+enum DownloadError {
+    /// Failure worth retrying, like a dropped connection or a server error.
+    Transient(io::Error),
+    /// Failure that won't go away by retrying, like a missing file.
+    Permanent(io::Error),
+}
 
-    let archive = archive.as_ref();
-    let untar_path = path.as_ref();
+/// Download `url` into `part`, continuing after the data already in it.
+fn download_part(url: &str, part: &Path) -> Result<(), DownloadError> {
+    use DownloadError::*;
 
-    let mut buf = [0; 16384];
-    let file = BufReader::new(File::open(archive).unwrap());
-    let file = flate2::bufread::GzDecoder::new(file);
-    let mut file = tar::Archive::new(file);
-    for x in file.entries().unwrap() {
-        let mut x = x.unwrap();
-        let path = x.path_bytes();
-        let mut path = str::from_utf8(path.as_ref()).unwrap();
-        if path.ends_with('/') {
-            fs::create_dir_all(untar_path.join(path)).unwrap();
-            continue;
-        }
-        for _ in 0..skip_segments {
-            let Some(i) = path.find('/') else {
-                continue;
-            };
-            path = &path[i + 1..];
+    let offset = fs::metadata(part).map_or(0, |x| x.len());
+    let mut request = match DOWNLOAD_SOURCES.get() {
+        Some(x) => x.agent.get(url),
+        None => ureq::get(url),
+    };
+    if offset > 0 {
+        request = request.header("Range", format!("bytes={offset}-"));
+    }
+    let mut resp = match request.call() {
+        Ok(x) => x,
+        Err(ureq::Error::StatusCode(416)) if offset > 0 => {
+            // The partial file does not match the remote file anymore.
+            fs::remove_file(part).map_err(Permanent)?;
+            return Err(Transient(io::Error::other("stale partial download")));
         }
-        let path = untar_path.join(path);
-        fs::create_dir_all(path.parent().unwrap()).unwrap();
-        let mut file = File::create(&path).unwrap();
-        loop {
-            let len = x.read(&mut buf).unwrap();
-            if len == 0 {
-                break;
-            }
-            file.write_all(&buf[0..len]).unwrap();
+        Err(ureq::Error::StatusCode(code))
+            if (400..500).contains(&code) && code != 408 && code != 429 =>
+        {
+            return Err(Permanent(io::Error::other(format!("http status {code}"))));
         }
-        file.flush().unwrap();
-        let mut perms = fs::metadata(path).unwrap().permissions();
-        if let Ok(x) = x.header().mode() {
-            perms.set_mode(x);
+        Err(why) => return Err(Transient(io::Error::other(why))),
+    };
+
+    // Servers without range support send the whole file again.
+    let resumed = offset > 0 && resp.status() == 206;
+    let offset = if resumed { offset } else { 0 };
+    let total = resp
+        .headers()
+        .get("content-length")
+        .and_then(|x| x.to_str().ok()?.parse::<u64>().ok())
+        .map(|x| x + offset);
+
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(resumed)
+        .truncate(!resumed)
+        .open(part)
+        .map_err(|why| {
+            Permanent(io::Error::new(
+                why.kind(),
+                format!("open({:?}): {why}", part.display()),
+            ))
+        })?;
+
+    let mut progress = DownloadProgress::new(offset, total);
+    let mut buf = [0; 16384];
+    let mut body = resp.body_mut().as_reader();
+    loop {
+        let len = body.read(&mut buf).map_err(Transient)?;
+        if len == 0 {
+            break;
         }
-        file.set_permissions(perms).unwrap();
+        file.write_all(&buf[0..len]).map_err(Permanent)?;
+        progress.advance(len);
+    }
+    file.flush().map_err(Permanent)?;
+    progress.finish();
+
+    match total {
+        Some(total) if progress.done < total => Err(Transient(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!("connection closed after {} of {total} bytes", progress.done),
+        ))),
+        _ => Ok(()),
     }
 }
 
-/// Extracts an xz-compressed tar archive.
-///
-/// # Arguments
-/// * `archive` - Path to the tar.xz archive
-/// * `path` - Destination directory
-/// * `skip_segments` - Number of path segments to skip when extracting
-#[cfg(unix)]
-pub fn untar_xz(archive: impl AsRef<Path>, path: impl AsRef<Path>, skip_segments: usize) {
-    use std::{io::BufReader, os::unix::fs::PermissionsExt};
+/// Progress of a download shown on stderr if it's a terminal.
+struct DownloadProgress {
+    /// Bytes downloaded so far, including ones from earlier attempts.
+    done: u64,
+    /// Size of the file, if the server sent it.
+    total: Option<u64>,
+    tty: bool,
+}
+impl DownloadProgress {
+    fn new(done: u64, total: Option<u64>) -> Self {
+        let progress = Self {
+            done,
+            total,
+            tty: stderr().is_terminal(),
+        };
+        progress.print();
+        progress
+    }
 
-    let archive = archive.as_ref();
-    let untar_path = path.as_ref();
+    fn advance(&mut self, len: usize) {
+        self.done += len as u64;
+        self.print();
+    }
 
-    let mut buf = [0; 16384];
-    let file = BufReader::new(File::open(archive).unwrap());
-    let file = xz::bufread::XzDecoder::new(file);
-    let mut file = tar::Archive::new(file);
-    for x in file.entries().unwrap() {
-        let mut x = x.unwrap();
-        let path = x.path_bytes();
-        let mut path = str::from_utf8(path.as_ref()).unwrap();
-        if path.ends_with('/') {
-            fs::create_dir_all(untar_path.join(path)).unwrap();
-            continue;
-        }
-        for _ in 0..skip_segments {
-            let Some(i) = path.find('/') else {
-                continue;
-            };
-            path = &path[i + 1..];
+    fn print(&self) {
+        if !self.tty {
+            return;
         }
-        let path = untar_path.join(path);
-        fs::create_dir_all(path.parent().unwrap()).unwrap();
-        let mut file = File::create(&path).unwrap();
-        loop {
-            let len = x.read(&mut buf).unwrap();
-            if len == 0 {
-                break;
+        let mib = |x: u64| x as f32 / 1024.0 / 1024.0;
+        match self.total {
+            Some(total) if total > 0 => {
+                let perc = (self.done.mul(100) / total).min(100) as usize;
+                eprint!(
+                    "\r\x1b[K[{}{}] {perc}% ({:.02}/{:.02}MiB)",
+                    "#".repeat(perc.div(10)),
+                    " ".repeat(10 - perc.div(10)),
+                    mib(self.done),
+                    mib(total),
+                );
             }
-            file.write_all(&buf[0..len]).unwrap();
+            _ => eprint!("\r\x1b[K[  ????  ] {:.02}MiB", mib(self.done)),
         }
-        file.flush().unwrap();
-        let mut perms = fs::metadata(path).unwrap().permissions();
-        if let Ok(x) = x.header().mode() {
-            perms.set_mode(x);
+        stderr().flush().ok();
+    }
+
+    fn finish(&self) {
+        if self.tty {
+            eprintln!();
         }
-        file.set_permissions(perms).unwrap();
     }
 }
 
@@ -647,3 +817,138 @@ impl PathBufExt for PathBuf {
 }
 
 // TODO: Implement for Windows
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    /// Directory the download mirrors of the tests point to, unique to the process.
+    fn mirror_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("buildscript-test-mirrors-{}", std::process::id()))
+    }
+
+    /// Set up download mirrors shared by every test, they can only be set once.
+    ///
+    /// `https://example.invalid/files/` is mirrored by a `file://` URL and
+    /// `https://example.invalid/files/plain/` by a plain path.
+    fn set_mirrors() {
+        static SETUP: std::sync::Once = std::sync::Once::new();
+        SETUP.call_once(setup_mirrors);
+    }
+
+    fn setup_mirrors() {
+        let dir = mirror_dir();
+        crate::fs2::create_dir_all(dir.join("url/plain")).unwrap();
+        crate::fs2::create_dir_all(dir.join("plain")).unwrap();
+        crate::fs2::write(dir.join("url/a.jar"), "from file url").unwrap();
+        crate::fs2::write(dir.join("url/plain/b.jar"), "shorter prefix").unwrap();
+        crate::fs2::write(dir.join("plain/b.jar"), "from path").unwrap();
+
+        let mirrors = HashMap::from([
+            (
+                "https://example.invalid/files/".to_string(),
+                format!("file://{}/url/", dir.display()),
+            ),
+            (
+                "https://example.invalid/files/plain/".to_string(),
+                format!("{}/plain/", dir.display()),
+            ),
+        ]);
+        set_download_sources(mirrors, None);
+    }
+
+    /// Empty directory for the downloads of a test.
+    fn out_dir(name: &str) -> PathBuf {
+        let dir = mirror_dir().join("out").join(name);
+        _ = fs::remove_dir_all(&dir);
+        crate::fs2::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn mirror_url_longest_prefix() {
+        let mirrors = HashMap::from([
+            (
+                "https://github.com/".to_string(),
+                "https://cache/gh/".to_string(),
+            ),
+            (
+                "https://github.com/Anuken/".to_string(),
+                "/srv/anuken/".to_string(),
+            ),
+        ]);
+        assert_eq!(
+            mirror_url(&mirrors, "https://github.com/Anuken/Arc.git"),
+            "/srv/anuken/Arc.git"
+        );
+        assert_eq!(
+            mirror_url(&mirrors, "https://github.com/Darkdustry-Coders/Forts.git"),
+            "https://cache/gh/Darkdustry-Coders/Forts.git"
+        );
+        assert_eq!(
+            mirror_url(&mirrors, "https://busybox.net/busybox"),
+            "https://busybox.net/busybox"
+        );
+    }
+
+    #[test]
+    fn hash_ignores_build_outputs_only() {
+        let dir = out_dir("hash");
+        for x in [
+            "Cargo.toml",
+            "build.gradle",
+            "src/build/A.java",
+            "src/out/B.java",
+        ] {
+            crate::fs2::create_dir_all(dir.join(x).parent().unwrap()).unwrap();
+            crate::fs2::write(dir.join(x), "").unwrap();
+        }
+        let before = hash_files([&dir]).unwrap();
+
+        for x in [
+            "build/a.jar",
+            "target/a",
+            "out/a",
+            ".gradle/a",
+            "src/target/a",
+        ] {
+            crate::fs2::create_dir_all(dir.join(x).parent().unwrap()).unwrap();
+            crate::fs2::write(dir.join(x), "output").unwrap();
+        }
+        // Only `src/target` is not a build output.
+        assert_ne!(hash_files([&dir]).unwrap(), before);
+        fs::remove_dir_all(dir.join("src/target")).unwrap();
+        assert_eq!(hash_files([&dir]).unwrap(), before);
+
+        crate::fs2::write(dir.join("src/build/A.java"), "changed").unwrap();
+        assert_ne!(hash_files([&dir]).unwrap(), before);
+    }
+
+    #[test]
+    fn download_from_file_url_mirror() {
+        set_mirrors();
+        let out = out_dir("file-url").join("a.jar");
+        try_download("https://example.invalid/files/a.jar", &out).unwrap();
+        assert_eq!(fs::read_to_string(&out).unwrap(), "from file url");
+        assert!(!out.with_extension("jar.part").exists());
+    }
+
+    #[test]
+    fn download_from_path_mirror() {
+        set_mirrors();
+        let out = out_dir("path").join("b.jar");
+        try_download("https://example.invalid/files/plain/b.jar", &out).unwrap();
+        assert_eq!(fs::read_to_string(&out).unwrap(), "from path");
+    }
+
+    #[test]
+    fn download_missing_from_mirror() {
+        set_mirrors();
+        let out = out_dir("missing").join("c.jar");
+        let why = try_download("https://example.invalid/files/c.jar", &out).unwrap_err();
+        assert_eq!(why.kind(), io::ErrorKind::NotFound);
+        assert!(!out.exists());
+    }
+}